@@ -0,0 +1,335 @@
+use crate::http::{date, Request, StatusCode};
+use std::{
+    fmt::Write as _,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+/// How each request is written to the access log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// `host ident user [time] "request line" status size`, as Apache and nginx write it.
+    Common,
+    /// `Common` followed by the quoted `Referer` and `User-Agent`.
+    Combined,
+    /// One JSON object per line, the only format that also records the latency and worker.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format.to_ascii_lowercase().as_str() {
+            "common" => Ok(LogFormat::Common),
+            "combined" => Ok(LogFormat::Combined),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!(
+                "unknown log format {:?}, expected common, combined or json",
+                format
+            )),
+        }
+    }
+}
+
+/// What is recorded about a request once its response was sent.
+#[derive(Debug)]
+pub struct Entry<'a> {
+    pub client: Option<SocketAddr>,
+    pub request: &'a Request,
+    pub status: StatusCode,
+    /// Size of the body sent, unknown for streams without a `Content-Length`.
+    pub size: Option<u64>,
+    /// From the request being parsed to the response being written.
+    pub latency: Duration,
+    pub worker: usize,
+    pub time: SystemTime,
+}
+
+/// Writes one line per request to a file or any other writer.
+///
+/// A log opened on a file can be rotated once it grows past a size: `access.log` is renamed to
+/// `access.log.1`, the previous `access.log.1` to `access.log.2` and so on, the oldest one being
+/// deleted.
+pub struct AccessLog {
+    format: LogFormat,
+    output: Mutex<Output>,
+}
+
+enum Output {
+    File(RotatingFile),
+    Writer(Box<dyn Write + Send>),
+}
+
+impl AccessLog {
+    /// Appends to the file at `path`, creating it if needed.
+    pub fn open(path: impl Into<PathBuf>, format: LogFormat) -> io::Result<Self> {
+        let path = path.into();
+        let file = open_append(&path)?;
+        let written = file.metadata()?.len();
+
+        Ok(AccessLog {
+            format,
+            output: Mutex::new(Output::File(RotatingFile {
+                path,
+                file,
+                written,
+                max_size: None,
+                max_files: 5,
+            })),
+        })
+    }
+
+    /// Logs to `writer`, e.g. stdout. Such a log is never rotated.
+    pub fn to_writer<W: Write + Send + 'static>(writer: W, format: LogFormat) -> Self {
+        AccessLog {
+            format,
+            output: Mutex::new(Output::Writer(Box::new(writer))),
+        }
+    }
+
+    /// Rotates the file once it would grow past `max_size` bytes, keeping `max_files` old ones.
+    /// Has no effect on a log that isn't written to a file.
+    pub fn rotate(self, max_size: u64, max_files: usize) -> Self {
+        if let Output::File(file) = &mut *self.output.lock().unwrap() {
+            file.max_size = Some(max_size);
+            file.max_files = max_files;
+        }
+        self
+    }
+
+    /// Writes `entry`. Failing to log must not fail the request, errors are reported and the
+    /// entry is lost.
+    pub fn log(&self, entry: &Entry) {
+        let line = format_entry(self.format, entry);
+
+        let result = match &mut *self.output.lock().unwrap() {
+            Output::File(file) => file.write_line(line.as_bytes()),
+            Output::Writer(writer) => writer.write_all(line.as_bytes()),
+        };
+
+        if let Err(e) = result {
+            log::error!("Failed to write to the access log: {}", e);
+        }
+    }
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    max_size: Option<u64>,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        if let Some(max_size) = self.max_size {
+            if self.written > 0 && self.written + line.len() as u64 > max_size {
+                self.rotate()?;
+            }
+        }
+
+        self.file.write_all(line)?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for index in (1..self.max_files).rev() {
+                let from = numbered(&self.path, index);
+                if from.exists() {
+                    fs::rename(from, numbered(&self.path, index + 1))?;
+                }
+            }
+            fs::rename(&self.path, numbered(&self.path, 1))?;
+        }
+
+        self.file = open_append(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// `access.log` becomes `access.log.1`.
+fn numbered(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+fn format_entry(format: LogFormat, entry: &Entry) -> String {
+    let request = entry.request;
+    let client = entry
+        .client
+        .map_or_else(|| String::from("-"), |client| client.ip().to_string());
+
+    match format {
+        LogFormat::Common | LogFormat::Combined => {
+            let mut line = format!(
+                "{} - - [{}] \"{} {} {}\" {} {}",
+                client,
+                date::format_common_log(entry.time),
+                request.method,
+                escape_quoted(&request.target),
+                request.version,
+                entry.status.code(),
+                entry
+                    .size
+                    .map_or_else(|| String::from("-"), |size| size.to_string())
+            );
+            if format == LogFormat::Combined {
+                let header = |name| escape_quoted(request.headers.get(name).unwrap_or("-"));
+                write!(
+                    line,
+                    " \"{}\" \"{}\"",
+                    header("Referer"),
+                    header("User-Agent")
+                )
+                .unwrap();
+            }
+            line.push('\n');
+            line
+        }
+        LogFormat::Json => {
+            let optional =
+                |value: Option<&str>| value.map_or_else(|| String::from("null"), json_string);
+            format!(
+                "{{\"time\":\"{}\",\"client\":{},\"method\":{},\"path\":{},\"query\":{},\"version\":\"{}\",\"status\":{},\"size\":{},\"latency_ms\":{:.3},\"worker\":{},\"referer\":{},\"user_agent\":{}}}\n",
+                date::format_rfc3339(entry.time),
+                optional(entry.client.map(|_| client.as_str())),
+                json_string(request.method.as_str()),
+                json_string(&request.path),
+                optional(request.target.split_once('?').map(|(_, query)| query)),
+                request.version,
+                entry.status.code(),
+                entry
+                    .size
+                    .map_or_else(|| String::from("null"), |size| size.to_string()),
+                entry.latency.as_secs_f64() * 1000.0,
+                entry.worker,
+                optional(request.headers.get("Referer")),
+                optional(request.headers.get("User-Agent")),
+            )
+        }
+    }
+}
+
+/// Escapes what a client sent so it can't break out of the quotes of a log field or forge a line.
+fn escape_quoted(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => write!(escaped, "\\x{:02x}", c as u32).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn json_string(value: &str) -> String {
+    let mut json = String::with_capacity(value.len() + 2);
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    fn request(raw: &str) -> Request {
+        Request::parse(&mut raw.as_bytes()).unwrap()
+    }
+
+    fn entry(request: &Request) -> Entry<'_> {
+        Entry {
+            client: Some("10.0.0.7:51234".parse().unwrap()),
+            request,
+            status: StatusCode::Ok,
+            size: Some(2326),
+            latency: Duration::from_micros(1500),
+            worker: 3,
+            time: UNIX_EPOCH + Duration::from_secs(784_111_777),
+        }
+    }
+
+    #[test]
+    fn it_formats_common_and_combined_lines() {
+        let request = request(
+            "GET /index.html?lang=en HTTP/1.1\r\nReferer: http://example.com/\r\nUser-Agent: curl \"8\"\r\n\r\n",
+        );
+
+        assert_eq!(
+            format_entry(LogFormat::Common, &entry(&request)),
+            "10.0.0.7 - - [06/Nov/1994:08:49:37 +0000] \"GET /index.html?lang=en HTTP/1.1\" 200 2326\n"
+        );
+        assert_eq!(
+            format_entry(LogFormat::Combined, &entry(&request)),
+            "10.0.0.7 - - [06/Nov/1994:08:49:37 +0000] \"GET /index.html?lang=en HTTP/1.1\" 200 2326 \"http://example.com/\" \"curl \\\"8\\\"\"\n"
+        );
+    }
+
+    #[test]
+    fn it_formats_json_lines() {
+        let request = request("POST /users?page=2 HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+        let mut entry = entry(&request);
+        entry.size = None;
+
+        assert_eq!(
+            format_entry(LogFormat::Json, &entry),
+            "{\"time\":\"1994-11-06T08:49:37Z\",\"client\":\"10.0.0.7\",\"method\":\"POST\",\"path\":\"/users\",\"query\":\"page=2\",\"version\":\"HTTP/1.1\",\"status\":200,\"size\":null,\"latency_ms\":1.500,\"worker\":3,\"referer\":null,\"user_agent\":null}\n"
+        );
+    }
+
+    #[test]
+    fn it_rotates_files_past_their_size() {
+        let dir = std::env::temp_dir().join(format!("access-log-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("access.log");
+
+        let request = request("GET / HTTP/1.1\r\n\r\n");
+        let line_length = format_entry(LogFormat::Common, &entry(&request)).len() as u64;
+        let log = AccessLog::open(&path, LogFormat::Common)
+            .unwrap()
+            .rotate(line_length * 2, 2);
+
+        for _ in 0..7 {
+            log.log(&entry(&request));
+        }
+
+        let lines = |path: PathBuf| fs::read_to_string(path).unwrap().lines().count();
+        assert_eq!(lines(path.clone()), 1);
+        assert_eq!(lines(numbered(&path, 1)), 2);
+        assert_eq!(lines(numbered(&path, 2)), 2);
+        assert!(!numbered(&path, 3).exists());
+
+        fs::remove_dir_all(dir).ok();
+    }
+}
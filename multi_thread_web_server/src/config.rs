@@ -0,0 +1,943 @@
+use crate::access_log::LogFormat;
+use crate::server::UnknownHosts;
+use log::LevelFilter;
+use std::{
+    env, error, fmt, fs, io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+/// Everything a deployment can tune without recompiling.
+///
+/// Values are layered, each source overriding the previous one:
+///
+/// 1. the defaults below,
+/// 2. a TOML file given with `--config <path>` or `SERVER_CONFIG`, using the key names as is
+///    (`document_root = "public"`),
+/// 3. environment variables named after the keys (`SERVER_DOCUMENT_ROOT=public`),
+/// 4. command-line flags (`--document-root public` or `--document-root=public`).
+///
+/// Durations are in seconds unless suffixed with `ms`, `s`, `m` or `h`, sizes are in bytes unless
+/// suffixed with `K`, `M` or `G` (binary multiples).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    pub workers: usize,
+    /// How many workers may run while connections wait for one, as many as `workers` when zero.
+    /// The extra ones stop after a minute without work.
+    pub max_workers: usize,
+    /// How many connections may wait for a free worker before the server stops accepting more,
+    /// any number when zero.
+    pub queue_capacity: usize,
+    pub document_root: PathBuf,
+    /// How long a keep-alive connection may wait for its next request.
+    pub idle_timeout: Duration,
+    /// How long a client may take to send the request line and header fields.
+    pub header_timeout: Duration,
+    /// How long reading a request body may wait for more bytes.
+    pub read_timeout: Duration,
+    /// How long writing a response may wait for the client.
+    pub write_timeout: Duration,
+    /// How many requests a single connection may send.
+    pub max_requests: usize,
+    /// How long in-flight requests get to finish once a shutdown was requested.
+    pub shutdown_timeout: Duration,
+    pub max_header_size: usize,
+    pub max_headers: usize,
+    pub max_body_size: usize,
+    /// Whether textual responses are compressed for clients that accept it.
+    pub compression: bool,
+    /// Responses smaller than this are never compressed.
+    pub compression_min_size: usize,
+    pub log_level: LevelFilter,
+    /// PEM certificate chain to serve HTTPS with, along with `tls_key`.
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    /// Port HTTPS is served on next to plain HTTP on `port`. Without it, `port` serves HTTPS only.
+    pub tls_port: Option<u16>,
+    /// Whether plain HTTP requests are redirected to HTTPS on `tls_port`.
+    pub https_redirect: bool,
+    /// The host name redirects to HTTPS point at when a request names a host the server doesn't
+    /// serve, the address HTTPS is served on when `None`.
+    pub server_name: Option<String>,
+    /// File every request is recorded in, none is kept when `None`.
+    pub access_log: Option<PathBuf>,
+    pub access_log_format: LogFormat,
+    /// Size past which the access log is rotated, it is never rotated when zero.
+    pub access_log_max_size: usize,
+    /// How many rotated access logs are kept.
+    pub access_log_max_files: usize,
+    /// Where to mount the `POST` route that shuts the server down, disabled when `None`.
+    pub shutdown_route: Option<String>,
+    /// Where to mount the readiness route, disabled when `None`.
+    pub health_route: Option<String>,
+    /// Where to mount the Prometheus metrics route, disabled when `None`.
+    pub metrics_route: Option<String>,
+    /// Route prefixes forwarded to upstream servers, written
+    /// `/api=127.0.0.1:9001,127.0.0.1:9002; /legacy=127.0.0.1:9100`.
+    pub proxy: Vec<ProxyRoute>,
+    /// Host names served from their own document root, written
+    /// `example.com=public/example; *.blog.example.com=public/blog`.
+    pub virtual_hosts: Vec<VirtualHost>,
+    /// What requests for hosts not in `virtual_hosts` get.
+    pub unknown_hosts: UnknownHosts,
+}
+
+/// Requests for the host `name` are served from `document_root`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VirtualHost {
+    pub name: String,
+    pub document_root: PathBuf,
+}
+
+/// Requests below `prefix` are forwarded to `upstreams` in turn.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyRoute {
+    pub prefix: String,
+    /// `host:port` pairs.
+    pub upstreams: Vec<String>,
+}
+
+/// Every key, in the order `usage` lists them.
+const KEYS: &[(&str, &str)] = &[
+    ("host", "address to bind to"),
+    ("port", "port to listen on"),
+    ("workers", "number of worker threads"),
+    (
+        "max_workers",
+        "worker threads started while connections wait, 0 for workers",
+    ),
+    (
+        "queue_capacity",
+        "connections waiting for a worker before accepting pauses, 0 for no limit",
+    ),
+    ("document_root", "directory static files are served from"),
+    (
+        "idle_timeout",
+        "how long an idle keep-alive connection is kept open",
+    ),
+    (
+        "header_timeout",
+        "how long a client may take to send the request head",
+    ),
+    ("read_timeout", "how long reading a request body may stall"),
+    ("write_timeout", "how long writing a response may stall"),
+    (
+        "max_requests",
+        "requests served on one connection before closing it",
+    ),
+    (
+        "shutdown_timeout",
+        "how long in-flight requests get to finish on shutdown",
+    ),
+    (
+        "max_header_size",
+        "largest request line and header fields accepted",
+    ),
+    ("max_headers", "most header fields accepted in a request"),
+    ("max_body_size", "largest request body accepted"),
+    ("compression", "true to gzip or deflate textual responses"),
+    ("compression_min_size", "smallest response compressed"),
+    ("log_level", "off, error, warn, info, debug or trace"),
+    ("tls_cert", "PEM certificate chain to serve HTTPS with"),
+    ("tls_key", "PEM private key of the certificate"),
+    ("tls_port", "port HTTPS is served on next to plain HTTP"),
+    (
+        "https_redirect",
+        "true to redirect plain HTTP requests to HTTPS",
+    ),
+    (
+        "server_name",
+        "host name redirects point at, empty for the address",
+    ),
+    ("access_log", "file every request is recorded in"),
+    ("access_log_format", "common, combined or json"),
+    (
+        "access_log_max_size",
+        "size past which the access log is rotated",
+    ),
+    (
+        "access_log_max_files",
+        "how many rotated access logs are kept",
+    ),
+    (
+        "shutdown_route",
+        "path of a POST route that shuts the server down",
+    ),
+    (
+        "health_route",
+        "path of the readiness route, empty to disable",
+    ),
+    (
+        "metrics_route",
+        "path of the Prometheus metrics, empty to disable",
+    ),
+    (
+        "proxy",
+        "prefixes to forwarded upstreams, e.g. /api=host:port,host:port",
+    ),
+    (
+        "virtual_hosts",
+        "hosts with their own document root, e.g. example.com=public",
+    ),
+    (
+        "unknown_hosts",
+        "default, 404 or 421 for hosts not in virtual_hosts",
+    ),
+];
+
+const ENV_PREFIX: &str = "SERVER_";
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            host: String::from("127.0.0.1"),
+            port: 8000,
+            workers: 4,
+            max_workers: 0,
+            queue_capacity: 1024,
+            document_root: PathBuf::from("static"),
+            idle_timeout: Duration::from_secs(5),
+            header_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(30),
+            write_timeout: Duration::from_secs(30),
+            max_requests: 100,
+            shutdown_timeout: Duration::from_secs(30),
+            max_header_size: 16 * 1024,
+            max_headers: 100,
+            max_body_size: 10 * 1024 * 1024,
+            compression: true,
+            compression_min_size: 1024,
+            log_level: LevelFilter::Info,
+            tls_cert: None,
+            tls_key: None,
+            tls_port: None,
+            https_redirect: false,
+            server_name: None,
+            access_log: None,
+            access_log_format: LogFormat::Combined,
+            access_log_max_size: 0,
+            access_log_max_files: 5,
+            shutdown_route: None,
+            health_route: Some(String::from("/health")),
+            metrics_route: Some(String::from("/metrics")),
+            proxy: Vec::new(),
+            virtual_hosts: Vec::new(),
+            unknown_hosts: UnknownHosts::Default,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Loads the configuration from the process arguments and environment, then validates it.
+    pub fn load() -> Result<Self, ConfigError> {
+        ServerConfig::from_sources(env::args().skip(1), env::vars())
+    }
+
+    /// Like `load`, with the flags (without the program name) and the environment variables
+    /// given explicitly.
+    pub fn from_sources<A, E>(args: A, vars: E) -> Result<Self, ConfigError>
+    where
+        A: IntoIterator<Item = String>,
+        E: IntoIterator<Item = (String, String)>,
+    {
+        let flags = parse_flags(args)?;
+        let vars: Vec<(String, String)> = vars.into_iter().collect();
+        let var = |name: &str| {
+            vars.iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        };
+
+        let mut config = ServerConfig::default();
+
+        let file = flags
+            .iter()
+            .rev()
+            .find(|(key, _)| key == "config")
+            .map(|(_, value)| value.clone())
+            .or_else(|| var("SERVER_CONFIG"));
+        if let Some(file) = file {
+            config.merge_file(Path::new(&file))?;
+        }
+
+        for (key, _) in KEYS {
+            let name = format!("{}{}", ENV_PREFIX, key.to_uppercase());
+            if let Some(value) = var(&name) {
+                config.set(key, &value, &Source::Env(name))?;
+            }
+        }
+
+        for (key, value) in flags.iter().filter(|(key, _)| key != "config") {
+            config.set(key, value, &Source::Flag(key.replace('_', "-")))?;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Overrides the values set in the TOML file at `path`.
+    pub fn merge_file(&mut self, path: &Path) -> Result<(), ConfigError> {
+        let contents = fs::read_to_string(path).map_err(|error| ConfigError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        self.merge_toml(&contents, &Source::File(path.to_path_buf()))
+    }
+
+    fn merge_toml(&mut self, contents: &str, source: &Source) -> Result<(), ConfigError> {
+        let table: toml::Table =
+            contents
+                .parse()
+                .map_err(|e: toml::de::Error| ConfigError::Syntax {
+                    source: source.clone(),
+                    message: e.message().to_string(),
+                })?;
+
+        for (key, value) in &table {
+            let value = match value {
+                toml::Value::String(value) => value.clone(),
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Float(value) => value.to_string(),
+                toml::Value::Boolean(value) => value.to_string(),
+                _ if !is_key(key) => {
+                    return Err(ConfigError::UnknownKey {
+                        key: key.clone(),
+                        source: source.clone(),
+                    })
+                }
+                other => {
+                    return Err(ConfigError::InvalidValue {
+                        key: key.clone(),
+                        value: other.to_string(),
+                        reason: String::from("expected a string or a number"),
+                        source: source.clone(),
+                    })
+                }
+            };
+            self.set(key, &value, source)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sets `key` from its textual `value`, the same way for every source.
+    fn set(&mut self, key: &str, value: &str, source: &Source) -> Result<(), ConfigError> {
+        let invalid = |reason: &str| ConfigError::InvalidValue {
+            key: String::from(key),
+            value: String::from(value),
+            reason: String::from(reason),
+            source: source.clone(),
+        };
+
+        match key {
+            "host" => self.host = String::from(value),
+            "port" => {
+                self.port = value
+                    .parse()
+                    .map_err(|_| invalid("expected a port number between 0 and 65535"))?
+            }
+            "workers" => self.workers = parse_number(value).map_err(|e| invalid(&e))?,
+            "max_workers" => self.max_workers = parse_number(value).map_err(|e| invalid(&e))?,
+            "queue_capacity" => {
+                self.queue_capacity = parse_number(value).map_err(|e| invalid(&e))?
+            }
+            "document_root" => self.document_root = PathBuf::from(value),
+            "idle_timeout" => self.idle_timeout = parse_duration(value).map_err(|e| invalid(&e))?,
+            "header_timeout" => {
+                self.header_timeout = parse_duration(value).map_err(|e| invalid(&e))?
+            }
+            "read_timeout" => self.read_timeout = parse_duration(value).map_err(|e| invalid(&e))?,
+            "write_timeout" => {
+                self.write_timeout = parse_duration(value).map_err(|e| invalid(&e))?
+            }
+            "max_requests" => self.max_requests = parse_number(value).map_err(|e| invalid(&e))?,
+            "shutdown_timeout" => {
+                self.shutdown_timeout = parse_duration(value).map_err(|e| invalid(&e))?
+            }
+            "max_header_size" => {
+                self.max_header_size = parse_size(value).map_err(|e| invalid(&e))?
+            }
+            "max_headers" => self.max_headers = parse_number(value).map_err(|e| invalid(&e))?,
+            "max_body_size" => self.max_body_size = parse_size(value).map_err(|e| invalid(&e))?,
+            "compression" => self.compression = parse_bool(value).map_err(|e| invalid(&e))?,
+            "compression_min_size" => {
+                self.compression_min_size = parse_size(value).map_err(|e| invalid(&e))?
+            }
+            "log_level" => {
+                self.log_level = LevelFilter::from_str(value)
+                    .map_err(|_| invalid("expected off, error, warn, info, debug or trace"))?
+            }
+            "tls_cert" => self.tls_cert = optional(value).map(PathBuf::from),
+            "tls_key" => self.tls_key = optional(value).map(PathBuf::from),
+            "tls_port" => {
+                self.tls_port = optional(value)
+                    .map(str::parse)
+                    .transpose()
+                    .map_err(|_| invalid("expected a port number between 0 and 65535"))?
+            }
+            "https_redirect" => self.https_redirect = parse_bool(value).map_err(|e| invalid(&e))?,
+            "server_name" => self.server_name = optional(value).map(String::from),
+            "access_log" => self.access_log = optional(value).map(PathBuf::from),
+            "access_log_format" => {
+                self.access_log_format = LogFormat::from_str(value).map_err(|e| invalid(&e))?
+            }
+            "access_log_max_size" => {
+                self.access_log_max_size = parse_size(value).map_err(|e| invalid(&e))?
+            }
+            "access_log_max_files" => {
+                self.access_log_max_files = parse_number(value).map_err(|e| invalid(&e))?
+            }
+            "shutdown_route" => self.shutdown_route = optional(value).map(String::from),
+            "health_route" => self.health_route = optional(value).map(String::from),
+            "metrics_route" => self.metrics_route = optional(value).map(String::from),
+            "proxy" => self.proxy = parse_proxy(value).map_err(|e| invalid(&e))?,
+            "virtual_hosts" => {
+                self.virtual_hosts = parse_virtual_hosts(value).map_err(|e| invalid(&e))?
+            }
+            "unknown_hosts" => {
+                self.unknown_hosts = UnknownHosts::from_str(value).map_err(|e| invalid(&e))?
+            }
+            _ => {
+                return Err(ConfigError::UnknownKey {
+                    key: String::from(key),
+                    source: source.clone(),
+                })
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks the values make sense together, whatever source they came from.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |key: &'static str, reason: &str| {
+            Err(ConfigError::Invalid {
+                key,
+                reason: String::from(reason),
+            })
+        };
+
+        if self.host.is_empty() {
+            return invalid("host", "must not be empty");
+        }
+        if self.workers == 0 {
+            return invalid("workers", "must be at least 1");
+        }
+        if self.max_workers != 0 && self.max_workers < self.workers {
+            return invalid("max_workers", "must be 0 or at least workers");
+        }
+        if self.max_requests == 0 {
+            return invalid("max_requests", "must be at least 1");
+        }
+        for (key, timeout) in [
+            ("idle_timeout", self.idle_timeout),
+            ("header_timeout", self.header_timeout),
+            ("read_timeout", self.read_timeout),
+            ("write_timeout", self.write_timeout),
+        ] {
+            // A zero timeout means none at all to the socket, which is what they are here to avoid.
+            if timeout.is_zero() {
+                return invalid(key, "must be longer than zero");
+            }
+        }
+        if self.max_header_size == 0 {
+            return invalid("max_header_size", "must be at least 1 byte");
+        }
+        if self.max_headers == 0 {
+            return invalid("max_headers", "must be at least 1");
+        }
+        if !self.document_root.is_dir() {
+            return invalid(
+                "document_root",
+                &format!("{} is not a directory", self.document_root.display()),
+            );
+        }
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(_), None) => return invalid("tls_key", "must be set along with tls_cert"),
+            (None, Some(_)) => return invalid("tls_cert", "must be set along with tls_key"),
+            (None, None) if self.tls_port.is_some() => {
+                return invalid("tls_port", "needs tls_cert and tls_key")
+            }
+            _ => {}
+        }
+        if self.https_redirect && self.tls_port.is_none() {
+            return invalid("https_redirect", "needs tls_port to redirect to");
+        }
+        // Redirecting to the address then sends clients to `0.0.0.0` or `::`.
+        let unspecified = IpAddr::from_str(&self.host).is_ok_and(|ip| ip.is_unspecified());
+        if self.https_redirect && unspecified && self.server_name.is_none() {
+            return invalid(
+                "server_name",
+                "must be set to redirect to HTTPS when host is an unspecified address",
+            );
+        }
+        if self.tls_port == Some(self.port) && self.port != 0 {
+            return invalid("tls_port", "must differ from port");
+        }
+        let routes = [
+            ("shutdown_route", &self.shutdown_route),
+            ("health_route", &self.health_route),
+            ("metrics_route", &self.metrics_route),
+        ];
+        for (key, route) in routes {
+            if route.as_ref().is_some_and(|route| !route.starts_with('/')) {
+                return invalid(key, "must start with a slash");
+            }
+        }
+        if self
+            .proxy
+            .iter()
+            .any(|route| !route.prefix.starts_with('/'))
+        {
+            return invalid("proxy", "prefixes must start with a slash");
+        }
+        for host in &self.virtual_hosts {
+            if !host.document_root.is_dir() {
+                return invalid(
+                    "virtual_hosts",
+                    &format!(
+                        "{} is not a directory, for {}",
+                        host.document_root.display(),
+                        host.name
+                    ),
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The `host:port` pair to bind to.
+    pub fn address(&self) -> String {
+        self.address_on(self.port)
+    }
+
+    /// The `host:port` pair to serve HTTPS on next to plain HTTP, if any.
+    pub fn tls_address(&self) -> Option<String> {
+        self.tls_port.map(|port| self.address_on(port))
+    }
+
+    fn address_on(&self, port: u16) -> String {
+        if self.host.contains(':') {
+            // An IPv6 address has to be bracketed to be told apart from the port.
+            format!("[{}]:{}", self.host, port)
+        } else {
+            format!("{}:{}", self.host, port)
+        }
+    }
+
+    /// A help text listing every flag, printed for `--help`.
+    pub fn usage() -> String {
+        let mut usage = String::from(
+            "Options, also settable as SERVER_<KEY> environment variables or keys of the file given to --config:\n\n  --config <path>\n",
+        );
+        for (key, description) in KEYS {
+            usage.push_str(&format!(
+                "  --{:<20} {}\n",
+                key.replace('_', "-"),
+                description
+            ));
+        }
+        usage
+    }
+}
+
+/// Where a value came from, for error messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    File(PathBuf),
+    Env(String),
+    Flag(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::File(path) => write!(f, "in {}", path.display()),
+            Source::Env(name) => write!(f, "in environment variable {}", name),
+            Source::Flag(name) => write!(f, "from flag --{}", name),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    Syntax {
+        source: Source,
+        message: String,
+    },
+    UnknownKey {
+        key: String,
+        source: Source,
+    },
+    InvalidValue {
+        key: String,
+        value: String,
+        reason: String,
+        source: Source,
+    },
+    /// A flag was given last, without its value.
+    MissingValue(String),
+    /// A value that parsed fine but can't be used.
+    Invalid {
+        key: &'static str,
+        reason: String,
+    },
+}
+
+impl ConfigError {
+    /// The key the error is about, if any.
+    pub fn key(&self) -> Option<&str> {
+        match self {
+            ConfigError::UnknownKey { key, .. } | ConfigError::InvalidValue { key, .. } => {
+                Some(key)
+            }
+            ConfigError::Invalid { key, .. } => Some(key),
+            ConfigError::MissingValue(key) => Some(key),
+            ConfigError::Io { .. } | ConfigError::Syntax { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, error } => {
+                write!(f, "failed to read {}: {}", path.display(), error)
+            }
+            ConfigError::Syntax { source, message } => {
+                write!(f, "invalid TOML {}: {}", source, message)
+            }
+            ConfigError::UnknownKey { key, source } => {
+                write!(f, "unknown key `{}` {}", key, source)
+            }
+            ConfigError::InvalidValue {
+                key,
+                value,
+                reason,
+                source,
+            } => write!(
+                f,
+                "invalid value {:?} for `{}` {}: {}",
+                value, key, source, reason
+            ),
+            ConfigError::MissingValue(key) => write!(f, "missing value for flag --{}", key),
+            ConfigError::Invalid { key, reason } => write!(f, "invalid `{}`: {}", key, reason),
+        }
+    }
+}
+
+impl error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ConfigError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+fn is_key(key: &str) -> bool {
+    KEYS.iter().any(|(known, _)| *known == key)
+}
+
+/// Splits `--key value` and `--key=value` flags into snake_case keys and their values.
+fn parse_flags<A: IntoIterator<Item = String>>(
+    args: A,
+) -> Result<Vec<(String, String)>, ConfigError> {
+    let mut flags = Vec::new();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        let flag = arg
+            .strip_prefix("--")
+            .ok_or_else(|| ConfigError::UnknownKey {
+                key: arg.clone(),
+                source: Source::Flag(arg.clone()),
+            })?;
+
+        let (name, value) = match flag.split_once('=') {
+            Some((name, value)) => (name, String::from(value)),
+            None => match args.next() {
+                Some(value) => (flag, value),
+                None => return Err(ConfigError::MissingValue(String::from(flag))),
+            },
+        };
+
+        let key = name.replace('-', "_");
+        if key != "config" && !is_key(&key) {
+            return Err(ConfigError::UnknownKey {
+                key,
+                source: Source::Flag(String::from(name)),
+            });
+        }
+        flags.push((key, value));
+    }
+
+    Ok(flags)
+}
+
+fn parse_number(value: &str) -> Result<usize, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| String::from("expected a positive whole number"))
+}
+
+/// An empty value unsets an optional key.
+fn optional(value: &str) -> Option<&str> {
+    Some(value).filter(|value| !value.is_empty())
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => Err(String::from("expected true or false")),
+    }
+}
+
+/// Parses `30`, `30s`, `500ms`, `2m` or `1h`.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| String::from("expected a duration such as 30s or 500ms"))?;
+
+    let seconds: u64 = match unit {
+        "ms" => return Ok(Duration::from_millis(amount)),
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        _ => {
+            return Err(format!(
+                "unknown duration unit {:?}, expected ms, s, m or h",
+                unit
+            ))
+        }
+    };
+
+    amount
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| String::from("duration is too long"))
+}
+
+/// Parses `/api=127.0.0.1:9001,127.0.0.1:9002; /legacy=127.0.0.1:9100`, an empty value
+/// proxies nothing.
+fn parse_proxy(value: &str) -> Result<Vec<ProxyRoute>, String> {
+    value
+        .split(';')
+        .map(str::trim)
+        .filter(|route| !route.is_empty())
+        .map(|route| {
+            let (prefix, upstreams) = route.split_once('=').ok_or_else(|| {
+                String::from("expected prefixes and upstreams such as /api=127.0.0.1:9001")
+            })?;
+            let upstreams: Vec<String> = upstreams
+                .split(',')
+                .map(str::trim)
+                .filter(|upstream| !upstream.is_empty())
+                .map(String::from)
+                .collect();
+            if upstreams.is_empty() {
+                return Err(format!("no upstream for {}", prefix.trim()));
+            }
+            Ok(ProxyRoute {
+                prefix: String::from(prefix.trim()),
+                upstreams,
+            })
+        })
+        .collect()
+}
+
+/// Parses `example.com=public/example; *.blog.example.com=public/blog`.
+fn parse_virtual_hosts(value: &str) -> Result<Vec<VirtualHost>, String> {
+    value
+        .split(';')
+        .map(str::trim)
+        .filter(|host| !host.is_empty())
+        .map(|host| match host.split_once('=') {
+            Some((name, root)) if !name.trim().is_empty() && !root.trim().is_empty() => {
+                Ok(VirtualHost {
+                    name: String::from(name.trim()),
+                    document_root: PathBuf::from(root.trim()),
+                })
+            }
+            _ => Err(String::from(
+                "expected host names and document roots such as example.com=public",
+            )),
+        })
+        .collect()
+}
+
+/// Parses `1024`, `16K`, `10M` or `1G`, optionally followed by `B` or `iB` (`16KiB`).
+fn parse_size(value: &str) -> Result<usize, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: usize = amount
+        .parse()
+        .map_err(|_| String::from("expected a size such as 16K or 10M"))?;
+
+    let unit = unit.trim_end_matches(['B', 'b']).trim_end_matches('i');
+    let multiplier: usize = match unit.to_ascii_uppercase().as_str() {
+        "" => 1,
+        "K" => 1024,
+        "M" => 1024 * 1024,
+        "G" => 1024 * 1024 * 1024,
+        _ => return Err(format!("unknown size unit {:?}, expected K, M or G", unit)),
+    };
+
+    amount
+        .checked_mul(multiplier)
+        .ok_or_else(|| String::from("size is too large"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(args: &[&str], vars: &[(&str, &str)]) -> Result<ServerConfig, ConfigError> {
+        ServerConfig::from_sources(
+            args.iter().map(|arg| String::from(*arg)),
+            vars.iter()
+                .map(|(name, value)| (String::from(*name), String::from(*value))),
+        )
+    }
+
+    #[test]
+    fn it_layers_file_environment_and_flags() {
+        let dir = env::temp_dir().join(format!("server-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("server.toml");
+        fs::write(
+            &file,
+            "port = 9000\nworkers = 8\nidle_timeout = \"500ms\"\nmax_body_size = \"1M\"\nlog_level = \"debug\"\n",
+        )
+        .unwrap();
+
+        let config = load(
+            &["--config", file.to_str().unwrap(), "--workers=2"],
+            &[("SERVER_PORT", "9001"), ("SERVER_WORKERS", "6")],
+        )
+        .unwrap();
+
+        assert_eq!(config.port, 9001);
+        assert_eq!(config.workers, 2);
+        assert_eq!(config.idle_timeout, Duration::from_millis(500));
+        assert_eq!(config.max_body_size, 1024 * 1024);
+        assert_eq!(config.log_level, LevelFilter::Debug);
+        assert_eq!(config.host, "127.0.0.1");
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn it_names_the_offending_key() {
+        let error = load(&[], &[("SERVER_PORT", "eighty")]).unwrap_err();
+        assert_eq!(error.key(), Some("port"));
+        assert!(error.to_string().contains("SERVER_PORT"));
+
+        let error = load(&["--max-body-size", "10X"], &[]).unwrap_err();
+        assert_eq!(error.key(), Some("max_body_size"));
+
+        let error = load(&["--workers", "0"], &[]).unwrap_err();
+        assert_eq!(error.key(), Some("workers"));
+        let error = load(&["--workers=4", "--max-workers=2"], &[]).unwrap_err();
+        assert_eq!(error.key(), Some("max_workers"));
+
+        let error = load(&["--colour", "blue"], &[]).unwrap_err();
+        assert!(matches!(error, ConfigError::UnknownKey { ref key, .. } if key == "colour"));
+
+        let redirect = ["--tls-cert=c.pem", "--tls-key=k.pem", "--tls-port=8443"];
+        let redirect = [&redirect[..], &["--https-redirect=true"]].concat();
+        for host in ["0.0.0.0", "::"] {
+            let error = load(&[&redirect[..], &["--host", host]].concat(), &[]).unwrap_err();
+            assert_eq!(error.key(), Some("server_name"));
+        }
+        let args = [&redirect[..], &["--host=::", "--server-name=example.com"]].concat();
+        assert!(load(&args, &[]).is_ok());
+        assert!(load(&[&redirect[..], &["--host=127.0.0.1"]].concat(), &[]).is_ok());
+
+        let error = load(&["--port"], &[]).unwrap_err();
+        assert!(matches!(error, ConfigError::MissingValue(_)));
+
+        let mut config = ServerConfig::default();
+        let error = config
+            .merge_toml(
+                "[tls]\ncert = \"x\"\n",
+                &Source::File(PathBuf::from("a.toml")),
+            )
+            .unwrap_err();
+        assert_eq!(error.key(), Some("tls"));
+    }
+
+    #[test]
+    fn it_parses_durations_and_sizes() {
+        assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert!(parse_duration("fast").is_err());
+        assert_eq!(
+            parse_duration("999999999999999999h"),
+            Err(String::from("duration is too long"))
+        );
+
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("16KiB"), Ok(16 * 1024));
+        assert_eq!(parse_size("2mb"), Ok(2 * 1024 * 1024));
+        assert!(parse_size("1T").is_err());
+
+        assert_eq!(parse_bool("off"), Ok(false));
+        assert!(parse_bool("maybe").is_err());
+
+        assert_eq!(
+            parse_proxy("/api=127.0.0.1:9001, 127.0.0.1:9002; /old=10.0.0.5:80;"),
+            Ok(vec![
+                ProxyRoute {
+                    prefix: String::from("/api"),
+                    upstreams: vec![
+                        String::from("127.0.0.1:9001"),
+                        String::from("127.0.0.1:9002")
+                    ],
+                },
+                ProxyRoute {
+                    prefix: String::from("/old"),
+                    upstreams: vec![String::from("10.0.0.5:80")],
+                },
+            ])
+        );
+        assert_eq!(parse_proxy(""), Ok(Vec::new()));
+        assert!(parse_proxy("/api").is_err());
+        assert!(parse_proxy("/api=").is_err());
+
+        assert_eq!(
+            parse_virtual_hosts("example.com = public ; *.example.org=sites/org"),
+            Ok(vec![
+                VirtualHost {
+                    name: String::from("example.com"),
+                    document_root: PathBuf::from("public"),
+                },
+                VirtualHost {
+                    name: String::from("*.example.org"),
+                    document_root: PathBuf::from("sites/org"),
+                },
+            ])
+        );
+        assert!(parse_virtual_hosts("example.com").is_err());
+        assert_eq!(UnknownHosts::from_str("421"), Ok(UnknownHosts::Misdirected));
+    }
+}
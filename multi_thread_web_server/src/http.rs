@@ -0,0 +1,20 @@
+pub mod chunked;
+pub mod cookie;
+pub mod date;
+pub mod deferred;
+pub mod headers;
+pub mod json;
+pub mod method;
+pub mod mime;
+pub mod request;
+pub mod response;
+pub mod status;
+
+pub use cookie::{Cookie, CookieError, SameSite};
+pub use deferred::{Completer, Deferred};
+pub use headers::Headers;
+pub use json::JsonError;
+pub use method::Method;
+pub use request::{Limits, ParseError, Request, Version};
+pub use response::{Body, Response};
+pub use status::StatusCode;
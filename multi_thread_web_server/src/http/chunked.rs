@@ -0,0 +1,209 @@
+use super::request::{read_line, Limits, ParseError};
+use std::io::{self, BufRead, Read, Write};
+
+/// Decodes a `Transfer-Encoding: chunked` body as it is read.
+/// Each chunk is a hexadecimal size line followed by that many bytes and a CRLF, a zero sized chunk
+/// ends the body, and any trailer fields after it are read and discarded.
+///
+/// Each size line may take up to `max_line_size` bytes, the last one and the trailer fields after
+/// it as many together.
+pub struct ChunkedReader<R> {
+    inner: R,
+    remaining: usize,
+    done: bool,
+    max_line_size: usize,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    /// A reader allowing lines as large as the default `Limits::max_header_size`.
+    pub fn new(inner: R) -> Self {
+        ChunkedReader::with_max_line_size(inner, Limits::default().max_header_size)
+    }
+
+    pub fn with_max_line_size(inner: R, max_line_size: usize) -> Self {
+        ChunkedReader {
+            inner,
+            remaining: 0,
+            done: false,
+            max_line_size,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Reads a line taking at most `budget` bytes, which it lowers by the ones it took.
+    fn read_line(&mut self, budget: &mut usize) -> io::Result<String> {
+        match read_line(&mut self.inner, budget) {
+            Ok(Some(line)) => Ok(line),
+            Ok(None) => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "chunked body ended early",
+            )),
+            Err(ParseError::Io(e)) => Err(e),
+            Err(ParseError::HeadersTooLarge) => Err(invalid("chunk line is too long")),
+            Err(_) => Err(invalid("chunk line is not valid UTF-8")),
+        }
+    }
+
+    fn next_chunk(&mut self) -> io::Result<()> {
+        let mut budget = self.max_line_size;
+        let line = self.read_line(&mut budget)?;
+        // Chunk extensions (";name=value") are allowed after the size, we have no use for them.
+        let size = line.split(';').next().unwrap_or("").trim();
+        // `from_str_radix` would also accept a leading sign.
+        if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid("invalid chunk size"));
+        }
+        let size = usize::from_str_radix(size, 16).map_err(|_| invalid("invalid chunk size"))?;
+
+        if size == 0 {
+            // The trailer fields share what the last size line left of the budget.
+            while !self.read_line(&mut budget)?.is_empty() {}
+            self.done = true;
+        }
+
+        self.remaining = size;
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+
+        if self.remaining == 0 {
+            self.next_chunk()?;
+            if self.done {
+                return Ok(0);
+            }
+        }
+
+        let max = buf.len().min(self.remaining);
+        let read = self.inner.read(&mut buf[..max])?;
+        if read == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "chunked body ended early",
+            ));
+        }
+
+        self.remaining -= read;
+        let mut budget = self.max_line_size;
+        if self.remaining == 0 && !self.read_line(&mut budget)?.is_empty() {
+            return Err(invalid("chunk data is not followed by CRLF"));
+        }
+
+        Ok(read)
+    }
+}
+
+/// Encodes everything written to it as `Transfer-Encoding: chunked`, one chunk per `write` call.
+/// `finish` must be called to send the final zero sized chunk.
+pub struct ChunkedWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    pub fn new(inner: W) -> Self {
+        ChunkedWriter { inner }
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(b"0\r\n\r\n")?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // An empty chunk would end the body early.
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let mut chunk = format!("{:x}\r\n", buf.len()).into_bytes();
+        chunk.extend_from_slice(buf);
+        chunk.extend_from_slice(b"\r\n");
+        self.inner.write_all(&chunk)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_decodes_chunks_and_skips_trailers() {
+        let raw = "4\r\nWiki\r\n5;ext=1\r\npedia\r\n0\r\nExpires: never\r\n\r\nNEXT";
+        let mut reader = ChunkedReader::new(raw.as_bytes());
+        let mut body = String::new();
+        reader.read_to_string(&mut body).unwrap();
+
+        assert_eq!(body, "Wikipedia");
+
+        let mut rest = String::new();
+        reader.into_inner().read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "NEXT");
+    }
+
+    #[test]
+    fn it_encodes_chunks_that_decode_back() {
+        let mut writer = ChunkedWriter::new(Vec::new());
+        writer.write_all(b"Wiki").unwrap();
+        writer.write_all(b"").unwrap();
+        writer.write_all(b"pedia").unwrap();
+        let encoded = writer.finish().unwrap();
+
+        assert_eq!(encoded, b"4\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\n");
+
+        let mut decoded = String::new();
+        ChunkedReader::new(&encoded[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "Wikipedia");
+    }
+
+    #[test]
+    fn it_rejects_invalid_chunk_sizes() {
+        for raw in ["zz\r\nabc\r\n0\r\n\r\n", "+3\r\nabc\r\n0\r\n\r\n"] {
+            let mut reader = ChunkedReader::new(raw.as_bytes());
+            let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn it_bounds_size_lines_and_trailers() {
+        let read = |raw: String| {
+            ChunkedReader::with_max_line_size(raw.as_bytes(), 32)
+                .read_to_end(&mut Vec::new())
+                .map_err(|e| e.kind())
+        };
+
+        assert_eq!(
+            read(format!("3;{}\r\nabc\r\n0\r\n\r\n", "x".repeat(20))),
+            Ok(3)
+        );
+        let extension = format!("3;{}\r\nabc\r\n0\r\n\r\n", "x".repeat(40));
+        assert_eq!(read(extension), Err(io::ErrorKind::InvalidData));
+
+        let trailers = "A: b\r\n".repeat(8);
+        let raw = format!("3\r\nabc\r\n0\r\n{}\r\n", trailers);
+        assert_eq!(read(raw), Err(io::ErrorKind::InvalidData));
+    }
+}
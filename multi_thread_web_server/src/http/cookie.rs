@@ -0,0 +1,263 @@
+use super::date;
+use super::request::is_token_byte;
+use std::{
+    error, fmt,
+    time::{Duration, SystemTime},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+/// A cookie to be sent with `Set-Cookie`, see `Response::with_cookie`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    expires: Option<SystemTime>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+/// Why a cookie couldn't be made, see `Cookie::try_new`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CookieError {
+    /// The name is empty or not a token, holds the name.
+    InvalidName(String),
+    /// The value holds whitespace, quotes, commas, semicolons or backslashes, holds the value.
+    InvalidValue(String),
+    /// The `Path` holds a semicolon or a control character, holds the path.
+    InvalidPath(String),
+    /// The `Domain` holds a semicolon or a control character, holds the domain.
+    InvalidDomain(String),
+}
+
+impl fmt::Display for CookieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CookieError::InvalidName(name) => write!(f, "invalid cookie name {:?}", name),
+            CookieError::InvalidValue(value) => write!(f, "invalid cookie value {:?}", value),
+            CookieError::InvalidPath(path) => write!(f, "invalid cookie path {:?}", path),
+            CookieError::InvalidDomain(domain) => write!(f, "invalid cookie domain {:?}", domain),
+        }
+    }
+}
+
+impl error::Error for CookieError {}
+
+impl Cookie {
+    /// Panics if `name` is not a token or `value` contains characters a cookie value can't hold,
+    /// use `try_new` for names and values that come from elsewhere.
+    pub fn new(name: &str, value: &str) -> Self {
+        Cookie::try_new(name, value).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// The cookie, unless `name` is not a token or `value` contains characters a cookie value
+    /// can't hold (whitespace, quotes, commas, semicolons or backslashes).
+    pub fn try_new(name: &str, value: &str) -> Result<Self, CookieError> {
+        if name.is_empty() || !name.bytes().all(is_token_byte) {
+            return Err(CookieError::InvalidName(String::from(name)));
+        }
+        if !value
+            .bytes()
+            .all(|b| b.is_ascii_graphic() && !b"\",;\\".contains(&b))
+        {
+            return Err(CookieError::InvalidValue(String::from(value)));
+        }
+
+        Ok(Cookie {
+            name: String::from(name),
+            value: String::from(value),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        })
+    }
+
+    /// A cookie that makes the client forget the cookie called `name`.
+    pub fn removal(name: &str) -> Self {
+        Cookie::new(name, "").max_age(Duration::ZERO)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Panics if `path` holds a semicolon or a control character, see `try_path`.
+    pub fn path(self, path: &str) -> Self {
+        self.try_path(path).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// The cookie with its `Path`, unless `path` holds a semicolon or a control character,
+    /// which would add attributes of its own.
+    pub fn try_path(mut self, path: &str) -> Result<Self, CookieError> {
+        if !is_attribute_value(path) {
+            return Err(CookieError::InvalidPath(String::from(path)));
+        }
+        self.path = Some(String::from(path));
+        Ok(self)
+    }
+
+    /// Panics if `domain` holds a semicolon or a control character, see `try_domain`.
+    pub fn domain(self, domain: &str) -> Self {
+        self.try_domain(domain).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// The cookie with its `Domain`, unless `domain` holds a semicolon or a control character.
+    pub fn try_domain(mut self, domain: &str) -> Result<Self, CookieError> {
+        if !is_attribute_value(domain) {
+            return Err(CookieError::InvalidDomain(String::from(domain)));
+        }
+        self.domain = Some(String::from(domain));
+        Ok(self)
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn expires(mut self, expires: SystemTime) -> Self {
+        self.expires = Some(expires);
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+}
+
+fn is_attribute_value(value: &str) -> bool {
+    value
+        .bytes()
+        .all(|b| b.is_ascii() && !b.is_ascii_control() && b != b';')
+}
+
+/// Formats the cookie as the value of a `Set-Cookie` header.
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", date::format(expires))?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => write!(f, "; SameSite=Strict"),
+            Some(SameSite::Lax) => write!(f, "; SameSite=Lax"),
+            Some(SameSite::None) => write!(f, "; SameSite=None"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn it_formats_set_cookie_values() {
+        let cookie = Cookie::new("session", "abc123")
+            .path("/")
+            .max_age(Duration::from_secs(3600))
+            .expires(UNIX_EPOCH + Duration::from_secs(784_111_777))
+            .secure(true)
+            .http_only(true)
+            .same_site(SameSite::Lax);
+
+        assert_eq!(
+            cookie.to_string(),
+            "session=abc123; Path=/; Max-Age=3600; Expires=Sun, 06 Nov 1994 08:49:37 GMT; Secure; HttpOnly; SameSite=Lax"
+        );
+        assert_eq!(
+            Cookie::removal("session").to_string(),
+            "session=; Max-Age=0"
+        );
+    }
+
+    #[test]
+    fn it_refuses_names_and_values_that_would_break_the_header() {
+        assert_eq!(
+            Cookie::try_new("session", "a b"),
+            Err(CookieError::InvalidValue(String::from("a b")))
+        );
+        assert_eq!(
+            Cookie::try_new("se;ssion", "abc"),
+            Err(CookieError::InvalidName(String::from("se;ssion")))
+        );
+        assert_eq!(
+            Cookie::try_new("session", "abc123").map(|cookie| cookie.to_string()),
+            Ok(String::from("session=abc123"))
+        );
+
+        let cookie = || Cookie::new("session", "abc123");
+        assert_eq!(
+            cookie().try_path("/; Domain=evil.example"),
+            Err(CookieError::InvalidPath(String::from(
+                "/; Domain=evil.example"
+            )))
+        );
+        assert_eq!(
+            cookie().try_domain("example.com\r\nX-Evil: 1"),
+            Err(CookieError::InvalidDomain(String::from(
+                "example.com\r\nX-Evil: 1"
+            )))
+        );
+        assert_eq!(
+            cookie()
+                .try_path("/app")
+                .and_then(|cookie| cookie.try_domain("example.com"))
+                .map(|cookie| cookie.to_string()),
+            Ok(String::from(
+                "session=abc123; Path=/app; Domain=example.com"
+            ))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid cookie value")]
+    fn it_refuses_values_that_would_break_the_header() {
+        Cookie::new("session", "a;b");
+    }
+}
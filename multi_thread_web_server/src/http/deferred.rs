@@ -0,0 +1,186 @@
+use super::{Response, StatusCode};
+use crate::timer::Timer;
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Makes the response once it is ready, on the worker that writes it.
+pub(crate) type Make = Box<dyn FnOnce() -> Response + Send + 'static>;
+
+/// A response that isn't ready yet, for handlers that would otherwise block a worker waiting for
+/// it. The server sets the connection aside without holding on to a worker, and picks it up
+/// again on any worker once the response is ready.
+///
+/// A handler returns one wrapped in `Response::deferred`:
+///
+/// ```
+/// use multi_thread_web_server::http::{Deferred, Response, StatusCode};
+/// use std::time::Duration;
+///
+/// let response = Response::deferred(Deferred::after(Duration::from_secs(1), || {
+///     Response::text(StatusCode::Ok, "a second later")
+/// }));
+/// assert!(response.is_deferred());
+/// ```
+pub struct Deferred {
+    kind: Kind,
+}
+
+enum Kind {
+    After(Duration, Make),
+    Completion(Arc<Mutex<Slot>>),
+}
+
+enum Slot {
+    Waiting,
+    Ready(Response),
+    Resuming(Box<dyn FnOnce(Make) + Send + 'static>),
+    Done,
+}
+
+impl Deferred {
+    /// Responds with what `make` returns once `delay` has elapsed, as timed by the server's
+    /// timer.
+    pub fn after<F>(delay: Duration, make: F) -> Self
+    where
+        F: FnOnce() -> Response + Send + 'static,
+    {
+        Deferred {
+            kind: Kind::After(delay, Box::new(make)),
+        }
+    }
+
+    /// A response completed through the returned `Completer`, from any thread.
+    pub fn channel() -> (Self, Completer) {
+        let slot = Arc::new(Mutex::new(Slot::Waiting));
+        let deferred = Deferred {
+            kind: Kind::Completion(Arc::clone(&slot)),
+        };
+        (deferred, Completer { slot })
+    }
+
+    /// Calls `resume` with the response once it is ready, from the timer thread or from the
+    /// thread completing it.
+    pub(crate) fn wait<F>(self, timer: &Timer, resume: F)
+    where
+        F: FnOnce(Make) + Send + 'static,
+    {
+        match self.kind {
+            Kind::After(delay, make) => timer.schedule(delay, move || resume(make)),
+            Kind::Completion(slot) => {
+                let mut slot = slot.lock().unwrap();
+                match std::mem::replace(&mut *slot, Slot::Done) {
+                    Slot::Ready(response) => {
+                        drop(slot);
+                        resume(Box::new(move || response));
+                    }
+                    _ => *slot = Slot::Resuming(Box::new(resume)),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Deferred {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            Kind::After(delay, _) => f.debug_tuple("Deferred::After").field(delay).finish(),
+            Kind::Completion(_) => f.write_str("Deferred::Completion"),
+        }
+    }
+}
+
+/// Completes the response of a `Deferred::channel`.
+///
+/// Dropping it without calling `complete` answers with a 500, so a failed producer doesn't leave
+/// the client waiting forever.
+pub struct Completer {
+    slot: Arc<Mutex<Slot>>,
+}
+
+impl Completer {
+    pub fn complete(mut self, response: Response) {
+        self.fill(response);
+    }
+
+    fn fill(&mut self, response: Response) {
+        let mut slot = self.slot.lock().unwrap();
+        match std::mem::replace(&mut *slot, Slot::Done) {
+            Slot::Waiting => *slot = Slot::Ready(response),
+            Slot::Resuming(resume) => {
+                drop(slot);
+                resume(Box::new(move || response));
+            }
+            // Already completed.
+            done => *slot = done,
+        }
+    }
+}
+
+impl Drop for Completer {
+    fn drop(&mut self) {
+        if matches!(
+            *self.slot.lock().unwrap(),
+            Slot::Waiting | Slot::Resuming(_)
+        ) {
+            self.fill(Response::text(
+                StatusCode::InternalServerError,
+                "Internal Server Error",
+            ));
+        }
+    }
+}
+
+impl fmt::Debug for Completer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Completer")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::mpsc, thread};
+
+    fn resumed(deferred: Deferred, timer: &Timer) -> mpsc::Receiver<Response> {
+        let (sender, receiver) = mpsc::channel();
+        deferred.wait(timer, move |make| sender.send(make()).unwrap());
+        receiver
+    }
+
+    #[test]
+    fn it_resumes_once_completed_whichever_comes_first() {
+        let timer = Timer::new();
+        let wait = Duration::from_secs(2);
+
+        let (deferred, completer) = Deferred::channel();
+        let receiver = resumed(deferred, &timer);
+        thread::spawn(move || completer.complete(Response::text(StatusCode::Ok, "later")));
+        let response = receiver.recv_timeout(wait).unwrap();
+        assert_eq!(response.body.as_bytes(), Some(&b"later"[..]));
+
+        let (deferred, completer) = Deferred::channel();
+        completer.complete(Response::new(StatusCode::Created));
+        assert_eq!(
+            resumed(deferred, &timer).recv_timeout(wait).unwrap().status,
+            StatusCode::Created
+        );
+
+        let (deferred, completer) = Deferred::channel();
+        drop(completer);
+        assert_eq!(
+            resumed(deferred, &timer).recv_timeout(wait).unwrap().status,
+            StatusCode::InternalServerError
+        );
+
+        let deferred = Deferred::after(Duration::from_millis(20), || {
+            Response::new(StatusCode::Accepted)
+        });
+        assert_eq!(
+            resumed(deferred, &timer).recv_timeout(wait).unwrap().status,
+            StatusCode::Accepted
+        );
+    }
+}
@@ -0,0 +1,139 @@
+use std::fmt;
+
+/// A list of HTTP header fields.
+/// Lookups ignore ASCII case as header names are case-insensitive, but the original spelling
+/// and insertion order are kept so the fields can be written back out as they came in.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers {
+    fields: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Self {
+        Headers { fields: Vec::new() }
+    }
+
+    /// Returns the first value for `name`, if any.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns every value for `name` in the order they were received.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.fields
+            .iter()
+            .filter(move |(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Adds a field, keeping any previous values for the same name.
+    ///
+    /// Panics if `name` or `value` holds a CR, LF or NUL, which would end the field early and let
+    /// what follows pass for fields of its own.
+    pub fn append(&mut self, name: &str, value: &str) {
+        assert!(
+            !name.contains(is_line_break) && !value.contains(is_line_break),
+            "invalid header field {:?}: {:?}",
+            name,
+            value
+        );
+        self.fields.push((String::from(name), String::from(value)));
+    }
+
+    /// Replaces every previous value for `name` with `value`, panicking like `append`.
+    pub fn insert(&mut self, name: &str, value: &str) {
+        self.remove(name);
+        self.append(name, value);
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.fields
+            .retain(|(field, _)| !field.eq_ignore_ascii_case(name));
+    }
+
+    /// Whether a comma separated header such as `Connection` or `Transfer-Encoding` lists `token`.
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|item| item.trim().eq_ignore_ascii_case(token))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+fn is_line_break(c: char) -> bool {
+    matches!(c, '\r' | '\n' | '\0')
+}
+
+impl fmt::Display for Headers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in &self.fields {
+            write!(f, "{}: {}\r\n", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_looks_up_names_ignoring_case() {
+        let mut headers = Headers::new();
+        headers.append("Content-Type", "text/html");
+
+        assert_eq!(headers.get("content-type"), Some("text/html"));
+        assert_eq!(headers.get("CONTENT-TYPE"), Some("text/html"));
+        assert_eq!(headers.get("Content-Length"), None);
+    }
+
+    #[test]
+    fn it_keeps_repeated_fields_and_replaces_on_insert() {
+        let mut headers = Headers::new();
+        headers.append("Accept", "text/html");
+        headers.append("accept", "application/json");
+
+        assert_eq!(
+            headers.get_all("Accept").collect::<Vec<_>>(),
+            vec!["text/html", "application/json"]
+        );
+
+        headers.insert("ACCEPT", "*/*");
+        assert_eq!(headers.get_all("accept").collect::<Vec<_>>(), vec!["*/*"]);
+    }
+
+    #[test]
+    fn it_finds_tokens_in_comma_separated_values() {
+        let mut headers = Headers::new();
+        headers.append("Transfer-Encoding", "gzip, Chunked");
+
+        assert!(headers.has_token("transfer-encoding", "chunked"));
+        assert!(!headers.has_token("transfer-encoding", "deflate"));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid header field")]
+    fn it_refuses_values_that_would_split_the_field() {
+        Headers::new().insert("Location", "/a\r\nSet-Cookie: session=stolen");
+    }
+}
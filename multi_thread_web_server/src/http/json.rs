@@ -0,0 +1,101 @@
+use super::{Response, StatusCode};
+use serde::Serialize;
+use std::{error, fmt};
+
+/// Why a request body couldn't be read as JSON, see `Request::json`.
+#[derive(Debug)]
+pub enum JsonError {
+    /// The request says its body is something else, holds the `Content-Type` it sent.
+    ContentType(String),
+    /// The body isn't valid JSON, or doesn't have the expected shape. `line` and `column` start
+    /// at 1 and point at where parsing stopped.
+    Invalid {
+        message: String,
+        line: usize,
+        column: usize,
+    },
+}
+
+impl From<serde_json::Error> for JsonError {
+    fn from(e: serde_json::Error) -> Self {
+        // serde_json's message ends with the location, which is reported on its own.
+        let message = e.to_string();
+        let message = match message.rfind(" at line ") {
+            Some(at) => String::from(&message[..at]),
+            None => message,
+        };
+
+        JsonError::Invalid {
+            message,
+            line: e.line(),
+            column: e.column(),
+        }
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonError::ContentType(content_type) => {
+                write!(f, "expected a JSON body, got {}", content_type)
+            }
+            JsonError::Invalid {
+                message,
+                line,
+                column,
+            } => write!(f, "{} at line {} column {}", message, line, column),
+        }
+    }
+}
+
+impl error::Error for JsonError {}
+
+/// A 415 for a body that isn't JSON, a 400 telling where parsing failed otherwise:
+/// `{"error":"missing field `name`","line":1,"column":12}`.
+impl From<JsonError> for Response {
+    fn from(e: JsonError) -> Self {
+        #[derive(Serialize)]
+        struct Body<'a> {
+            error: &'a str,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            line: Option<usize>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            column: Option<usize>,
+        }
+
+        match &e {
+            JsonError::ContentType(_) => Response::json(
+                StatusCode::UnsupportedMediaType,
+                &Body {
+                    error: &e.to_string(),
+                    line: None,
+                    column: None,
+                },
+            ),
+            JsonError::Invalid {
+                message,
+                line,
+                column,
+            } => Response::json(
+                StatusCode::BadRequest,
+                &Body {
+                    error: message,
+                    line: Some(*line),
+                    column: Some(*column),
+                },
+            ),
+        }
+    }
+}
+
+/// Whether `content_type` is `application/json` or a `+json` type, parameters aside.
+pub(crate) fn is_json(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+
+    essence == "application/json" || essence.ends_with("+json")
+}
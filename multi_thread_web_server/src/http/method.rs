@@ -0,0 +1,84 @@
+use super::request::is_token_byte;
+use std::fmt;
+
+/// The request method, the standard ones from RFC 9110 and RFC 5789 plus any extension method a
+/// client may send.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Connect,
+    Options,
+    Trace,
+    Patch,
+    /// Any other syntactically valid method, e.g. `PURGE` or WebDAV's `PROPFIND`.
+    Extension(String),
+}
+
+impl Method {
+    /// Parses a method token. Methods are case-sensitive, so `get` is an extension method rather
+    /// than `GET`. Returns `None` when `token` is not a valid token at all.
+    pub fn from_token(token: &str) -> Option<Method> {
+        let method = match token {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "CONNECT" => Method::Connect,
+            "OPTIONS" => Method::Options,
+            "TRACE" => Method::Trace,
+            "PATCH" => Method::Patch,
+            _ if !token.is_empty() && token.bytes().all(is_token_byte) => {
+                Method::Extension(String::from(token))
+            }
+            _ => return None,
+        };
+
+        Some(method)
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Connect => "CONNECT",
+            Method::Options => "OPTIONS",
+            Method::Trace => "TRACE",
+            Method::Patch => "PATCH",
+            Method::Extension(token) => token,
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_standard_and_extension_methods() {
+        assert_eq!(Method::from_token("DELETE"), Some(Method::Delete));
+        assert_eq!(
+            Method::from_token("PROPFIND"),
+            Some(Method::Extension(String::from("PROPFIND")))
+        );
+        assert_eq!(
+            Method::from_token("get"),
+            Some(Method::Extension(String::from("get")))
+        );
+        assert_eq!(Method::from_token("GE(T"), None);
+        assert_eq!(Method::from_token(""), None);
+    }
+}
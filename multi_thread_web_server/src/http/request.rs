@@ -0,0 +1,693 @@
+use super::chunked::ChunkedReader;
+use super::headers::Headers;
+use super::json::{self, JsonError};
+use super::method::Method;
+use super::status::StatusCode;
+use serde::de::DeserializeOwned;
+use std::{
+    error::Error,
+    fmt,
+    io::{self, BufRead, Read},
+    net::SocketAddr,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Version::Http10 => write!(f, "HTTP/1.0"),
+            Version::Http11 => write!(f, "HTTP/1.1"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    /// The client closed the connection before sending anything.
+    ConnectionClosed,
+    Io(io::Error),
+    MalformedRequestLine(String),
+    UnsupportedVersion(String),
+    MalformedHeader(String),
+    InvalidContentLength(String),
+    UnsupportedTransferEncoding(String),
+    InvalidChunkedBody(String),
+    UriTooLong,
+    HeadersTooLarge,
+    TooManyHeaders,
+    BodyTooLarge,
+    /// The client took longer than allowed to send the request.
+    TimedOut,
+}
+
+impl ParseError {
+    /// The status to answer the client with.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ParseError::UnsupportedVersion(_) => StatusCode::HttpVersionNotSupported,
+            ParseError::UnsupportedTransferEncoding(_) => StatusCode::NotImplemented,
+            ParseError::UriTooLong => StatusCode::UriTooLong,
+            ParseError::HeadersTooLarge | ParseError::TooManyHeaders => {
+                StatusCode::RequestHeaderFieldsTooLarge
+            }
+            ParseError::BodyTooLarge => StatusCode::ContentTooLarge,
+            ParseError::TimedOut => StatusCode::RequestTimeout,
+            _ => StatusCode::BadRequest,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::ConnectionClosed => write!(f, "connection closed by client"),
+            ParseError::Io(e) => write!(f, "failed to read request: {}", e),
+            ParseError::MalformedRequestLine(line) => {
+                write!(f, "malformed request line {:?}", line)
+            }
+            ParseError::UnsupportedVersion(version) => {
+                write!(f, "unsupported HTTP version {:?}", version)
+            }
+            ParseError::MalformedHeader(line) => write!(f, "malformed header {:?}", line),
+            ParseError::InvalidContentLength(value) => {
+                write!(f, "invalid Content-Length {:?}", value)
+            }
+            ParseError::UnsupportedTransferEncoding(value) => {
+                write!(f, "unsupported Transfer-Encoding {:?}", value)
+            }
+            ParseError::InvalidChunkedBody(reason) => write!(f, "invalid chunked body: {}", reason),
+            ParseError::UriTooLong => write!(f, "request line is too long"),
+            ParseError::HeadersTooLarge => write!(f, "request header fields are too large"),
+            ParseError::TooManyHeaders => write!(f, "too many request header fields"),
+            ParseError::BodyTooLarge => write!(f, "request body is too large"),
+            ParseError::TimedOut => write!(f, "timed out waiting for the request"),
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ParseError {
+    fn from(e: io::Error) -> Self {
+        ParseError::Io(e)
+    }
+}
+
+/// How much a client is allowed to send, anything bigger is refused before being read in full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// The size in bytes of the request line and header fields together.
+    pub max_header_size: usize,
+    pub max_headers: usize,
+    pub max_body_size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_header_size: 16 * 1024,
+            max_headers: 100,
+            max_body_size: 10 * 1024 * 1024,
+        }
+    }
+}
+
+/// A parsed HTTP/1.x request.
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: Method,
+    /// The request target exactly as it was sent, e.g. `/search?q=rust%20book`.
+    pub target: String,
+    /// The percent-decoded path of the target, without the query string.
+    pub path: String,
+    /// The percent-decoded query parameters in the order they were sent.
+    pub query: Vec<(String, String)>,
+    pub version: Version,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    /// The address of the client that sent the request, filled in by the server.
+    pub remote_addr: Option<SocketAddr>,
+}
+
+impl Request {
+    /// Reads a single request (request line, header fields and body) from `reader`, with the
+    /// default `Limits`.
+    /// The reader is left positioned right after the body, so it can be called again for the next
+    /// request sent on the same connection.
+    pub fn parse<R: BufRead>(reader: &mut R) -> Result<Request, ParseError> {
+        Request::parse_with_limits(reader, &Limits::default())
+    }
+
+    pub fn parse_with_limits<R: BufRead>(
+        reader: &mut R,
+        limits: &Limits,
+    ) -> Result<Request, ParseError> {
+        let mut request = Request::parse_head(reader, limits)?;
+        request.read_body(reader, limits)?;
+        Ok(request)
+    }
+
+    /// Reads the request line and the header fields only, leaving the body to `read_body`. Lets
+    /// the caller read both under different timeouts.
+    pub fn parse_head<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Request, ParseError> {
+        let mut budget = limits.max_header_size;
+
+        let request_line = loop {
+            let line = match read_line(reader, &mut budget) {
+                Err(ParseError::HeadersTooLarge) => return Err(ParseError::UriTooLong),
+                line => line?,
+            };
+
+            match line {
+                // A client may send empty lines ahead of the request line, those are ignored.
+                Some(line) if line.is_empty() => continue,
+                Some(line) => break line,
+                None => return Err(ParseError::ConnectionClosed),
+            }
+        };
+
+        let mut parts = request_line.split(' ');
+        let (method, target, version) =
+            match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                (Some(method), Some(target), Some(version), None)
+                    if !method.is_empty() && !target.is_empty() =>
+                {
+                    (method, target, version)
+                }
+                _ => return Err(ParseError::MalformedRequestLine(request_line)),
+            };
+
+        let method = Method::from_token(method)
+            .ok_or_else(|| ParseError::MalformedRequestLine(request_line.clone()))?;
+
+        let version = match version {
+            "HTTP/1.1" => Version::Http11,
+            "HTTP/1.0" => Version::Http10,
+            other => return Err(ParseError::UnsupportedVersion(String::from(other))),
+        };
+
+        let (path, query) = parse_target(target)
+            .ok_or_else(|| ParseError::MalformedRequestLine(request_line.clone()))?;
+
+        let mut headers = Headers::new();
+        loop {
+            let line = read_line(reader, &mut budget)?.ok_or_else(|| {
+                ParseError::Io(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed in the middle of the headers",
+                ))
+            })?;
+
+            if line.is_empty() {
+                break;
+            }
+
+            if headers.len() == limits.max_headers {
+                return Err(ParseError::TooManyHeaders);
+            }
+            let (name, value) =
+                parse_header(&line).ok_or(ParseError::MalformedHeader(line.clone()))?;
+            headers.append(name, value);
+        }
+
+        Ok(Request {
+            method,
+            target: String::from(target),
+            path,
+            query,
+            version,
+            headers,
+            body: Vec::new(),
+            remote_addr: None,
+        })
+    }
+
+    /// Reads the body announced by the header fields read by `parse_head`.
+    pub fn read_body<R: BufRead>(
+        &mut self,
+        reader: &mut R,
+        limits: &Limits,
+    ) -> Result<(), ParseError> {
+        self.body = read_body(reader, &self.headers, limits)?;
+        Ok(())
+    }
+
+    /// The path and query of the target as sent, without the scheme and host of an absolute-form
+    /// target. Leading slashes are collapsed into one, a redirect to it stays on this server
+    /// either way.
+    pub fn origin_form(&self) -> &str {
+        let target = origin_form(&self.target);
+        match target.trim_start_matches('/') {
+            rest if rest.len() < target.len() => &target[target.len() - rest.len() - 1..],
+            _ => target,
+        }
+    }
+
+    /// Returns the first value of the query parameter `name`.
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the value of the cookie `name` sent in the `Cookie` header.
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get_all("Cookie")
+            .flat_map(|cookies| cookies.split(';'))
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.trim_matches('"'))
+    }
+
+    /// Parses the body as JSON into `T`. A request without a `Content-Type` is assumed to send
+    /// JSON, one sending another type is refused. The error converts into the response to send
+    /// back:
+    ///
+    /// ```
+    /// use multi_thread_web_server::http::{Request, Response, StatusCode};
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct NewUser {
+    ///     name: String,
+    /// }
+    ///
+    /// fn create(request: &Request) -> Response {
+    ///     match request.json::<NewUser>() {
+    ///         Ok(user) => Response::text(StatusCode::Created, user.name),
+    ///         Err(e) => e.into(),
+    ///     }
+    /// }
+    /// ```
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, JsonError> {
+        if let Some(content_type) = self.headers.get("Content-Type") {
+            if !json::is_json(content_type) {
+                return Err(JsonError::ContentType(String::from(content_type)));
+            }
+        }
+
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+}
+
+/// Reads a line terminated by LF (optionally preceded by CR), without the terminator.
+/// Returns `None` when the reader is already at the end of its input.
+/// The line, terminator included, is taken out of `budget` and `ParseError::HeadersTooLarge` is
+/// returned as soon as it runs out, without buffering the rest of the line.
+pub(crate) fn read_line<R: BufRead>(
+    reader: &mut R,
+    budget: &mut usize,
+) -> Result<Option<String>, ParseError> {
+    let mut line = Vec::new();
+    let read = reader
+        .take(*budget as u64 + 1)
+        .read_until(b'\n', &mut line)?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if read > *budget {
+        return Err(ParseError::HeadersTooLarge);
+    }
+    *budget -= read;
+
+    if line.last() == Some(&b'\n') {
+        line.pop();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+    } else {
+        return Err(ParseError::Io(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed in the middle of a line",
+        )));
+    }
+
+    String::from_utf8(line).map(Some).map_err(|e| {
+        ParseError::MalformedHeader(String::from_utf8_lossy(e.as_bytes()).into_owned())
+    })
+}
+
+pub(crate) fn parse_header(line: &str) -> Option<(&str, &str)> {
+    let (name, value) = line.split_once(':')?;
+
+    // Field names are tokens, so whitespace before the colon or a folded continuation line are
+    // both rejected instead of guessed at.
+    if name.is_empty() || !name.bytes().all(is_token_byte) {
+        return None;
+    }
+
+    // A bare CR or NUL left in a value could end up splitting a response field it is copied to.
+    let value = value.trim_matches(|c| c == ' ' || c == '\t');
+    if value.contains(['\r', '\0']) {
+        return None;
+    }
+
+    Some((name, value))
+}
+
+pub(crate) fn is_token_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+/// Absolute-form targets ("http://host/path") are sent to proxies, keeps only the path and query.
+fn origin_form(target: &str) -> &str {
+    match target.split_once("://") {
+        Some((scheme, rest))
+            if scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https") =>
+        {
+            match rest.find('/') {
+                Some(index) => &rest[index..],
+                None => "/",
+            }
+        }
+        _ => target,
+    }
+}
+
+/// Splits a request target into its decoded path and query parameters.
+fn parse_target(target: &str) -> Option<(String, Vec<(String, String)>)> {
+    if target.bytes().any(|b| b.is_ascii_control()) {
+        return None;
+    }
+    if target == "*" {
+        return Some((String::from(target), Vec::new()));
+    }
+
+    let target = origin_form(target);
+    if !target.starts_with('/') {
+        return None;
+    }
+
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, query),
+        None => (target, ""),
+    };
+
+    let path = String::from_utf8(percent_decode(path, false)?).ok()?;
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Some((decode_component(key)?, decode_component(value)?))
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    Some((path, query))
+}
+
+fn decode_component(component: &str) -> Option<String> {
+    percent_decode(component, true).map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Decodes `%XX` escapes, and `+` as a space when decoding form style query strings.
+fn percent_decode(input: &str, plus_as_space: bool) -> Option<Vec<u8>> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = bytes.get(i + 1..i + 3)?;
+                // `from_str_radix` would take a sign, as in `%+1`.
+                if !hex.iter().all(u8::is_ascii_hexdigit) {
+                    return None;
+                }
+                let hex = std::str::from_utf8(hex).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    Some(decoded)
+}
+
+fn read_body<R: BufRead>(
+    reader: &mut R,
+    headers: &Headers,
+    limits: &Limits,
+) -> Result<Vec<u8>, ParseError> {
+    let mut body = Vec::new();
+    let max_size = limits.max_body_size;
+
+    if headers.contains("Transfer-Encoding") {
+        // Every field counts, a `chunked` one followed by `gzip` must not be read as chunked.
+        let encoding = headers
+            .get_all("Transfer-Encoding")
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        // A message carrying both framings is a classic request smuggling vector, refuse it.
+        if headers.contains("Content-Length") {
+            return Err(ParseError::InvalidContentLength(String::from(
+                "Content-Length sent along Transfer-Encoding",
+            )));
+        }
+
+        if !encoding.trim().eq_ignore_ascii_case("chunked") {
+            return Err(ParseError::UnsupportedTransferEncoding(encoding));
+        }
+
+        ChunkedReader::with_max_line_size(reader, limits.max_header_size)
+            .take(max_size as u64 + 1)
+            .read_to_end(&mut body)
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::InvalidData => ParseError::InvalidChunkedBody(e.to_string()),
+                _ => ParseError::Io(e),
+            })?;
+
+        if body.len() > max_size {
+            return Err(ParseError::BodyTooLarge);
+        }
+
+        return Ok(body);
+    }
+
+    let mut lengths = headers.get_all("Content-Length");
+    let length = match lengths.next() {
+        Some(value) => value,
+        None => return Ok(body),
+    };
+
+    if lengths.any(|other| other != length) {
+        return Err(ParseError::InvalidContentLength(String::from(length)));
+    }
+
+    // `usize::from_str` would also accept a leading '+', which Content-Length does not.
+    let length: usize = match length.parse() {
+        Ok(parsed) if length.bytes().all(|b| b.is_ascii_digit()) => parsed,
+        _ => return Err(ParseError::InvalidContentLength(String::from(length))),
+    };
+
+    if length > max_size {
+        return Err(ParseError::BodyTooLarge);
+    }
+
+    body.resize(length, 0);
+    reader.read_exact(&mut body)?;
+
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> Result<Request, ParseError> {
+        Request::parse(&mut raw.as_bytes())
+    }
+
+    #[test]
+    fn it_parses_the_request_line_and_query() {
+        let request = parse(
+            "GET /search/rust%20book?q=ownership&page=2&empty HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        )
+        .unwrap();
+
+        assert_eq!(request.method, Method::Get);
+        assert_eq!(
+            request.target,
+            "/search/rust%20book?q=ownership&page=2&empty"
+        );
+        assert_eq!(request.path, "/search/rust book");
+        assert_eq!(request.version, Version::Http11);
+        assert_eq!(request.query_param("q"), Some("ownership"));
+        assert_eq!(request.query_param("page"), Some("2"));
+        assert_eq!(request.query_param("empty"), Some(""));
+        assert_eq!(request.headers.get("host"), Some("localhost"));
+        assert_eq!(request.cookie("session"), None);
+        assert!(request.body.is_empty());
+    }
+
+    #[test]
+    fn it_reads_a_content_length_body_and_leaves_the_next_request() {
+        let raw =
+            "POST /form HTTP/1.0\r\nContent-Length: 11\r\n\r\nhello=worldGET / HTTP/1.1\r\n\r\n";
+        let mut reader = raw.as_bytes();
+
+        let first = Request::parse(&mut reader).unwrap();
+        assert_eq!(first.version, Version::Http10);
+        assert_eq!(first.body, b"hello=world");
+
+        let second = Request::parse(&mut reader).unwrap();
+        assert_eq!(second.path, "/");
+    }
+
+    #[test]
+    fn it_parses_json_bodies_into_typed_values() {
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct Point {
+            x: i32,
+            y: i32,
+        }
+        let request = |content_type: &str, body: &str| {
+            parse(&format!(
+                "POST / HTTP/1.1\r\n{}Content-Length: {}\r\n\r\n{}",
+                content_type,
+                body.len(),
+                body
+            ))
+            .unwrap()
+        };
+
+        let point =
+            request("Content-Type: application/json\r\n", r#"{"x":1,"y":-2}"#).json::<Point>();
+        assert_eq!(point.unwrap(), Point { x: 1, y: -2 });
+        let point = request("", r#"{"x":1,"y":-2}"#).json::<Point>();
+        assert!(point.is_ok());
+
+        match request("", "{\n  \"x\": 1,\n  \"y\": \"two\"\n}").json::<Point>() {
+            Err(JsonError::Invalid { line, column, .. }) => assert_eq!((line, column), (3, 12)),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(
+            request("Content-Type: text/plain\r\n", "{}").json::<Point>(),
+            Err(JsonError::ContentType(_))
+        ));
+    }
+
+    #[test]
+    fn it_reads_cookies() {
+        let request =
+            parse("GET / HTTP/1.1\r\nCookie: theme=dark; session=\"abc\"\r\n\r\n").unwrap();
+
+        assert_eq!(request.cookie("session"), Some("abc"));
+        assert_eq!(request.cookie("theme"), Some("dark"));
+        assert_eq!(request.cookie("missing"), None);
+    }
+
+    #[test]
+    fn it_reads_a_chunked_body() {
+        let request = parse(
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n",
+        )
+        .unwrap();
+
+        assert_eq!(request.body, b"abcde");
+    }
+
+    #[test]
+    fn it_refuses_requests_over_the_limits() {
+        let limits = Limits {
+            max_header_size: 64,
+            max_headers: 2,
+            max_body_size: 4,
+        };
+        let parse = |raw: &str| Request::parse_with_limits(&mut raw.as_bytes(), &limits);
+
+        let long_target = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(64));
+        assert!(matches!(parse(&long_target), Err(ParseError::UriTooLong)));
+
+        let long_headers = format!("GET / HTTP/1.1\r\nX-Padding: {}\r\n\r\n", "a".repeat(40));
+        let error = parse(&long_headers).unwrap_err();
+        assert_eq!(error.status(), StatusCode::RequestHeaderFieldsTooLarge);
+
+        let many_headers = "GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n";
+        assert!(matches!(
+            parse(many_headers),
+            Err(ParseError::TooManyHeaders)
+        ));
+
+        let error = parse("POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello").unwrap_err();
+        assert_eq!(error.status(), StatusCode::ContentTooLarge);
+
+        let chunked =
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n";
+        assert!(matches!(parse(chunked), Err(ParseError::BodyTooLarge)));
+
+        assert!(parse("POST / HTTP/1.1\r\nContent-Length: 4\r\n\r\nhell").is_ok());
+    }
+
+    #[test]
+    fn it_rejects_malformed_requests() {
+        assert!(matches!(
+            parse("GET\r\n\r\n"),
+            Err(ParseError::MalformedRequestLine(_))
+        ));
+        assert!(matches!(
+            parse("G(T / HTTP/1.1\r\n\r\n"),
+            Err(ParseError::MalformedRequestLine(_))
+        ));
+        assert!(matches!(
+            parse("GET nope HTTP/1.1\r\n\r\n"),
+            Err(ParseError::MalformedRequestLine(_))
+        ));
+        assert!(matches!(
+            parse("GET / HTTP/2.0\r\n\r\n"),
+            Err(ParseError::UnsupportedVersion(_))
+        ));
+        assert!(matches!(
+            parse("GET / HTTP/1.1\r\nBad Header: x\r\n\r\n"),
+            Err(ParseError::MalformedHeader(_))
+        ));
+        assert!(matches!(
+            parse("GET / HTTP/1.1\r\nX-Forwarded-Host: a\rSet-Cookie: b\r\n\r\n"),
+            Err(ParseError::MalformedHeader(_))
+        ));
+        assert!(matches!(
+            parse("GET /a\rb HTTP/1.1\r\n\r\n"),
+            Err(ParseError::MalformedRequestLine(_))
+        ));
+        for target in ["/a%+1", "/a?b=%-1", "/a%1"] {
+            assert!(matches!(
+                parse(&format!("GET {} HTTP/1.1\r\n\r\n", target)),
+                Err(ParseError::MalformedRequestLine(_))
+            ));
+        }
+        assert!(matches!(
+            parse("POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n"),
+            Err(ParseError::InvalidContentLength(_))
+        ));
+        assert!(matches!(parse(""), Err(ParseError::ConnectionClosed)));
+
+        let smuggled = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: gzip\r\n\r\n0\r\n\r\n";
+        assert!(matches!(
+            parse(smuggled),
+            Err(ParseError::UnsupportedTransferEncoding(encoding)) if encoding == "chunked, gzip"
+        ));
+    }
+}
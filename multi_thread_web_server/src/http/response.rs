@@ -0,0 +1,411 @@
+use super::chunked::ChunkedWriter;
+use super::cookie::Cookie;
+use super::date;
+use super::deferred::Deferred;
+use super::headers::Headers;
+use super::request::Version;
+use super::status::StatusCode;
+use crate::websocket::Upgrade;
+use serde::Serialize;
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    time::SystemTime,
+};
+
+pub enum Body {
+    Bytes(Vec<u8>),
+    /// Read from `reader` while the response is written, so a large file never has to be loaded in
+    /// memory. Without a known `length` the body is sent with chunked encoding.
+    Stream {
+        reader: Box<dyn Read + Send>,
+        length: Option<u64>,
+    },
+}
+
+impl Body {
+    /// The size of the body, `None` for a stream of unknown length.
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::Stream { length, .. } => *length,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    /// The body contents, unless it is streamed.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes),
+            Body::Stream { .. } => None,
+        }
+    }
+
+    /// Reads the whole body in memory.
+    pub fn into_bytes(self) -> io::Result<Vec<u8>> {
+        match self {
+            Body::Bytes(bytes) => Ok(bytes),
+            Body::Stream {
+                reader,
+                length: Some(length),
+            } => {
+                let mut bytes = Vec::new();
+                reader.take(length).read_to_end(&mut bytes)?;
+                Ok(bytes)
+            }
+            Body::Stream {
+                mut reader,
+                length: None,
+            } => {
+                let mut bytes = Vec::new();
+                reader.read_to_end(&mut bytes)?;
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Body::Bytes(bytes) => f.debug_tuple("Bytes").field(&bytes.len()).finish(),
+            Body::Stream { length, .. } => {
+                f.debug_struct("Stream").field("length", length).finish()
+            }
+        }
+    }
+}
+
+/// An HTTP response ready to be written back to the client.
+///
+/// Responses are put together by chaining the `with_*` methods, the framing headers (`Date`,
+/// `Content-Length` or `Transfer-Encoding`) are filled in when the response is written:
+///
+/// ```
+/// use multi_thread_web_server::http::{Cookie, Response, StatusCode};
+///
+/// let response = Response::new(StatusCode::Created)
+///     .with_header("Location", "/users/42")
+///     .with_cookie(Cookie::new("session", "abc123").http_only(true))
+///     .with_body("created");
+/// ```
+#[derive(Debug)]
+pub struct Response {
+    pub status: StatusCode,
+    pub headers: Headers,
+    pub body: Body,
+    /// Set once the body was dropped for a HEAD request, the framing headers describe the body
+    /// that would have been sent.
+    bodiless: bool,
+    /// Set for a placeholder standing in for a response that isn't ready yet.
+    deferred: Option<Deferred>,
+    /// What the connection turns into once this response was sent.
+    upgrade: Option<Upgrade>,
+}
+
+impl Response {
+    pub fn new(status: StatusCode) -> Self {
+        Response {
+            status,
+            headers: Headers::new(),
+            body: Body::Bytes(Vec::new()),
+            bodiless: false,
+            deferred: None,
+            upgrade: None,
+        }
+    }
+
+    /// Stands in for the response `deferred` produces later. The server waits for that one
+    /// without holding on to a worker, runs the `after` hooks of the middlewares on it and sends
+    /// it. The placeholder itself is never sent.
+    pub fn deferred(deferred: Deferred) -> Self {
+        let mut response = Response::new(StatusCode::Ok);
+        response.deferred = Some(deferred);
+        response
+    }
+
+    pub fn is_deferred(&self) -> bool {
+        self.deferred.is_some()
+    }
+
+    pub(crate) fn take_deferred(&mut self) -> Option<Deferred> {
+        self.deferred.take()
+    }
+
+    pub(crate) fn with_upgrade(mut self, upgrade: Upgrade) -> Self {
+        self.upgrade = Some(upgrade);
+        self
+    }
+
+    pub(crate) fn take_upgrade(&mut self) -> Option<Upgrade> {
+        self.upgrade.take()
+    }
+
+    /// A `text/plain` response.
+    pub fn text(status: StatusCode, text: impl Into<String>) -> Self {
+        Response::new(status)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(text.into())
+    }
+
+    /// A `text/html` response.
+    pub fn html(status: StatusCode, html: impl Into<String>) -> Self {
+        Response::new(status)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(html.into())
+    }
+
+    /// An `application/json` response holding `value`. Serializing can only fail for values
+    /// JSON can't represent (e.g. maps with non-string keys), which gets a 500 instead.
+    pub fn json<T: Serialize + ?Sized>(status: StatusCode, value: &T) -> Self {
+        match serde_json::to_vec(value) {
+            Ok(body) => Response::new(status)
+                .with_header("Content-Type", "application/json")
+                .with_body(body),
+            Err(e) => {
+                log::error!("Failed to serialize a JSON response: {}", e);
+                Response::text(StatusCode::InternalServerError, "Internal Server Error")
+            }
+        }
+    }
+
+    pub fn with_status(mut self, status: StatusCode) -> Self {
+        self.status = status;
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = Body::Bytes(body.into());
+        self
+    }
+
+    /// Streams the body from `reader`, which must yield at least `length` bytes.
+    pub fn with_stream<R: Read + Send + 'static>(mut self, reader: R, length: u64) -> Self {
+        self.body = Body::Stream {
+            reader: Box::new(reader),
+            length: Some(length),
+        };
+        self
+    }
+
+    /// Streams the body from `reader` until it ends, using chunked encoding.
+    pub fn with_chunked_stream<R: Read + Send + 'static>(mut self, reader: R) -> Self {
+        self.body = Body::Stream {
+            reader: Box::new(reader),
+            length: None,
+        };
+        self
+    }
+
+    /// Sets `name` to `value`, replacing any previous value.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Adds a value for `name`, keeping the previous ones.
+    pub fn with_appended_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    pub fn with_cookie(self, cookie: Cookie) -> Self {
+        let value = cookie.to_string();
+        self.with_appended_header("Set-Cookie", &value)
+    }
+
+    /// Drops the body while keeping the `Content-Length` (or `Transfer-Encoding`) it would have
+    /// had, which is what a response to a HEAD request looks like.
+    pub fn without_body(mut self) -> Self {
+        if self.status.allows_body() && !self.headers.contains("Content-Length") {
+            match self.body.len() {
+                Some(length) => self.headers.insert("Content-Length", &length.to_string()),
+                None => self.headers.insert("Transfer-Encoding", "chunked"),
+            }
+        }
+        self.body = Body::Bytes(Vec::new());
+        self.bodiless = true;
+        self
+    }
+
+    /// Whether the client can only tell where the body ends by the connection being closed,
+    /// i.e. a stream of unknown length sent to an HTTP/1.0 client that doesn't know chunked.
+    pub fn is_close_delimited(&self, version: Version) -> bool {
+        version == Version::Http10
+            && !self.bodiless
+            && self.status.allows_body()
+            && self.body.len().is_none()
+    }
+
+    /// Writes the status line, the header fields and the body for a client speaking `version`.
+    /// `Date` is added when missing, and `Content-Length` is set from the body unless it is already
+    /// there, so handlers never have to count bytes themselves. Streams of unknown length are
+    /// chunked for HTTP/1.1 clients and close-delimited for HTTP/1.0 ones.
+    pub fn write_to<W: Write>(self, writer: &mut W, version: Version) -> io::Result<()> {
+        let mut headers = self.headers;
+        if !headers.contains("Date") {
+            headers.insert("Date", &date::format(SystemTime::now()));
+        }
+
+        let sends_body = self.status.allows_body() && !self.bodiless;
+        let mut chunked = false;
+        if sends_body {
+            match self.body.len() {
+                Some(length) => {
+                    if !headers.contains("Content-Length") {
+                        headers.insert("Content-Length", &length.to_string());
+                    }
+                }
+                None if version == Version::Http11 => {
+                    headers.remove("Content-Length");
+                    headers.insert("Transfer-Encoding", "chunked");
+                    chunked = true;
+                }
+                None => headers.remove("Content-Length"),
+            }
+        }
+
+        let mut head = format!("HTTP/1.1 {}\r\n{}\r\n", self.status, headers).into_bytes();
+
+        if !sends_body {
+            writer.write_all(&head)?;
+            return writer.flush();
+        }
+
+        match self.body {
+            Body::Bytes(bytes) => {
+                // One write for small responses instead of one for the head and one for the body.
+                head.extend_from_slice(&bytes);
+                writer.write_all(&head)?;
+            }
+            Body::Stream {
+                reader,
+                length: Some(length),
+            } => {
+                writer.write_all(&head)?;
+                let copied = io::copy(&mut reader.take(length), writer)?;
+                if copied < length {
+                    // The client was promised more bytes than we have, all we can do is drop the
+                    // connection so it doesn't wait for them.
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "response body ended before its Content-Length",
+                    ));
+                }
+            }
+            Body::Stream {
+                mut reader,
+                length: None,
+            } => {
+                writer.write_all(&head)?;
+                if chunked {
+                    let mut chunks = ChunkedWriter::new(&mut *writer);
+                    io::copy(&mut reader, &mut chunks)?;
+                    chunks.finish()?;
+                } else {
+                    io::copy(&mut reader, writer)?;
+                }
+            }
+        }
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(response: Response, version: Version) -> String {
+        let mut out = Vec::new();
+        response.write_to(&mut out, version).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn it_adds_date_and_content_length() {
+        let out = written(Response::text(StatusCode::Ok, "hello"), Version::Http11);
+
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(out.contains("\r\nDate: "));
+        assert!(out.contains("\r\nContent-Length: 5\r\n"));
+        assert!(out.ends_with("\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn it_chunks_streams_of_unknown_length() {
+        let response = Response::new(StatusCode::Ok).with_chunked_stream(&b"streamed"[..]);
+        let out = written(response, Version::Http11);
+
+        assert!(out.contains("\r\nTransfer-Encoding: chunked\r\n"));
+        assert!(!out.contains("Content-Length"));
+        assert!(out.ends_with("\r\n\r\n8\r\nstreamed\r\n0\r\n\r\n"));
+
+        let response = Response::new(StatusCode::Ok).with_chunked_stream(&b"streamed"[..]);
+        assert!(response.is_close_delimited(Version::Http10));
+        let out = written(response, Version::Http10);
+        assert!(!out.contains("Transfer-Encoding"));
+        assert!(out.ends_with("\r\n\r\nstreamed"));
+    }
+
+    #[test]
+    fn it_keeps_the_framing_of_a_dropped_body() {
+        let out = written(
+            Response::text(StatusCode::Ok, "hello").without_body(),
+            Version::Http11,
+        );
+        assert!(out.contains("\r\nContent-Length: 5\r\n"));
+        assert!(out.ends_with("\r\n\r\n"));
+
+        let response = Response::new(StatusCode::Ok)
+            .with_chunked_stream(&b"streamed"[..])
+            .without_body();
+        let out = written(response, Version::Http11);
+        assert!(out.contains("\r\nTransfer-Encoding: chunked\r\n"));
+        assert!(out.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn it_never_sends_a_body_with_204_or_304() {
+        let out = written(
+            Response::text(StatusCode::NoContent, "ignored"),
+            Version::Http11,
+        );
+
+        assert!(!out.contains("Content-Length"));
+        assert!(out.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn it_serializes_json_and_reports_where_parsing_failed() {
+        let response = Response::json(StatusCode::Ok, &vec![1, 2, 3]);
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("application/json")
+        );
+        assert_eq!(response.body.as_bytes(), Some(&b"[1,2,3]"[..]));
+
+        let e = serde_json::from_str::<Vec<u32>>("[1,\n-2]").unwrap_err();
+        let response = Response::from(crate::http::JsonError::from(e));
+        assert_eq!(response.status, StatusCode::BadRequest);
+        let body: serde_json::Value =
+            serde_json::from_slice(response.body.as_bytes().unwrap()).unwrap();
+        assert_eq!(body["line"], 2);
+        assert_eq!(body["column"], 2);
+        assert!(body["error"].as_str().unwrap().starts_with("invalid value"));
+    }
+
+    #[test]
+    fn it_sends_one_set_cookie_per_cookie() {
+        let response = Response::new(StatusCode::Ok)
+            .with_cookie(Cookie::new("a", "1"))
+            .with_cookie(Cookie::new("b", "2").path("/"));
+
+        assert_eq!(
+            response.headers.get_all("set-cookie").collect::<Vec<_>>(),
+            vec!["a=1", "b=2; Path=/"]
+        );
+    }
+}
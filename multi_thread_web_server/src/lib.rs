@@ -0,0 +1,14 @@
+pub mod access_log;
+pub mod config;
+pub mod http;
+pub mod logger;
+pub mod metrics;
+pub mod middleware;
+pub mod proxy;
+pub mod router;
+pub mod server;
+pub mod static_files;
+pub mod template;
+pub mod timer;
+pub mod tls;
+pub mod websocket;
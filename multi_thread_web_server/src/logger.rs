@@ -0,0 +1,38 @@
+use crate::http::date;
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+use std::{io::Write, time::SystemTime};
+
+/// Writes the server's diagnostics to stderr, one line per message.
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = format!(
+            "[{} {:<5} {}] {}\n",
+            date::format(SystemTime::now()),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        // Written in one go so lines from different workers don't interleave.
+        std::io::stderr().write_all(line.as_bytes()).ok();
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs the logger for the whole process, messages less severe than `level` are dropped.
+/// Fails if a logger was already installed.
+pub fn init(level: LevelFilter) -> Result<(), SetLoggerError> {
+    log::set_logger(&Logger)?;
+    log::set_max_level(level);
+    Ok(())
+}
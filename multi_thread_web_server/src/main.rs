@@ -1,120 +1,135 @@
-use multi_thread_web_server_pool::WorkerPool;
-use std::{
-    fs,
-    io::{prelude::*, BufReader, Write},
-    net::TcpListener,
-    net::TcpStream,
-    thread,
-    time::Duration,
-};
-
-const HOST: &'static str = "127.0.0.1";
-const PORT: &'static str = "8000";
-const METHOD: usize = 0;
-const PATH: usize = 1;
-const PROTOCOL: usize = 2;
-
-enum Method {
-    GET,
-    POST,
-}
-
-enum Route {
-    Home,
-    Sleep,
-    NotFound,
-}
-
-fn route(request: &Vec<String>) -> Route {
-    let route_line = request.into_iter().next().unwrap();
-    let route: Vec<_> = route_line.split(' ').collect();
-    let path = route[PATH];
-
-    match path {
-        "/" => Route::Home,
-        "/sleep" => Route::Sleep,
-        _ => Route::NotFound,
+use multi_thread_web_server::access_log::AccessLog;
+use multi_thread_web_server::config::ServerConfig;
+use multi_thread_web_server::http::{Deferred, Response, StatusCode};
+use multi_thread_web_server::logger;
+use multi_thread_web_server::middleware::{Compression, RequestId};
+use multi_thread_web_server::proxy::Proxy;
+use multi_thread_web_server::router::Router;
+use multi_thread_web_server::server::Server;
+use multi_thread_web_server::static_files::StaticFiles;
+use multi_thread_web_server::template::Templates;
+use multi_thread_web_server::tls::Tls;
+use serde_json::json;
+use std::{env, net::TcpListener, path::Path, process, sync::Arc, time::Duration};
+
+/// The routes of a host serving its static files from `document_root`.
+fn routes(config: &ServerConfig, document_root: &Path) -> Router {
+    let files = StaticFiles::new(document_root);
+    // Compiled once, and again whenever they are edited in debug builds.
+    let templates = Arc::new(Templates::new("src"));
+    let workers = config.workers;
+    let mut router = Router::new();
+    for route in &config.proxy {
+        match Proxy::new(&route.upstreams) {
+            Ok(proxy) => {
+                router.proxy(&route.prefix, proxy);
+            }
+            Err(e) => {
+                eprintln!("Failed to resolve the upstreams of {}: {}", route.prefix, e);
+                process::exit(2);
+            }
+        }
     }
+    let index = Arc::clone(&templates);
+    let heavy_task = Arc::clone(&templates);
+    router
+        .get("/", move |_, _| {
+            index.response(StatusCode::Ok, "index.html", &json!({ "workers": workers }))
+        })
+        .get("/sleep", move |_, _| {
+            // Waits on the server's timer rather than on a worker.
+            let templates = Arc::clone(&heavy_task);
+            Response::deferred(Deferred::after(Duration::from_secs(10), move || {
+                templates.response(StatusCode::Ok, "heavy-task.html", &json!({ "seconds": 10 }))
+            }))
+        })
+        .get("/static/*path", move |request, params| {
+            files.serve(request, params.raw("path").unwrap_or(""))
+        })
+        .not_found(move |request, _| {
+            templates.response(
+                StatusCode::NotFound,
+                "404.html",
+                &json!({ "path": request.path }),
+            )
+        });
+    router
 }
 
-fn method(request: &Vec<String>) -> Method {
-    let route_line = request.into_iter().next().unwrap();
-    let route: Vec<_> = route_line.split(' ').collect();
-    let method = route[METHOD];
-
-    if method == "GET" {
-        return Method::GET;
+fn bind(address: &str) -> TcpListener {
+    match TcpListener::bind(address) {
+        Ok(listener) => {
+            log::info!("Listening on {}.", address);
+            listener
+        }
+        Err(e) => {
+            panic!("Failed to bind to address {}: {}", address, e);
+        }
     }
-
-    Method::POST
 }
 
 fn main() {
-    let pool = WorkerPool::new(4);
-    let address = String::from(HOST) + ":" + PORT;
-    let listener = match TcpListener::bind(address) {
-        Ok(listener) => listener,
+    if env::args().any(|arg| arg == "--help" || arg == "-h") {
+        print!("{}", ServerConfig::usage());
+        return;
+    }
+
+    let config = match ServerConfig::load() {
+        Ok(config) => config,
         Err(e) => {
-            panic!("Failed to bind to address: {}", e);
+            eprintln!("Invalid configuration: {}", e);
+            process::exit(2);
         }
     };
+    logger::init(config.log_level).expect("no logger is installed yet");
 
-    // Commenting out the take(2) will make the server run forever.
-    // For our purposes implementing the multi threaded web server, we'll remove the sender and main thread join mechanincs to keep it working as intended.
-    for stream in listener.incoming()
-    /*.take(2)*/
-    {
-        let mut stream = match stream {
-            Ok(stream) => stream,
+    let mut server = Server::from_config(routes(&config, &config.document_root), &config)
+        .middleware(RequestId::new());
+    for host in &config.virtual_hosts {
+        server = server.virtual_host(&host.name, routes(&config, &host.document_root));
+    }
+    if config.compression {
+        server = server.middleware(Compression::new().min_size(config.compression_min_size as u64));
+    }
+    if let Some(path) = &config.access_log {
+        match AccessLog::open(path, config.access_log_format) {
+            Ok(access_log) => {
+                let access_log = match config.access_log_max_size {
+                    0 => access_log,
+                    max_size => access_log.rotate(max_size as u64, config.access_log_max_files),
+                };
+                server = server.access_log(access_log);
+            }
             Err(e) => {
-                panic!("Failed to establish a connection: {}", e);
+                eprintln!("Failed to open the access log {}: {}", path.display(), e);
+                process::exit(2);
             }
-        };
-
-        pool.execute(move |id: usize| {
-            let request = handle_connection(&mut stream);
-            let method = method(&request);
-            let route = route(&request);
-            let status = match method {
-                Method::GET => (200, "OK"),
-                _ => (404, "NOT FOUND"),
-            };
-            let (code, msg) = status;
-
-            let page = match (method, route) {
-                (Method::GET, Route::Home) => fs::read_to_string("src/index.html").unwrap(),
-                (Method::GET, Route::Sleep) => {
-                    thread::sleep(Duration::from_secs(10));
-                    fs::read_to_string("src/heavy-task.html").unwrap()
-                }
-                _ => fs::read_to_string("src/404.html").unwrap(),
-            };
-            let length = page.len();
-
-            stream
-                .write_fmt(format_args!(
-                    "HTTP/1.1 {} {}\r\nContent-Length: {}\r\n\r\n{}",
-                    code, msg, length, page
-                ))
-                .unwrap();
-
-            println!("Connection established!");
-            println!("Worker {id} finished task.");
-            Ok(())
-        });
+        }
+    }
+    if let (Some(cert), Some(key)) = (&config.tls_cert, &config.tls_key) {
+        match Tls::from_pem_files(cert, key) {
+            Ok(tls) => server = server.tls(tls),
+            Err(e) => {
+                eprintln!("Failed to load the TLS certificate: {}", e);
+                process::exit(2);
+            }
+        }
     }
+    let listener = bind(&config.address());
+    let tls_listener = config.tls_address().map(|address| bind(&address));
 
-    println!("Shutting down.");
-}
+    #[cfg(unix)]
+    if let Err(e) = server.shutdown_handle().on_signals() {
+        log::warn!("Failed to listen for shutdown signals: {}", e);
+    }
 
-fn handle_connection(stream: &mut TcpStream) -> Vec<String> {
-    let buffer = BufReader::new(stream);
-    let request: Vec<_> = buffer
-        .lines()
-        .map(|r| r.unwrap())
-        .take_while(|line| !line.is_empty())
-        .collect();
+    let report = match tls_listener {
+        Some(tls_listener) => server.run_http_and_https(listener, tls_listener),
+        None => server.run(listener),
+    };
 
-    // println!("Request: {:?}", request);
-    request
+    log::info!(
+        "Shutting down, {} connection(s) abandoned.",
+        report.abandoned
+    );
 }
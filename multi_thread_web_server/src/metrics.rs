@@ -0,0 +1,241 @@
+use crate::http::{Method, StatusCode};
+use multi_thread_web_server_pool::WorkerPool;
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Counts the requests answered and how long they took, and renders them in the Prometheus text
+/// format along with what the worker pool is up to.
+///
+/// Requests are labelled with the pattern of the route they matched (`/users/:id`, not
+/// `/users/42`) so the number of series stays bounded, the ones no route matched are labelled
+/// `unmatched`.
+pub struct Metrics {
+    started: Instant,
+    recorded: Mutex<Recorded>,
+}
+
+#[derive(Default)]
+struct Recorded {
+    /// By route, method and status.
+    requests: BTreeMap<(String, String, u16), u64>,
+    /// By route.
+    latencies: BTreeMap<String, Histogram>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// How many observations fell in each bucket, not cumulated.
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if let Some(bucket) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            started: Instant::now(),
+            recorded: Mutex::new(Recorded::default()),
+        }
+    }
+
+    /// Records a request to `route` answered with `status` after `latency`.
+    pub fn record(&self, route: &str, method: &Method, status: StatusCode, latency: Duration) {
+        let mut recorded = self.recorded.lock().unwrap();
+        *recorded
+            .requests
+            .entry((
+                String::from(route),
+                String::from(method.as_str()),
+                status.code(),
+            ))
+            .or_default() += 1;
+        match recorded.latencies.get_mut(route) {
+            Some(histogram) => histogram.observe(latency.as_secs_f64()),
+            None => {
+                let mut histogram = Histogram::default();
+                histogram.observe(latency.as_secs_f64());
+                recorded.latencies.insert(String::from(route), histogram);
+            }
+        }
+    }
+
+    /// Everything recorded so far, plus the state of `pool` unless it is already gone.
+    pub fn render(&self, pool: Option<&WorkerPool>) -> String {
+        let mut out = String::new();
+        let recorded = self.recorded.lock().unwrap();
+
+        out.push_str(
+            "# HELP http_requests_total Requests answered, by route, method and status.\n",
+        );
+        out.push_str("# TYPE http_requests_total counter\n");
+        for ((route, method, status), count) in &recorded.requests {
+            writeln!(
+                out,
+                "http_requests_total{{route=\"{}\",method=\"{}\",status=\"{}\"}} {}",
+                escape(route),
+                escape(method),
+                status,
+                count
+            )
+            .unwrap();
+        }
+
+        out.push_str(
+            "# HELP http_request_duration_seconds Time from a request being read to its response being written.\n",
+        );
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for (route, histogram) in &recorded.latencies {
+            let route = escape(route);
+            let mut cumulated = 0;
+            for (bound, count) in BUCKETS.iter().zip(histogram.buckets) {
+                cumulated += count;
+                writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{route=\"{}\",le=\"{}\"}} {}",
+                    route, bound, cumulated
+                )
+                .unwrap();
+            }
+            writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{route=\"{}\",le=\"+Inf\"}} {}",
+                route, histogram.count
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "http_request_duration_seconds_sum{{route=\"{}\"}} {}",
+                route, histogram.sum
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "http_request_duration_seconds_count{{route=\"{}\"}} {}",
+                route, histogram.count
+            )
+            .unwrap();
+        }
+        drop(recorded);
+
+        if let Some(pool) = pool {
+            let gauges = [
+                (
+                    "worker_pool_workers",
+                    "Workers still running.",
+                    pool.alive(),
+                ),
+                (
+                    "worker_pool_busy_workers",
+                    "Workers running a task.",
+                    pool.busy(),
+                ),
+                (
+                    "worker_pool_queued_tasks",
+                    "Tasks waiting for a free worker.",
+                    pool.queued(),
+                ),
+            ];
+            for (name, help, value) in gauges {
+                writeln!(out, "# HELP {} {}", name, help).unwrap();
+                writeln!(out, "# TYPE {} gauge", name).unwrap();
+                writeln!(out, "{} {}", name, value).unwrap();
+            }
+
+            out.push_str("# HELP worker_pool_panics_total Tasks that panicked.\n");
+            out.push_str("# TYPE worker_pool_panics_total counter\n");
+            writeln!(out, "worker_pool_panics_total {}", pool.panicked()).unwrap();
+        }
+
+        out.push_str("# HELP process_uptime_seconds Time since the server started.\n");
+        out.push_str("# TYPE process_uptime_seconds gauge\n");
+        writeln!(
+            out,
+            "process_uptime_seconds {}",
+            self.started.elapsed().as_secs_f64()
+        )
+        .unwrap();
+
+        out
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+/// Escapes a label value: backslashes, double quotes and line feeds.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_renders_counters_and_cumulative_histograms() {
+        let metrics = Metrics::new();
+        let ms = Duration::from_millis;
+        metrics.record("/users/:id", &Method::Get, StatusCode::Ok, ms(3));
+        metrics.record("/users/:id", &Method::Get, StatusCode::Ok, ms(40));
+        metrics.record("/users/:id", &Method::Get, StatusCode::NotFound, ms(20_000));
+        metrics.record("unmatched", &Method::Post, StatusCode::NotFound, ms(1));
+
+        let pool = WorkerPool::new(2);
+        let text = metrics.render(Some(&pool));
+        let lines: Vec<_> = text.lines().collect();
+        let has = |line: &str| lines.contains(&line);
+
+        assert!(has(
+            r#"http_requests_total{route="/users/:id",method="GET",status="200"} 2"#
+        ));
+        assert!(has(
+            r#"http_requests_total{route="unmatched",method="POST",status="404"} 1"#
+        ));
+        assert!(has(
+            r#"http_request_duration_seconds_bucket{route="/users/:id",le="0.005"} 1"#
+        ));
+        assert!(has(
+            r#"http_request_duration_seconds_bucket{route="/users/:id",le="0.05"} 2"#
+        ));
+        assert!(has(
+            r#"http_request_duration_seconds_bucket{route="/users/:id",le="10"} 2"#
+        ));
+        assert!(has(
+            r#"http_request_duration_seconds_bucket{route="/users/:id",le="+Inf"} 3"#
+        ));
+        assert!(has(
+            r#"http_request_duration_seconds_count{route="/users/:id"} 3"#
+        ));
+        assert!(has("worker_pool_workers 2"));
+        assert!(has("worker_pool_queued_tasks 0"));
+        assert!(has("worker_pool_panics_total 0"));
+
+        assert!(!metrics.render(None).contains("worker_pool"));
+        assert_eq!(escape("a\"b\\c"), r#"a\"b\\c"#);
+    }
+}
@@ -0,0 +1,180 @@
+use crate::http::{Request, Response};
+
+mod compression;
+mod cors;
+mod request_id;
+
+pub use compression::Compression;
+pub use cors::Cors;
+pub use request_id::RequestId;
+
+/// Code that runs around every route handler, for concerns that don't belong to any single route
+/// (request ids, CORS, compression, authentication...).
+///
+/// Both hooks do nothing by default, a middleware only implements the ones it needs.
+pub trait Middleware: Send + Sync + 'static {
+    /// Runs before the handler. Returning a response skips the handler and the `before` hook of
+    /// every middleware added after this one, e.g. to refuse an unauthenticated request.
+    fn before(&self, _request: &mut Request) -> Option<Response> {
+        None
+    }
+
+    /// Runs once the response is ready, whether it came from the handler or from a `before` hook.
+    fn after(&self, _request: &Request, _response: &mut Response) {}
+}
+
+/// Middlewares in the order they were added.
+///
+/// `before` hooks run in that order and `after` hooks in reverse, so the first middleware added
+/// is the outermost one: it sees the request first and the response last. When a `before` hook
+/// answers early, only the middlewares that already ran their `before` hook run their `after` one.
+///
+/// ```
+/// use multi_thread_web_server::middleware::{Chain, Cors, RequestId};
+///
+/// let mut chain = Chain::new();
+/// chain.add(RequestId::new()).add(Cors::any());
+/// ```
+#[derive(Default)]
+pub struct Chain {
+    middlewares: Vec<Box<dyn Middleware>>,
+}
+
+impl Chain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add<M: Middleware>(&mut self, middleware: M) -> &mut Self {
+        self.middlewares.push(Box::new(middleware));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.middlewares.is_empty()
+    }
+
+    /// Runs `request` through the middlewares and `handler`.
+    pub fn handle<F>(&self, request: &mut Request, handler: F) -> Response
+    where
+        F: FnOnce(&Request) -> Response,
+    {
+        let (ran, early) = self.before(request);
+        let mut response = early.unwrap_or_else(|| handler(request));
+        self.after(ran, request, &mut response);
+        response
+    }
+
+    /// Runs the `before` hooks until one answers. Returns how many ran, to be passed to `after`
+    /// along with the response, and the early answer if any.
+    pub(crate) fn before(&self, request: &mut Request) -> (usize, Option<Response>) {
+        let mut ran = 0;
+        for middleware in &self.middlewares {
+            ran += 1;
+            if let Some(response) = middleware.before(request) {
+                return (ran, Some(response));
+            }
+        }
+        (ran, None)
+    }
+
+    /// Runs the `after` hooks of the first `ran` middlewares, in reverse.
+    pub(crate) fn after(&self, ran: usize, request: &Request, response: &mut Response) {
+        for middleware in self.middlewares[..ran].iter().rev() {
+            middleware.after(request, response);
+        }
+    }
+}
+
+/// Adds `name` to the `Vary` header unless it is already listed.
+pub(crate) fn add_vary(response: &mut Response, name: &str) {
+    if !response.headers.has_token("Vary", name) && !response.headers.has_token("Vary", "*") {
+        response.headers.append("Vary", name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::StatusCode;
+    use std::sync::{Arc, Mutex};
+
+    /// Records its hook calls, and answers early when `stop` is set.
+    struct Probe {
+        name: &'static str,
+        stop: bool,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Middleware for Probe {
+        fn before(&self, _request: &mut Request) -> Option<Response> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("before {}", self.name));
+            self.stop
+                .then(|| Response::text(StatusCode::Unauthorized, self.name))
+        }
+
+        fn after(&self, _request: &Request, response: &mut Response) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("after {}", self.name));
+            response.headers.append("X-Seen-By", self.name);
+        }
+    }
+
+    fn chain(stops: &[(&'static str, bool)], calls: &Arc<Mutex<Vec<String>>>) -> Chain {
+        let mut chain = Chain::new();
+        for (name, stop) in stops {
+            chain.add(Probe {
+                name,
+                stop: *stop,
+                calls: Arc::clone(calls),
+            });
+        }
+        chain
+    }
+
+    fn request() -> Request {
+        Request::parse(&mut "GET / HTTP/1.1\r\n\r\n".as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn it_runs_before_hooks_in_order_and_after_hooks_in_reverse() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let chain = chain(&[("outer", false), ("inner", false)], &calls);
+
+        let response = chain.handle(&mut request(), |_| {
+            Response::text(StatusCode::Ok, "handled")
+        });
+
+        assert_eq!(response.status, StatusCode::Ok);
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec!["before outer", "before inner", "after inner", "after outer"]
+        );
+        assert_eq!(
+            response.headers.get_all("X-Seen-By").collect::<Vec<_>>(),
+            vec!["inner", "outer"]
+        );
+    }
+
+    #[test]
+    fn it_skips_the_handler_and_inner_middlewares_when_answered_early() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let chain = chain(
+            &[("outer", false), ("auth", true), ("inner", false)],
+            &calls,
+        );
+
+        let response = chain.handle(&mut request(), |_| unreachable!());
+
+        assert_eq!(response.status, StatusCode::Unauthorized);
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec!["before outer", "before auth", "after auth", "after outer"]
+        );
+    }
+}
@@ -0,0 +1,300 @@
+use super::{add_vary, Middleware};
+use crate::http::{Body, Method, Request, Response};
+use flate2::{
+    read::{GzEncoder, ZlibEncoder},
+    Compression as Level,
+};
+use std::io::{self, Read, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+}
+
+/// Compresses responses with gzip or deflate when the client accepts it.
+///
+/// Only textual content types are compressed (`text/*`, JSON, JavaScript, XML and SVG), images,
+/// archives and the like are already compressed and would only cost CPU. Bodies smaller than the
+/// minimum size are left alone as well, the compression headers would outweigh the savings.
+/// Streamed bodies are compressed as they are sent, and sent chunked.
+#[derive(Debug, Clone)]
+pub struct Compression {
+    min_size: u64,
+    level: Level,
+}
+
+impl Compression {
+    pub fn new() -> Self {
+        Compression {
+            min_size: 1024,
+            level: Level::default(),
+        }
+    }
+
+    /// Bodies smaller than `min_size` bytes are sent as is, 1 KiB by default.
+    pub fn min_size(mut self, min_size: u64) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// From 0 (fastest) to 9 (smallest), 6 by default.
+    pub fn level(mut self, level: u32) -> Self {
+        self.level = Level::new(level.min(9));
+        self
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::new()
+    }
+}
+
+impl Middleware for Compression {
+    fn after(&self, request: &Request, response: &mut Response) {
+        if !response.status.allows_body()
+            || response.headers.contains("Content-Encoding")
+            || response.headers.contains("Content-Range")
+            || response.headers.has_token("Cache-Control", "no-transform")
+            || !response
+                .headers
+                .get("Content-Type")
+                .is_some_and(is_compressible)
+        {
+            return;
+        }
+
+        // Whether or not this client gets it compressed, another one might, so caches must keep
+        // both apart.
+        add_vary(response, "Accept-Encoding");
+
+        // HEAD responses already lost their body, compressing it now would advertise the wrong
+        // length.
+        if request.method == Method::Head
+            || response.body.len().is_some_and(|len| len < self.min_size)
+        {
+            return;
+        }
+
+        let encoding = match request.headers.get("Accept-Encoding").and_then(negotiate) {
+            Some(encoding) => encoding,
+            None => return,
+        };
+
+        let body = match std::mem::replace(&mut response.body, Body::Bytes(Vec::new())) {
+            Body::Bytes(bytes) => match compress(&bytes, encoding, self.level) {
+                Ok(compressed) if compressed.len() < bytes.len() => Body::Bytes(compressed),
+                // Incompressible after all, keep the original.
+                _ => {
+                    response.body = Body::Bytes(bytes);
+                    return;
+                }
+            },
+            Body::Stream { reader, length } => {
+                let reader = match length {
+                    Some(length) => Box::new(reader.take(length)) as Box<dyn Read + Send>,
+                    None => reader,
+                };
+                let reader: Box<dyn Read + Send> = match encoding {
+                    Encoding::Gzip => Box::new(GzEncoder::new(reader, self.level)),
+                    Encoding::Deflate => Box::new(ZlibEncoder::new(reader, self.level)),
+                };
+                Body::Stream {
+                    reader,
+                    length: None,
+                }
+            }
+        };
+        response.body = body;
+
+        response.headers.remove("Content-Length");
+        response.headers.insert("Content-Encoding", encoding.name());
+        // The compressed bytes differ from the ones the tag was computed for, it can only be a
+        // weak validator now. Conditional requests use the weak comparison so they still work.
+        if let Some(etag) = response.headers.get("ETag") {
+            if !etag.starts_with("W/") {
+                let weak = format!("W/{}", etag);
+                response.headers.insert("ETag", &weak);
+            }
+        }
+    }
+}
+
+fn is_compressible(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(
+            essence.as_str(),
+            "application/json"
+                | "application/javascript"
+                | "application/xml"
+                | "application/wasm"
+                | "image/svg+xml"
+        )
+}
+
+/// Picks the encoding the client prefers from an `Accept-Encoding` value, gzip winning ties.
+/// Returns `None` when the client accepts neither, or prefers the uncompressed body.
+fn negotiate(accept: &str) -> Option<Encoding> {
+    let mut gzip = None;
+    let mut deflate = None;
+    let mut identity = None;
+    let mut any = None;
+
+    for item in accept.split(',') {
+        let mut parts = item.split(';');
+        let coding = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        let quality = parts
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .filter_map(|q| q.trim().parse::<f32>().ok())
+            .next()
+            .unwrap_or(1.0);
+
+        match coding.as_str() {
+            "gzip" | "x-gzip" => gzip = Some(quality),
+            "deflate" => deflate = Some(quality),
+            "identity" => identity = Some(quality),
+            "*" => any = Some(quality),
+            _ => {}
+        }
+    }
+
+    let gzip = gzip.or(any).unwrap_or(0.0);
+    let deflate = deflate.or(any).unwrap_or(0.0);
+    let identity = identity.or(any).unwrap_or(1.0);
+
+    let (encoding, quality) = if gzip >= deflate {
+        (Encoding::Gzip, gzip)
+    } else {
+        (Encoding::Deflate, deflate)
+    };
+
+    (quality > 0.0 && quality >= identity).then_some(encoding)
+}
+
+fn compress(bytes: &[u8], encoding: Encoding, level: Level) -> io::Result<Vec<u8>> {
+    match encoding {
+        Encoding::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), level);
+            encoder.write_all(bytes)?;
+            encoder.finish()
+        }
+        Encoding::Deflate => {
+            let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), level);
+            encoder.write_all(bytes)?;
+            encoder.finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::StatusCode;
+    use crate::middleware::Chain;
+    use flate2::read::{GzDecoder, ZlibDecoder};
+
+    fn handle(raw: &str, response: Response) -> Response {
+        let mut chain = Chain::new();
+        chain.add(Compression::new().min_size(16));
+        let mut request = Request::parse(&mut raw.as_bytes()).unwrap();
+        chain.handle(&mut request, |_| response)
+    }
+
+    fn page() -> String {
+        "<p>Hello, compression!</p>\n".repeat(20)
+    }
+
+    #[test]
+    fn it_compresses_text_for_clients_that_accept_it() {
+        let response = handle(
+            "GET / HTTP/1.1\r\nAccept-Encoding: deflate, gzip\r\n\r\n",
+            Response::html(StatusCode::Ok, page()).with_header("ETag", "\"1-2\""),
+        );
+        assert_eq!(response.headers.get("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.headers.get("Vary"), Some("Accept-Encoding"));
+        assert_eq!(response.headers.get("ETag"), Some("W/\"1-2\""));
+
+        let mut decoded = String::new();
+        GzDecoder::new(response.body.as_bytes().unwrap())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, page());
+
+        let response = handle(
+            "GET / HTTP/1.1\r\nAccept-Encoding: gzip;q=0.5, deflate\r\n\r\n",
+            Response::new(StatusCode::Ok)
+                .with_header("Content-Type", "application/json")
+                .with_chunked_stream(io::Cursor::new(page())),
+        );
+        assert_eq!(response.headers.get("Content-Encoding"), Some("deflate"));
+        let mut decoded = String::new();
+        ZlibDecoder::new(response.body.into_bytes().unwrap().as_slice())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, page());
+    }
+
+    #[test]
+    fn it_leaves_other_responses_alone() {
+        let plain = |raw: &str, response: Response| {
+            let response = handle(raw, response);
+            assert!(!response.headers.contains("Content-Encoding"), "{}", raw);
+            response
+        };
+
+        let response = plain(
+            "GET / HTTP/1.1\r\n\r\n",
+            Response::html(StatusCode::Ok, page()),
+        );
+        assert_eq!(response.headers.get("Vary"), Some("Accept-Encoding"));
+
+        plain(
+            "GET / HTTP/1.1\r\nAccept-Encoding: gzip;q=0, identity\r\n\r\n",
+            Response::html(StatusCode::Ok, page()),
+        );
+        plain(
+            "GET / HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n",
+            Response::html(StatusCode::Ok, "tiny"),
+        );
+        let response = plain(
+            "GET / HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n",
+            Response::new(StatusCode::Ok)
+                .with_header("Content-Type", "image/png")
+                .with_body(page()),
+        );
+        assert!(!response.headers.contains("Vary"));
+        plain(
+            "HEAD / HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n",
+            Response::html(StatusCode::Ok, page()).without_body(),
+        );
+    }
+
+    #[test]
+    fn it_negotiates_accept_encoding() {
+        assert_eq!(negotiate("gzip, deflate, br"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("deflate"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("*"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("*;q=0, deflate;q=0.1"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("br"), None);
+        assert_eq!(negotiate("gzip;q=0.5, identity"), None);
+    }
+}
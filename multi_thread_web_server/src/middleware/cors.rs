@@ -0,0 +1,260 @@
+use super::{add_vary, Middleware};
+use crate::http::{Method, Request, Response, StatusCode};
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Origins {
+    Any,
+    List(Vec<String>),
+}
+
+/// Lets pages served from other origins call the server from a browser.
+///
+/// Preflight requests (`OPTIONS` with `Access-Control-Request-Method`) from an allowed origin are
+/// answered right away, the other requests from an allowed origin go through and get the
+/// `Access-Control-Allow-*` headers added to their response. Requests from other origins are left
+/// alone, the browser is what refuses them.
+#[derive(Debug, Clone)]
+pub struct Cors {
+    origins: Origins,
+    methods: Vec<Method>,
+    headers: Vec<String>,
+    exposed_headers: Vec<String>,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+impl Cors {
+    /// Allows every origin.
+    pub fn any() -> Self {
+        Cors::new(Origins::Any)
+    }
+
+    /// Allows the given origins only, e.g. `https://example.com`.
+    pub fn allow_origins(origins: &[&str]) -> Self {
+        Cors::new(Origins::List(
+            origins.iter().map(|origin| String::from(*origin)).collect(),
+        ))
+    }
+
+    fn new(origins: Origins) -> Self {
+        Cors {
+            origins,
+            methods: vec![Method::Get, Method::Head, Method::Post],
+            headers: Vec::new(),
+            exposed_headers: Vec::new(),
+            credentials: false,
+            max_age: None,
+        }
+    }
+
+    /// The methods a preflight request may ask for, `GET`, `HEAD` and `POST` by default.
+    pub fn methods(mut self, methods: &[Method]) -> Self {
+        self.methods = methods.to_vec();
+        self
+    }
+
+    /// The request headers a preflight request may ask for.
+    pub fn headers(mut self, headers: &[&str]) -> Self {
+        self.headers = headers.iter().map(|header| String::from(*header)).collect();
+        self
+    }
+
+    /// The response headers scripts are allowed to read.
+    pub fn expose_headers(mut self, headers: &[&str]) -> Self {
+        self.exposed_headers = headers.iter().map(|header| String::from(*header)).collect();
+        self
+    }
+
+    /// Whether requests may carry cookies. The allowed origin is then always named explicitly,
+    /// browsers refuse `*` for such requests.
+    ///
+    /// Panics when every origin is allowed, any page could then make requests with the user's
+    /// cookies and read the responses. Use `allow_origins` instead.
+    pub fn credentials(mut self, credentials: bool) -> Self {
+        assert!(
+            !(credentials && self.origins == Origins::Any),
+            "credentials can't be allowed for every origin, list the allowed origins"
+        );
+        self.credentials = credentials;
+        self
+    }
+
+    /// How long browsers may cache a preflight response.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    fn allows(&self, origin: &str) -> bool {
+        match &self.origins {
+            Origins::Any => true,
+            Origins::List(origins) => origins.iter().any(|allowed| allowed == origin),
+        }
+    }
+
+    fn allow_origin(&self, origin: &str, response: &mut Response) {
+        if self.origins == Origins::Any && !self.credentials {
+            response.headers.insert("Access-Control-Allow-Origin", "*");
+        } else {
+            response
+                .headers
+                .insert("Access-Control-Allow-Origin", origin);
+            // The response depends on who asked, caches must not serve it to other origins.
+            add_vary(response, "Origin");
+        }
+        if self.credentials {
+            response
+                .headers
+                .insert("Access-Control-Allow-Credentials", "true");
+        }
+    }
+}
+
+impl Middleware for Cors {
+    fn before(&self, request: &mut Request) -> Option<Response> {
+        let origin = request.headers.get("Origin")?;
+        let requested = request.headers.get("Access-Control-Request-Method")?;
+        if request.method != Method::Options || !self.allows(origin) {
+            return None;
+        }
+
+        let mut response = Response::new(StatusCode::NoContent);
+        self.allow_origin(origin, &mut response);
+
+        // Asking for a method we don't allow gets a response without the allow headers, which the
+        // browser treats as a refusal.
+        if Method::from_token(requested).is_some_and(|method| self.methods.contains(&method)) {
+            let methods: Vec<_> = self.methods.iter().map(Method::as_str).collect();
+            response
+                .headers
+                .insert("Access-Control-Allow-Methods", &methods.join(", "));
+            if !self.headers.is_empty() {
+                response
+                    .headers
+                    .insert("Access-Control-Allow-Headers", &self.headers.join(", "));
+            }
+            if let Some(max_age) = self.max_age {
+                response
+                    .headers
+                    .insert("Access-Control-Max-Age", &max_age.as_secs().to_string());
+            }
+        }
+
+        Some(response)
+    }
+
+    fn after(&self, request: &Request, response: &mut Response) {
+        let origin = match request.headers.get("Origin") {
+            Some(origin) if self.allows(origin) => origin,
+            _ => {
+                // Other origins would get the allow headers, caches must not serve them this one.
+                if let Origins::List(_) = self.origins {
+                    add_vary(response, "Origin");
+                }
+                return;
+            }
+        };
+        if response.headers.contains("Access-Control-Allow-Origin") {
+            // Already answered as a preflight.
+            return;
+        }
+
+        self.allow_origin(origin, response);
+        if !self.exposed_headers.is_empty() {
+            response.headers.insert(
+                "Access-Control-Expose-Headers",
+                &self.exposed_headers.join(", "),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::Chain;
+
+    fn handle(cors: Cors, raw: &str) -> Response {
+        let mut chain = Chain::new();
+        chain.add(cors);
+        let mut request = Request::parse(&mut raw.as_bytes()).unwrap();
+        chain.handle(&mut request, |_| Response::text(StatusCode::Ok, "handled"))
+    }
+
+    #[test]
+    fn it_answers_preflight_requests_from_allowed_origins() {
+        let cors = || {
+            Cors::allow_origins(&["https://app.example"])
+                .methods(&[Method::Get, Method::Put])
+                .headers(&["Content-Type"])
+                .max_age(Duration::from_secs(600))
+        };
+        let preflight = |origin: &str, method: &str| {
+            format!(
+                "OPTIONS /users HTTP/1.1\r\nOrigin: {}\r\nAccess-Control-Request-Method: {}\r\n\r\n",
+                origin, method
+            )
+        };
+
+        let response = handle(cors(), &preflight("https://app.example", "PUT"));
+        assert_eq!(response.status, StatusCode::NoContent);
+        let header = |name| response.headers.get(name);
+        assert_eq!(
+            header("Access-Control-Allow-Origin"),
+            Some("https://app.example")
+        );
+        assert_eq!(header("Access-Control-Allow-Methods"), Some("GET, PUT"));
+        assert_eq!(header("Access-Control-Allow-Headers"), Some("Content-Type"));
+        assert_eq!(header("Access-Control-Max-Age"), Some("600"));
+        assert_eq!(header("Vary"), Some("Origin"));
+
+        let response = handle(cors(), &preflight("https://app.example", "DELETE"));
+        assert!(!response.headers.contains("Access-Control-Allow-Methods"));
+
+        let response = handle(cors(), &preflight("https://evil.example", "PUT"));
+        assert_eq!(response.status, StatusCode::Ok);
+        assert!(!response.headers.contains("Access-Control-Allow-Origin"));
+        assert_eq!(response.headers.get("Vary"), Some("Origin"));
+    }
+
+    #[test]
+    fn it_adds_headers_to_simple_requests() {
+        let response = handle(
+            Cors::any().expose_headers(&["X-Request-Id"]),
+            "GET / HTTP/1.1\r\nOrigin: https://app.example\r\n\r\n",
+        );
+        assert_eq!(response.body.as_bytes(), Some(&b"handled"[..]));
+        assert_eq!(
+            response.headers.get("Access-Control-Allow-Origin"),
+            Some("*")
+        );
+        assert_eq!(
+            response.headers.get("Access-Control-Expose-Headers"),
+            Some("X-Request-Id")
+        );
+
+        let response = handle(
+            Cors::allow_origins(&["https://app.example"]).credentials(true),
+            "GET / HTTP/1.1\r\nOrigin: https://app.example\r\n\r\n",
+        );
+        assert_eq!(
+            response.headers.get("Access-Control-Allow-Origin"),
+            Some("https://app.example")
+        );
+        assert_eq!(
+            response.headers.get("Access-Control-Allow-Credentials"),
+            Some("true")
+        );
+
+        let response = handle(Cors::any(), "GET / HTTP/1.1\r\n\r\n");
+        assert!(!response.headers.contains("Access-Control-Allow-Origin"));
+        assert!(!response.headers.contains("Vary"));
+    }
+
+    #[test]
+    #[should_panic(expected = "credentials can't be allowed for every origin")]
+    fn it_refuses_credentials_for_every_origin() {
+        let _ = Cors::any().credentials(true);
+    }
+}
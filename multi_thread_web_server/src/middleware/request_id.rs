@@ -0,0 +1,109 @@
+use super::Middleware;
+use crate::http::{Request, Response};
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Tags every request with an id, so its log lines can be found on both sides.
+///
+/// An id sent by the client (or a proxy in front of us) is kept when it looks sane, otherwise a
+/// new one is generated. Either way handlers find it in the request headers and it is echoed in
+/// the response.
+pub struct RequestId {
+    header: String,
+    prefix: String,
+    next: AtomicU64,
+}
+
+/// Ids sent by clients longer than this are replaced.
+const MAX_LENGTH: usize = 128;
+
+impl RequestId {
+    /// Uses the `X-Request-Id` header.
+    pub fn new() -> Self {
+        RequestId::with_header("X-Request-Id")
+    }
+
+    pub fn with_header(header: &str) -> Self {
+        // Ids are unique within a run thanks to the counter, and across restarts thanks to the
+        // start time.
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+
+        RequestId {
+            header: String::from(header),
+            prefix: format!("{:x}", started),
+            next: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Default for RequestId {
+    fn default() -> Self {
+        RequestId::new()
+    }
+}
+
+impl Middleware for RequestId {
+    fn before(&self, request: &mut Request) -> Option<Response> {
+        let sent = request.headers.get(&self.header).filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_LENGTH
+                && id
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
+        });
+
+        if sent.is_none() {
+            let id = self.next.fetch_add(1, Ordering::Relaxed);
+            let id = format!("{}-{:x}", self.prefix, id);
+            request.headers.insert(&self.header, &id);
+        }
+        None
+    }
+
+    fn after(&self, request: &Request, response: &mut Response) {
+        if let Some(id) = request.headers.get(&self.header) {
+            response.headers.insert(&self.header, id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::StatusCode;
+    use crate::middleware::Chain;
+
+    fn handle(chain: &Chain, raw: &str) -> (Request, Response) {
+        let mut request = Request::parse(&mut raw.as_bytes()).unwrap();
+        let response = chain.handle(&mut request, |request| {
+            Response::text(
+                StatusCode::Ok,
+                request.headers.get("X-Request-Id").unwrap_or(""),
+            )
+        });
+        (request, response)
+    }
+
+    #[test]
+    fn it_generates_unique_ids_and_keeps_sane_ones() {
+        let mut chain = Chain::new();
+        chain.add(RequestId::new());
+
+        let (_, first) = handle(&chain, "GET / HTTP/1.1\r\n\r\n");
+        let (_, second) = handle(&chain, "GET / HTTP/1.1\r\n\r\n");
+        let first_id = first.headers.get("X-Request-Id").unwrap();
+        assert_eq!(first.body.as_bytes(), Some(first_id.as_bytes()));
+        assert_ne!(Some(first_id), second.headers.get("X-Request-Id"));
+
+        let (_, kept) = handle(&chain, "GET / HTTP/1.1\r\nX-Request-Id: abc-123\r\n\r\n");
+        assert_eq!(kept.headers.get("X-Request-Id"), Some("abc-123"));
+
+        let (_, replaced) = handle(&chain, "GET / HTTP/1.1\r\nX-Request-Id: <script>\r\n\r\n");
+        assert_ne!(replaced.headers.get("X-Request-Id"), Some("<script>"));
+    }
+}
@@ -0,0 +1,427 @@
+use crate::http::chunked::ChunkedReader;
+use crate::http::request::{self, ParseError};
+use crate::http::{Headers, Method, Request, Response, StatusCode};
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Header fields that only concern one connection, which a proxy must not pass on.
+const HOP_BY_HOP: [&str; 9] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "Proxy-Connection",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+/// The size in bytes of the status line and header fields an upstream may answer with.
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// Forwards requests to upstream servers, taking turns between them, and streams their responses
+/// back. Mounted on a route prefix with `Router::proxy`:
+///
+/// ```no_run
+/// use multi_thread_web_server::proxy::Proxy;
+/// use multi_thread_web_server::router::Router;
+///
+/// let mut router = Router::new();
+/// router.proxy("/api", Proxy::new(&["127.0.0.1:9001", "127.0.0.1:9002"]).unwrap());
+/// ```
+///
+/// Forwarded requests carry the upstream as their `Host`, the original one in `X-Forwarded-Host`,
+/// and the client address appended to `X-Forwarded-For`.
+///
+/// Upstreams are checked passively: one that fails `max_fails` times in a row is left out for
+/// `fail_timeout`, after which it gets requests again. A request is only retried on the next
+/// upstream when the connection couldn't be made at all, as the failed upstream may have acted on
+/// it otherwise. When every upstream is left out they are all tried anyway, as one of them may
+/// well be back.
+pub struct Proxy {
+    upstreams: Vec<Upstream>,
+    next: AtomicUsize,
+    connect_timeout: Duration,
+    timeout: Duration,
+    max_fails: u32,
+    fail_timeout: Duration,
+}
+
+struct Upstream {
+    /// The `host:port` pair as configured, sent as the `Host` of forwarded requests.
+    authority: String,
+    address: SocketAddr,
+    health: Mutex<Health>,
+}
+
+#[derive(Default)]
+struct Health {
+    /// Failures since the last success.
+    failures: u32,
+    down_until: Option<Instant>,
+}
+
+impl Proxy {
+    /// Proxies to `upstreams`, `host:port` pairs that are resolved right away.
+    pub fn new<S: AsRef<str>>(upstreams: &[S]) -> io::Result<Self> {
+        if upstreams.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a proxy needs at least one upstream",
+            ));
+        }
+
+        let upstreams = upstreams
+            .iter()
+            .map(|authority| {
+                let authority = authority.as_ref();
+                let address = authority.to_socket_addrs()?.next().ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("{} resolves to no address", authority),
+                    )
+                })?;
+                Ok(Upstream {
+                    authority: String::from(authority),
+                    address,
+                    health: Mutex::new(Health::default()),
+                })
+            })
+            .collect::<io::Result<_>>()?;
+
+        Ok(Proxy {
+            upstreams,
+            next: AtomicUsize::new(0),
+            connect_timeout: Duration::from_secs(5),
+            timeout: Duration::from_secs(30),
+            max_fails: 1,
+            fail_timeout: Duration::from_secs(10),
+        })
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// How long an upstream may take to accept the request or to send more of its response,
+    /// answered with a 504 when it is exceeded before the response started.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How many failures in a row leave an upstream out, at least one.
+    pub fn max_fails(mut self, max_fails: u32) -> Self {
+        self.max_fails = max_fails.max(1);
+        self
+    }
+
+    /// How long an upstream is left out once it failed `max_fails` times.
+    pub fn fail_timeout(mut self, fail_timeout: Duration) -> Self {
+        self.fail_timeout = fail_timeout;
+        self
+    }
+
+    /// Sends `request` to the next upstream and returns its response, its body still to be read
+    /// from the upstream as it is written to the client. Answers with a 502 or 504 when no
+    /// upstream could.
+    pub fn forward(&self, request: &Request) -> Response {
+        for upstream in self.candidates() {
+            let stream = match TcpStream::connect_timeout(&upstream.address, self.connect_timeout) {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::warn!(
+                        "Failed to connect to upstream {}: {}",
+                        upstream.authority,
+                        e
+                    );
+                    self.failed(upstream);
+                    continue;
+                }
+            };
+
+            return match self.exchange(stream, upstream, request) {
+                Ok(response) => {
+                    *upstream.health.lock().unwrap() = Health::default();
+                    response
+                }
+                Err(e) => {
+                    log::warn!("Upstream {} failed: {}", upstream.authority, e);
+                    self.failed(upstream);
+                    match e.kind() {
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                            Response::text(StatusCode::GatewayTimeout, "Gateway Timeout")
+                        }
+                        _ => Response::text(StatusCode::BadGateway, "Bad Gateway"),
+                    }
+                }
+            };
+        }
+
+        Response::text(StatusCode::BadGateway, "Bad Gateway")
+    }
+
+    /// The upstreams to try in turn, starting with the next one round-robin and leaving out the
+    /// ones that failed recently unless that would leave none.
+    fn candidates(&self) -> Vec<&Upstream> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let in_turn: Vec<_> = (0..self.upstreams.len())
+            .map(|i| &self.upstreams[(start + i) % self.upstreams.len()])
+            .collect();
+
+        let now = Instant::now();
+        let up: Vec<_> = in_turn
+            .iter()
+            .copied()
+            .filter(|upstream| {
+                let health = upstream.health.lock().unwrap();
+                health.down_until.is_none_or(|until| until <= now)
+            })
+            .collect();
+
+        if up.is_empty() {
+            in_turn
+        } else {
+            up
+        }
+    }
+
+    fn failed(&self, upstream: &Upstream) {
+        let mut health = upstream.health.lock().unwrap();
+        health.failures += 1;
+        if health.failures >= self.max_fails {
+            if health.down_until.is_none() {
+                log::warn!(
+                    "Leaving upstream {} out for {:?}.",
+                    upstream.authority,
+                    self.fail_timeout
+                );
+            }
+            health.down_until = Some(Instant::now() + self.fail_timeout);
+        }
+    }
+
+    /// Writes `request` to the upstream and reads the head of its response.
+    fn exchange(
+        &self,
+        stream: TcpStream,
+        upstream: &Upstream,
+        request: &Request,
+    ) -> io::Result<Response> {
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        let headers = forwarded_headers(request, &upstream.authority);
+        let head = format!(
+            "{} {} HTTP/1.1\r\n{}\r\n",
+            request.method.as_str(),
+            request.target,
+            headers
+        );
+        let mut writer = &stream;
+        writer.write_all(head.as_bytes())?;
+        writer.write_all(&request.body)?;
+        writer.flush()?;
+
+        let mut reader = BufReader::new(stream);
+        let (status, received) = read_head(&mut reader)?;
+
+        let chunked = received.has_token("Transfer-Encoding", "chunked");
+        let length = match received.get("Content-Length") {
+            Some(length) => Some(length.trim().parse::<u64>().map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, "invalid Content-Length")
+            })?),
+            None => None,
+        };
+
+        let mut response = Response::new(status);
+        response.headers = without_hop_by_hop(&received);
+        if request.method == Method::Head || !status.allows_body() {
+            // Keeps the Content-Length describing the body that would have been sent.
+            return Ok(response);
+        }
+
+        response.headers.remove("Content-Length");
+        Ok(match (chunked, length) {
+            (true, _) => response.with_chunked_stream(ChunkedReader::new(reader)),
+            (false, Some(length)) => response.with_stream(reader, length),
+            // The body ends when the upstream closes the connection.
+            (false, None) => response.with_chunked_stream(reader),
+        })
+    }
+}
+
+/// The header fields to send upstream for `request`.
+fn forwarded_headers(request: &Request, authority: &str) -> Headers {
+    let mut headers = Headers::new();
+    headers.append("Host", authority);
+
+    for (name, value) in without_hop_by_hop(&request.headers).iter() {
+        let skipped = ["Host", "Content-Length", "X-Forwarded-For"];
+        if !skipped
+            .iter()
+            .any(|skipped| name.eq_ignore_ascii_case(skipped))
+        {
+            headers.append(name, value);
+        }
+    }
+
+    let mut forwarded_for: Vec<String> = request
+        .headers
+        .get_all("X-Forwarded-For")
+        .map(String::from)
+        .collect();
+    if let Some(client) = request.remote_addr {
+        forwarded_for.push(client.ip().to_string());
+    }
+    if !forwarded_for.is_empty() {
+        headers.append("X-Forwarded-For", &forwarded_for.join(", "));
+    }
+    if let Some(host) = request.headers.get("Host") {
+        headers.insert("X-Forwarded-Host", host);
+    }
+
+    // The body was read in full, whatever framing the client used.
+    let had_body = ["Content-Length", "Transfer-Encoding"]
+        .iter()
+        .any(|name| request.headers.contains(name));
+    if had_body || !request.body.is_empty() {
+        headers.append("Content-Length", &request.body.len().to_string());
+    }
+    headers.append("Connection", "close");
+    headers
+}
+
+/// `headers` without the fields that only concern one connection, including those the
+/// `Connection` field names.
+fn without_hop_by_hop(headers: &Headers) -> Headers {
+    let mut kept = Headers::new();
+    for (name, value) in headers.iter() {
+        let hop_by_hop = HOP_BY_HOP
+            .iter()
+            .any(|field| name.eq_ignore_ascii_case(field))
+            || headers.has_token("Connection", name);
+        if !hop_by_hop {
+            kept.append(name, value);
+        }
+    }
+    kept
+}
+
+/// Reads the status line and header fields of an upstream response, skipping interim `1xx`
+/// responses.
+fn read_head<R: BufRead>(reader: &mut R) -> io::Result<(StatusCode, Headers)> {
+    loop {
+        let mut budget = MAX_HEAD_SIZE;
+        let status_line = request::read_line(reader, &mut budget)
+            .map_err(malformed)?
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "upstream closed the connection without responding",
+                )
+            })?;
+
+        let code = status_line
+            .strip_prefix("HTTP/1.")
+            .and_then(|rest| rest.get(2..5))
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("malformed status line {:?}", status_line),
+                )
+            })?;
+
+        let mut headers = Headers::new();
+        loop {
+            let line = request::read_line(reader, &mut budget)
+                .map_err(malformed)?
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "upstream closed the connection in the middle of the headers",
+                    )
+                })?;
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = request::parse_header(&line).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("malformed header field {:?}", line),
+                )
+            })?;
+            headers.append(name, value);
+        }
+
+        if (100..200).contains(&code) {
+            continue;
+        }
+        let status = StatusCode::from_code(code).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown status code {}", code),
+            )
+        })?;
+        return Ok((status, headers));
+    }
+}
+
+fn malformed(e: ParseError) -> io::Error {
+    match e {
+        ParseError::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(text: &str) -> Request {
+        Request::parse(&mut text.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn it_rewrites_the_headers_sent_upstream() {
+        let mut request = request(
+            "POST /api/items HTTP/1.1\r\nHost: example.com\r\nConnection: keep-alive, X-Trace\r\nX-Trace: 1\r\nX-Forwarded-For: 10.0.0.1\r\nAccept: */*\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n",
+        );
+        request.remote_addr = Some("192.168.1.7:51000".parse().unwrap());
+
+        let headers = forwarded_headers(&request, "127.0.0.1:9001");
+        let fields: Vec<_> = headers.iter().collect();
+        assert_eq!(
+            fields,
+            [
+                ("Host", "127.0.0.1:9001"),
+                ("Accept", "*/*"),
+                ("X-Forwarded-For", "10.0.0.1, 192.168.1.7"),
+                ("X-Forwarded-Host", "example.com"),
+                ("Content-Length", "3"),
+                ("Connection", "close"),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_reads_the_head_past_interim_responses() {
+        let mut upstream = &b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 201 Created\r\nLocation: /items/1\r\nContent-Length: 0\r\n\r\n"[..];
+        let (status, headers) = read_head(&mut upstream).unwrap();
+        assert_eq!(status, StatusCode::Created);
+        assert_eq!(headers.get("Location"), Some("/items/1"));
+
+        assert!(read_head(&mut &b"SSH-2.0-OpenSSH\r\n\r\n"[..]).is_err());
+        assert!(read_head(&mut &b""[..]).is_err());
+    }
+}
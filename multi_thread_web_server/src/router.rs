@@ -0,0 +1,519 @@
+use crate::http::{Method, Request, Response, StatusCode};
+use crate::proxy::Proxy;
+use crate::websocket::{self, WebSocket};
+use std::{error::Error, fmt, str::FromStr, sync::Arc};
+
+pub type Handler = Box<dyn Fn(&Request, &Params) -> Response + Send + Sync + 'static>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Static(String),
+    /// `:name`, matches exactly one path segment.
+    Param(String),
+    /// `*name`, matches the rest of the path, slashes included. Only allowed as the last segment.
+    Wildcard(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Pattern {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Pattern {
+        assert!(
+            pattern.starts_with('/'),
+            "route pattern {:?} must start with '/'",
+            pattern
+        );
+
+        let parts: Vec<_> = pattern[1..].split('/').collect();
+        let segments = parts
+            .iter()
+            .enumerate()
+            .map(|(i, part)| {
+                if let Some(name) = part.strip_prefix(':') {
+                    Segment::Param(String::from(name))
+                } else if let Some(name) = part.strip_prefix('*') {
+                    assert!(
+                        i == parts.len() - 1,
+                        "wildcard must be the last segment of {:?}",
+                        pattern
+                    );
+                    Segment::Wildcard(String::from(name))
+                } else {
+                    Segment::Static(String::from(*part))
+                }
+            })
+            .collect();
+
+        Pattern {
+            source: String::from(pattern),
+            segments,
+        }
+    }
+
+    fn matches(&self, path: &str) -> Option<Params> {
+        let mut parts = path.strip_prefix('/')?.split('/');
+        let mut params = Params::default();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Static(expected) => {
+                    if parts.next()? != expected {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    let value = parts.next().filter(|value| !value.is_empty())?;
+                    params.values.push((name.clone(), String::from(value)));
+                }
+                Segment::Wildcard(name) => {
+                    let rest: Vec<_> = parts.by_ref().collect();
+                    params.values.push((name.clone(), rest.join("/")));
+                }
+            }
+        }
+
+        match parts.next() {
+            Some(_) => None,
+            None => Some(params),
+        }
+    }
+}
+
+/// Values captured from the path by `:name` and `*name` segments.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Params {
+    values: Vec<(String, String)>,
+}
+
+impl Params {
+    /// Returns the captured text for `name`.
+    pub fn raw(&self, name: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Parses the captured value for `name` into any `FromStr` type, e.g. `params.get::<u32>("id")`.
+    pub fn get<T: FromStr>(&self, name: &str) -> Result<T, ParamError> {
+        let value = self
+            .raw(name)
+            .ok_or_else(|| ParamError::Missing(String::from(name)))?;
+
+        value.parse().map_err(|_| ParamError::Invalid {
+            name: String::from(name),
+            value: String::from(value),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamError {
+    Missing(String),
+    Invalid { name: String, value: String },
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamError::Missing(name) => write!(f, "missing path parameter {:?}", name),
+            ParamError::Invalid { name, value } => {
+                write!(f, "invalid value {:?} for path parameter {:?}", value, name)
+            }
+        }
+    }
+}
+
+impl Error for ParamError {}
+
+impl From<ParamError> for Response {
+    fn from(e: ParamError) -> Self {
+        Response::text(StatusCode::BadRequest, e.to_string())
+    }
+}
+
+struct Route {
+    method: Method,
+    pattern: Pattern,
+    handler: Handler,
+}
+
+/// Dispatches requests to the handler registered for their method and path.
+/// Routes are tried in the order they were registered and the first one matching both the method
+/// and the path wins.
+///
+/// HEAD requests fall back to the GET handler with the body dropped, and OPTIONS requests are
+/// answered from the registered routes unless a handler is registered for them explicitly.
+pub struct Router {
+    routes: Vec<Route>,
+    not_found: Handler,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Router {
+            routes: Vec::new(),
+            not_found: Box::new(|_, _| Response::text(StatusCode::NotFound, "Not Found")),
+        }
+    }
+
+    /// Registers `handler` for `method` requests whose path matches `pattern`.
+    /// A pattern is made of `/` separated segments that are either literal text, `:name` to capture
+    /// one segment, or a trailing `*name` to capture the rest of the path.
+    ///
+    /// Panics if the pattern does not start with `/` or a wildcard is not the last segment.
+    pub fn route<F>(&mut self, method: Method, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.routes.push(Route {
+            method,
+            pattern: Pattern::parse(pattern),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    pub fn get<F>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    pub fn post<F>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    pub fn put<F>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Put, pattern, handler)
+    }
+
+    pub fn delete<F>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Delete, pattern, handler)
+    }
+
+    /// Registers a `GET` route at `pattern` accepting WebSocket handshakes. `handler` gets the
+    /// upgraded connection along with the handshake request and the captured path parameters,
+    /// and runs on the worker that served the handshake until it returns.
+    pub fn websocket<F>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(WebSocket, &Request, &Params) + Send + Sync + 'static,
+    {
+        let handler = Arc::new(handler);
+        self.get(pattern, move |request, params| {
+            let handler = Arc::clone(&handler);
+            let (handshake, params) = (request.clone(), params.clone());
+            websocket::upgrade(request, move |socket| handler(socket, &handshake, &params))
+        })
+    }
+
+    /// Forwards the requests whose path is `prefix` or below it to `proxy`, with their target left
+    /// as is, whatever their method besides `CONNECT` and `TRACE`.
+    pub fn proxy(&mut self, prefix: &str, proxy: Proxy) -> &mut Self {
+        let proxy = Arc::new(proxy);
+        let pattern = format!("{}/*path", prefix.trim_end_matches('/'));
+        for method in [
+            Method::Get,
+            Method::Post,
+            Method::Put,
+            Method::Delete,
+            Method::Options,
+            Method::Patch,
+        ] {
+            let proxy = Arc::clone(&proxy);
+            self.route(method, &pattern, move |request, _| proxy.forward(request));
+        }
+        self
+    }
+
+    /// Replaces the handler used when no route matches the path.
+    pub fn not_found<F>(&mut self, handler: F) -> &mut Self
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.not_found = Box::new(handler);
+        self
+    }
+
+    /// The pattern of the first route whose path matches `request`, whatever its method, e.g.
+    /// `/users/:id` for `/users/42`.
+    pub fn route_of(&self, request: &Request) -> Option<&str> {
+        self.routes
+            .iter()
+            .find(|route| route.pattern.matches(&request.path).is_some())
+            .map(|route| route.pattern.source.as_str())
+    }
+
+    pub fn handle(&self, request: &Request) -> Response {
+        if !self.implements(&request.method) {
+            return Response::text(StatusCode::NotImplemented, "Not Implemented");
+        }
+
+        let response = self.dispatch(request);
+        if request.method == Method::Head {
+            return response.without_body();
+        }
+
+        response
+    }
+
+    /// Whether the server knows `method` at all: every standard method does, an extension method
+    /// only when some route was registered for it.
+    fn implements(&self, method: &Method) -> bool {
+        match method {
+            Method::Extension(_) => self.routes.iter().any(|route| &route.method == method),
+            _ => true,
+        }
+    }
+
+    fn dispatch(&self, request: &Request) -> Response {
+        if request.method == Method::Options && request.path == "*" {
+            return options(self.routes.iter().map(|route| &route.method).collect());
+        }
+
+        let mut allowed: Vec<&Method> = Vec::new();
+        let mut get = None;
+
+        for route in &self.routes {
+            let params = match route.pattern.matches(&request.path) {
+                Some(params) => params,
+                None => continue,
+            };
+
+            if route.method == request.method {
+                return (route.handler)(request, &params);
+            }
+
+            if route.method == Method::Get && get.is_none() {
+                get = Some((route, params));
+            }
+            allowed.push(&route.method);
+        }
+
+        match (&request.method, get) {
+            (Method::Head, Some((route, params))) => return (route.handler)(request, &params),
+            (Method::Options, _) if !allowed.is_empty() => return options(allowed),
+            _ => {}
+        }
+
+        if allowed.is_empty() {
+            return (self.not_found)(request, &Params::default());
+        }
+
+        // The path exists, just not for this method.
+        Response::text(StatusCode::MethodNotAllowed, "Method Not Allowed")
+            .with_header("Allow", &allow_header(allowed))
+    }
+}
+
+/// The automatic answer to an OPTIONS request.
+fn options(methods: Vec<&Method>) -> Response {
+    Response::new(StatusCode::NoContent).with_header("Allow", &allow_header(methods))
+}
+
+/// Lists `methods` once each, adding HEAD next to GET and OPTIONS at the end since the router
+/// answers those on its own.
+fn allow_header(methods: Vec<&Method>) -> String {
+    let mut allowed: Vec<&Method> = Vec::new();
+
+    for method in methods {
+        if !allowed.contains(&method) {
+            allowed.push(method);
+        }
+        if method == &Method::Get && !allowed.contains(&&Method::Head) {
+            allowed.push(&Method::Head);
+        }
+    }
+    if !allowed.contains(&&Method::Options) {
+        allowed.push(&Method::Options);
+    }
+
+    allowed
+        .iter()
+        .map(|method| method.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, target: &str) -> Request {
+        let raw = format!("{} {} HTTP/1.1\r\n\r\n", method, target);
+        Request::parse(&mut raw.as_bytes()).unwrap()
+    }
+
+    fn body(response: &Response) -> &str {
+        std::str::from_utf8(response.body.as_bytes().unwrap()).unwrap()
+    }
+
+    fn router() -> Router {
+        let mut router = Router::new();
+        router
+            .get("/", |_, _| Response::text(StatusCode::Ok, "home"))
+            .get("/users/:id", |_, params| match params.get::<u32>("id") {
+                Ok(id) => Response::text(StatusCode::Ok, format!("user {}", id)),
+                Err(e) => e.into(),
+            })
+            .delete("/users/:id", |_, params| {
+                Response::text(
+                    StatusCode::Ok,
+                    format!("deleted {}", params.raw("id").unwrap()),
+                )
+            })
+            .get("/static/*path", |_, params| {
+                Response::text(StatusCode::Ok, params.raw("path").unwrap().to_string())
+            });
+        router
+    }
+
+    #[test]
+    fn it_matches_literal_and_parameter_segments() {
+        let router = router();
+
+        assert_eq!(body(&router.handle(&request("GET", "/"))), "home");
+        assert_eq!(
+            body(&router.handle(&request("GET", "/users/42"))),
+            "user 42"
+        );
+        assert_eq!(
+            body(&router.handle(&request("DELETE", "/users/7"))),
+            "deleted 7"
+        );
+        assert_eq!(
+            router.handle(&request("GET", "/users")).status,
+            StatusCode::NotFound
+        );
+        assert_eq!(
+            router.handle(&request("GET", "/users/42/posts")).status,
+            StatusCode::NotFound
+        );
+
+        assert_eq!(
+            router.route_of(&request("DELETE", "/users/7")),
+            Some("/users/:id")
+        );
+        assert_eq!(router.route_of(&request("GET", "/users")), None);
+    }
+
+    #[test]
+    fn it_rejects_parameters_of_the_wrong_type() {
+        let response = router().handle(&request("GET", "/users/abc"));
+
+        assert_eq!(response.status, StatusCode::BadRequest);
+        assert!(body(&response).contains("\"id\""));
+    }
+
+    #[test]
+    fn it_captures_the_rest_of_the_path_with_a_wildcard() {
+        let router = router();
+
+        assert_eq!(
+            body(&router.handle(&request("GET", "/static/css/site.css"))),
+            "css/site.css"
+        );
+        assert_eq!(body(&router.handle(&request("GET", "/static/"))), "");
+    }
+
+    #[test]
+    fn it_answers_405_with_the_allowed_methods() {
+        let response = router().handle(&request("POST", "/users/42"));
+
+        assert_eq!(response.status, StatusCode::MethodNotAllowed);
+        assert_eq!(
+            response.headers.get("Allow"),
+            Some("GET, HEAD, DELETE, OPTIONS")
+        );
+    }
+
+    #[test]
+    fn it_answers_head_with_the_get_headers_and_no_body() {
+        let response = router().handle(&request("HEAD", "/users/42"));
+
+        assert_eq!(response.status, StatusCode::Ok);
+        assert_eq!(response.headers.get("Content-Length"), Some("7"));
+        assert!(response.body.is_empty());
+
+        let response = router().handle(&request("HEAD", "/missing"));
+        assert_eq!(response.status, StatusCode::NotFound);
+        assert!(response.body.is_empty());
+    }
+
+    #[test]
+    fn it_answers_options_from_the_registered_routes() {
+        let router = router();
+
+        let response = router.handle(&request("OPTIONS", "/users/42"));
+        assert_eq!(response.status, StatusCode::NoContent);
+        assert_eq!(
+            response.headers.get("Allow"),
+            Some("GET, HEAD, DELETE, OPTIONS")
+        );
+
+        let response = router.handle(&request("OPTIONS", "*"));
+        assert_eq!(
+            response.headers.get("Allow"),
+            Some("GET, HEAD, DELETE, OPTIONS")
+        );
+
+        assert_eq!(
+            router.handle(&request("OPTIONS", "/missing")).status,
+            StatusCode::NotFound
+        );
+    }
+
+    #[test]
+    fn it_answers_501_to_unknown_methods() {
+        let mut router = router();
+        assert_eq!(
+            router.handle(&request("PURGE", "/")).status,
+            StatusCode::NotImplemented
+        );
+
+        router.route(
+            Method::Extension(String::from("PURGE")),
+            "/cache",
+            |_, _| Response::new(StatusCode::Ok),
+        );
+        assert_eq!(
+            router.handle(&request("PURGE", "/cache")).status,
+            StatusCode::Ok
+        );
+        assert_eq!(
+            router.handle(&request("PURGE", "/")).status,
+            StatusCode::MethodNotAllowed
+        );
+    }
+
+    #[test]
+    fn it_uses_the_not_found_handler() {
+        let mut router = router();
+        router.not_found(|request, _| Response::text(StatusCode::NotFound, request.path.clone()));
+
+        assert_eq!(
+            body(&router.handle(&request("GET", "/missing"))),
+            "/missing"
+        );
+    }
+}
@@ -0,0 +1,804 @@
+use crate::access_log::{AccessLog, Entry};
+use crate::config::ServerConfig;
+use crate::http::{Limits, Method, ParseError, Request, Response, StatusCode, Version};
+use crate::metrics::Metrics;
+use crate::middleware::{Chain, Middleware};
+use crate::router::Router;
+use crate::timer::Timer;
+use crate::tls::Tls;
+use crate::websocket::{Transport, Upgrade, WebSocket};
+use hosts::Hosts;
+use multi_thread_web_server_pool::{panic_message, Builder, WorkerPool};
+use shutdown::{Connection, Connections};
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{IpAddr, SocketAddr, TcpListener},
+    sync::{Arc, Weak},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+use timeouts::{Socket, Timed};
+
+mod hosts;
+mod shutdown;
+mod timeouts;
+
+pub use hosts::UnknownHosts;
+pub use shutdown::{ShutdownHandle, ShutdownReport};
+pub use timeouts::Timeouts;
+
+/// How long a refused request may keep sending before its connection is closed all the same.
+const LINGER_TIMEOUT: Duration = Duration::from_secs(2);
+/// How much of a refused request is read away at most.
+const LINGER_SIZE: u64 = 1024 * 1024;
+
+/// How long a connection is kept open between requests, and how many requests it may serve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeepAlive {
+    pub idle_timeout: Duration,
+    pub max_requests: usize,
+}
+
+impl Default for KeepAlive {
+    fn default() -> Self {
+        KeepAlive {
+            idle_timeout: Duration::from_secs(5),
+            max_requests: 100,
+        }
+    }
+}
+
+pub struct Server {
+    hosts: Hosts,
+    middleware: Chain,
+    pool: Arc<WorkerPool>,
+    keep_alive: KeepAlive,
+    timeouts: Timeouts,
+    limits: Limits,
+    access_log: Option<AccessLog>,
+    metrics: Option<Arc<Metrics>>,
+    tls: Option<Tls>,
+    redirect_to_https: bool,
+    timer: Timer,
+    shutdown: ShutdownHandle,
+    shutdown_timeout: Duration,
+}
+
+/// What serving a connection needs, shared by every worker.
+struct Shared {
+    hosts: Hosts,
+    middleware: Chain,
+    keep_alive: KeepAlive,
+    timeouts: Timeouts,
+    limits: Limits,
+    access_log: Option<AccessLog>,
+    metrics: Option<Arc<Metrics>>,
+    tls: Option<Tls>,
+    /// The port plain HTTP requests are redirected to, they are served as usual when `None`.
+    https_port: Option<u16>,
+    timer: Timer,
+    /// Where sessions resume once their deferred response is ready. Weak since the pool's tasks
+    /// hold on to `Shared`, and the pool must be dropped by `Server::serve`.
+    pool: Weak<WorkerPool>,
+}
+
+impl Server {
+    pub fn new(router: Router, workers: usize) -> Self {
+        Server::with_pool(router, pool(workers).build())
+    }
+
+    /// A server handing its connections to `pool`, e.g. one with a bounded queue.
+    pub fn with_pool(router: Router, pool: WorkerPool) -> Self {
+        Server {
+            hosts: Hosts::new(router),
+            middleware: Chain::new(),
+            pool: Arc::new(pool),
+            keep_alive: KeepAlive::default(),
+            timeouts: Timeouts::default(),
+            limits: Limits::default(),
+            access_log: None,
+            metrics: None,
+            tls: None,
+            redirect_to_https: false,
+            timer: Timer::new(),
+            shutdown: ShutdownHandle::new(),
+            shutdown_timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// A server set up as `config` says. Binding to `config.address()` is left to the caller.
+    pub fn from_config(router: Router, config: &ServerConfig) -> Self {
+        let mut pool = pool(config.workers).max_size(config.max_workers.max(config.workers));
+        if config.queue_capacity > 0 {
+            pool = pool.capacity(config.queue_capacity);
+        }
+        let server = Server::with_pool(router, pool.build())
+            .keep_alive(KeepAlive {
+                idle_timeout: config.idle_timeout,
+                max_requests: config.max_requests,
+            })
+            .timeouts(Timeouts {
+                header: config.header_timeout,
+                read: config.read_timeout,
+                write: config.write_timeout,
+            })
+            .limits(Limits {
+                max_header_size: config.max_header_size,
+                max_headers: config.max_headers,
+                max_body_size: config.max_body_size,
+            })
+            .shutdown_timeout(config.shutdown_timeout)
+            .redirect_to_https(config.https_redirect)
+            .unknown_hosts(config.unknown_hosts);
+
+        let mut server = match &config.shutdown_route {
+            Some(path) => server.shutdown_route(path),
+            None => server,
+        };
+        if let Some(name) = &config.server_name {
+            server = server.server_name(name);
+        }
+        if let Some(path) = &config.health_route {
+            server = server.health_route(path);
+        }
+        if let Some(path) = &config.metrics_route {
+            server = server.metrics_route(path);
+        }
+        server
+    }
+
+    /// Runs `middleware` around every request, after the ones added before it.
+    pub fn middleware<M: Middleware>(mut self, middleware: M) -> Self {
+        self.middleware.add(middleware);
+        self
+    }
+
+    pub fn keep_alive(mut self, keep_alive: KeepAlive) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// How long clients may take to send requests, slower ones get a 408.
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// How large a request may be, larger ones are refused with 413, 414 or 431.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Records every request answered in `access_log`.
+    pub fn access_log(mut self, access_log: AccessLog) -> Self {
+        self.access_log = Some(access_log);
+        self
+    }
+
+    /// Serves connections over TLS: every connection accepted by `run`, or the ones accepted on
+    /// the second listener of `run_http_and_https`.
+    pub fn tls(mut self, tls: Tls) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Makes `run_http_and_https` answer every plain HTTP request with a redirect to the same
+    /// URL over HTTPS instead of serving it.
+    pub fn redirect_to_https(mut self, redirect: bool) -> Self {
+        self.redirect_to_https = redirect;
+        self
+    }
+
+    /// Serves the requests for the host `name` with `router` rather than the default one given to
+    /// `new`. `name` is matched against the `Host` header without its port, ignoring case, and
+    /// `*.example.com` matches any subdomain of `example.com` that has no host of its own.
+    ///
+    /// The health, metrics and shutdown routes are answered whatever the host.
+    pub fn virtual_host(mut self, name: &str, router: Router) -> Self {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        self.hosts.named.retain(|(named, _)| *named != name);
+        self.hosts.named.push((name, router));
+        self
+    }
+
+    /// The name the server goes by. Redirects to HTTPS keep the host a request names only when
+    /// it is this one or has a `virtual_host`, and point here otherwise. Without a name, they
+    /// point at the address of the HTTPS listener, or `localhost` when it listens on every
+    /// address.
+    pub fn server_name(mut self, name: &str) -> Self {
+        self.hosts.name = Some(name.trim_end_matches('.').to_ascii_lowercase());
+        self
+    }
+
+    /// What requests for hosts without a `virtual_host` get, the default host serves them unless
+    /// told otherwise. Requests that name no host at all always go to the default host.
+    pub fn unknown_hosts(mut self, unknown: UnknownHosts) -> Self {
+        self.hosts.unknown = unknown;
+        self
+    }
+
+    /// How long in-flight requests get to finish once a shutdown was requested.
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Adds a `POST` route at `path` that shuts the server down.
+    /// Anyone able to reach it can stop the server, only enable it on a trusted network.
+    pub fn shutdown_route(mut self, path: &str) -> Self {
+        let shutdown = self.shutdown.clone();
+        self.hosts.admin.post(path, move |_, _| {
+            shutdown.shutdown();
+            Response::text(StatusCode::Accepted, "Shutting down")
+        });
+        self
+    }
+
+    /// The timer deferred responses wait on. It runs as long as the server does, and can time
+    /// other work as well.
+    pub fn timer(&self) -> Timer {
+        self.timer.clone()
+    }
+
+    /// Adds a `GET` route at `path` telling whether the server is ready to take requests: it
+    /// answers 200 while workers of the pool are running and a listener is bound, 503 otherwise
+    /// (before `run`, and once a shutdown was requested).
+    pub fn health_route(mut self, path: &str) -> Self {
+        let pool = Arc::downgrade(&self.pool);
+        let shutdown = self.shutdown.clone();
+        self.hosts.admin.get(path, move |_, _| {
+            let workers = pool.upgrade().map_or(0, |pool| pool.alive());
+            let listening = shutdown.is_listening();
+            let (status, state) = match workers > 0 && listening {
+                true => (StatusCode::Ok, "ready"),
+                false => (StatusCode::ServiceUnavailable, "unavailable"),
+            };
+
+            Response::new(status)
+                .with_header("Content-Type", "application/json")
+                .with_header("Cache-Control", "no-store")
+                .with_body(format!(
+                    "{{\"status\":\"{}\",\"workers\":{},\"listening\":{}}}",
+                    state, workers, listening
+                ))
+        });
+        self
+    }
+
+    /// Records every request answered and adds a `GET` route at `path` exposing the counts,
+    /// latencies and the state of the pool in the Prometheus text format.
+    pub fn metrics_route(mut self, path: &str) -> Self {
+        let metrics = Arc::clone(self.metrics.get_or_insert_with(Default::default));
+        let pool = Arc::downgrade(&self.pool);
+        self.hosts.admin.get(path, move |_, _| {
+            let pool = pool.upgrade();
+            Response::new(StatusCode::Ok)
+                .with_header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+                .with_header("Cache-Control", "no-store")
+                .with_body(metrics.render(pool.as_deref()))
+        });
+        self
+    }
+
+    /// A handle to stop the server once it runs, e.g. from another thread.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Accepts connections on `listener`, each one served by a worker of the pool, until a
+    /// shutdown is requested through a `ShutdownHandle`. Connections are TLS ones if a
+    /// certificate was set with `tls`.
+    ///
+    /// Connections waiting between requests are then closed, the ones in the middle of a request
+    /// get up to the shutdown timeout to finish, and the pool is dropped once they are done.
+    pub fn run(self, listener: TcpListener) -> ShutdownReport {
+        let secure = self.tls.is_some();
+        self.serve(vec![(listener, secure)], None)
+    }
+
+    /// Like `run`, serving plain HTTP on `http` and HTTPS on `https` at the same time.
+    ///
+    /// Panics if no certificate was set with `tls`.
+    pub fn run_http_and_https(mut self, http: TcpListener, https: TcpListener) -> ShutdownReport {
+        assert!(
+            self.tls.is_some(),
+            "serving HTTPS needs a certificate, see Server::tls"
+        );
+
+        let https_port = match https.local_addr() {
+            Ok(address) if self.redirect_to_https => {
+                if self.hosts.name.is_none() {
+                    self.hosts.name = match address.ip() {
+                        ip if ip.is_unspecified() => None,
+                        IpAddr::V6(ip) => Some(format!("[{}]", ip)),
+                        ip => Some(ip.to_string()),
+                    };
+                }
+                Some(address.port())
+            }
+            _ => None,
+        };
+        self.serve(vec![(http, false), (https, true)], https_port)
+    }
+
+    fn serve(self, listeners: Vec<(TcpListener, bool)>, https_port: Option<u16>) -> ShutdownReport {
+        for (listener, _) in &listeners {
+            if let Ok(address) = listener.local_addr() {
+                self.shutdown.listening_on(address);
+            }
+        }
+
+        let pool = self.pool;
+        let shared = Arc::new(Shared {
+            hosts: self.hosts,
+            middleware: self.middleware,
+            keep_alive: self.keep_alive,
+            timeouts: self.timeouts,
+            limits: self.limits,
+            access_log: self.access_log,
+            metrics: self.metrics,
+            tls: self.tls,
+            https_port,
+            timer: self.timer,
+            pool: Arc::downgrade(&pool),
+        });
+        let connections = Arc::new(Connections::new(self.shutdown.clone()));
+
+        // One thread per listener blocks on `accept`, they all hand their connections to the pool.
+        thread::scope(|scope| {
+            for (listener, secure) in &listeners {
+                let (shared, connections, pool) = (&shared, &connections, &pool);
+                let shutdown = &self.shutdown;
+                scope.spawn(move || accept(listener, *secure, shared, connections, pool, shutdown));
+            }
+        });
+
+        drop(listeners);
+        log::info!("No longer accepting connections, waiting for in-flight requests.");
+        let abandoned = connections.drain(self.shutdown_timeout);
+        drop(pool);
+
+        if abandoned > 0 {
+            log::warn!("Abandoned {} in-flight connection(s).", abandoned);
+        }
+
+        ShutdownReport { abandoned }
+    }
+}
+
+/// A pool of `workers` logging the panics of its tasks.
+fn pool(workers: usize) -> Builder {
+    WorkerPool::builder(workers).panic_handler(|worker, payload| {
+        log::error!("Worker {} panicked: {}", worker, panic_message(payload));
+    })
+}
+
+/// Hands every connection accepted on `listener` to the pool until a shutdown is requested.
+fn accept(
+    listener: &TcpListener,
+    secure: bool,
+    shared: &Arc<Shared>,
+    connections: &Arc<Connections>,
+    pool: &WorkerPool,
+    shutdown: &ShutdownHandle,
+) {
+    for stream in listener.incoming() {
+        if shutdown.is_requested() {
+            break;
+        }
+
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn!("Failed to establish a connection: {}", e);
+                continue;
+            }
+        };
+
+        let connection = match connections.register(&stream) {
+            Ok(connection) => connection,
+            Err(e) => {
+                log::warn!("Failed to track a connection: {}", e);
+                continue;
+            }
+        };
+
+        // Kept to answer the client if the queue turns the connection down.
+        let spare = stream.try_clone();
+        let write_timeout = shared.timeouts.write;
+        let shared = Arc::clone(shared);
+        let rejected = pool.try_execute(move |id: usize| {
+            let client = stream.peer_addr().ok();
+            log::debug!("Worker {} serving {:?}.", id, client);
+            stream.set_write_timeout(Some(shared.timeouts.write))?;
+            match &shared.tls {
+                Some(tls) if secure => {
+                    let stream = tls.accept(stream)?;
+                    Session::new(stream, &shared, connection, client, true).serve(shared, id)?
+                }
+                _ => Session::new(stream, &shared, connection, client, false).serve(shared, id)?,
+            }
+            log::debug!("Worker {} finished task.", id);
+            Ok(())
+        });
+
+        if let Err(rejected) = rejected {
+            // Drops the connection's stream and stops tracking it.
+            drop(rejected);
+            log::warn!("Worker queue is full, turning a connection down.");
+            match spare {
+                // A TLS client can't read a plain response.
+                Ok(mut stream) if !secure => {
+                    stream.set_write_timeout(Some(write_timeout)).ok();
+                    Response::text(StatusCode::ServiceUnavailable, "Service Unavailable")
+                        .with_header("Connection", "close")
+                        .write_to(&mut stream, Version::Http11)
+                        .ok();
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// A connection being served. It owns everything about the connection so that it can be set
+/// aside while a response is deferred, and picked up again by whichever worker is free.
+struct Session<S> {
+    reader: BufReader<Timed<S>>,
+    connection: Connection,
+    /// Only used to fill in the access log.
+    client: Option<SocketAddr>,
+    /// Whether the connection is a TLS one.
+    secure: bool,
+    served: usize,
+}
+
+impl<S: Socket> Session<S> {
+    fn new(
+        stream: S,
+        shared: &Shared,
+        connection: Connection,
+        client: Option<SocketAddr>,
+        secure: bool,
+    ) -> Self {
+        Session {
+            reader: BufReader::new(Timed::new(stream, shared.timeouts.read)),
+            connection,
+            client,
+            secure,
+            served: 0,
+        }
+    }
+
+    /// Serves requests one after the other until either side asks to close the connection, the
+    /// client stays idle for longer than `keep_alive.idle_timeout`, `keep_alive.max_requests`
+    /// have been answered, or the server shuts down. `worker` is the one serving it for now.
+    ///
+    /// A deferred response hands the session over to the timer and frees the worker right away,
+    /// the session carries on from `resume` on another worker once the response is ready.
+    ///
+    /// Pipelined requests need nothing special: whatever the client sent ahead stays in the
+    /// buffered reader and is parsed on the next turn of the loop, so responses go out in request
+    /// order.
+    fn serve(mut self, shared: Arc<Shared>, worker: usize) -> io::Result<()> {
+        loop {
+            let mut request = match self.read_request(&shared)? {
+                Some(request) => request,
+                None => return self.close(),
+            };
+            request.remote_addr = self.client;
+
+            self.connection.busy();
+            let started = Instant::now();
+
+            let (ran, mut response) = match shared.https_port {
+                Some(port) if !self.secure => (0, redirect_to_https(&request, port, &shared.hosts)),
+                _ => {
+                    let (ran, early) = shared.middleware.before(&mut request);
+                    let response = early.unwrap_or_else(|| shared.hosts.handle(&request));
+                    (ran, response)
+                }
+            };
+
+            if let Some(deferred) = response.take_deferred() {
+                let timer = shared.timer.clone();
+                deferred.wait(&timer, move |make| {
+                    // The pool only goes away once every connection is done or abandoned, this
+                    // one included.
+                    if let Some(pool) = shared.pool.upgrade() {
+                        // Never blocking on a full queue nor running the session here, on the
+                        // timer thread.
+                        pool.execute_unbounded(move |worker| {
+                            let mut response = make();
+                            if request.method == Method::Head {
+                                response = response.without_body();
+                            }
+                            let pending = Pending {
+                                request,
+                                ran,
+                                started,
+                            };
+                            self.resume(shared, pending, response, worker)
+                        });
+                    }
+                });
+                return Ok(());
+            }
+
+            let pending = Pending {
+                request,
+                ran,
+                started,
+            };
+            match self.respond(&shared, pending, response, worker)? {
+                Outcome::KeepOpen => {}
+                Outcome::Close => return self.close(),
+                Outcome::Upgrade(upgrade) => return self.upgrade(upgrade),
+            }
+        }
+    }
+
+    /// Sends the response that was deferred, then carries on serving the connection.
+    fn resume(
+        mut self,
+        shared: Arc<Shared>,
+        pending: Pending,
+        response: Response,
+        worker: usize,
+    ) -> io::Result<()> {
+        log::debug!("Worker {} resuming {:?}.", worker, self.client);
+        match self.respond(&shared, pending, response, worker)? {
+            Outcome::KeepOpen => self.serve(shared, worker),
+            Outcome::Close => self.close(),
+            Outcome::Upgrade(upgrade) => self.upgrade(upgrade),
+        }
+    }
+
+    /// Waits for the next request and reads it. Returns `None` once the connection should be
+    /// closed, after answering requests that can't be parsed.
+    fn read_request(&mut self, shared: &Shared) -> io::Result<Option<Request>> {
+        if self.served > 0 && !self.connection.idle() {
+            return Ok(None);
+        }
+
+        // Waiting for a request to start is allowed to take the idle timeout, once it has started
+        // the rest has to come within the header and read timeouts.
+        let idle_until = Instant::now() + shared.keep_alive.idle_timeout;
+        self.reader.get_mut().set_deadline(Some(idle_until));
+        match self.reader.fill_buf() {
+            Ok([]) => return Ok(None),
+            Ok(_) => {}
+            Err(e) if is_timeout(&e) => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        match read_request(&mut self.reader, shared) {
+            Ok(request) => Ok(Some(request)),
+            Err(ParseError::ConnectionClosed) => Ok(None),
+            Err(ParseError::Io(e)) => Err(e),
+            Err(e) => {
+                let status = e.status();
+                Response::text(status, format!("Error {}: {}", status.code(), e))
+                    .with_header("Connection", "close")
+                    .write_to(self.reader.get_mut(), Version::Http11)?;
+                // A client too slow to send its request isn't waited for any longer.
+                if !matches!(e, ParseError::TimedOut) {
+                    self.linger();
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    /// Reads away what the client is still sending, an unread body most likely, before the
+    /// connection is closed. Closing with unread input resets the connection, and the client may
+    /// lose the response before reading it.
+    fn linger(&mut self) {
+        let timed = self.reader.get_mut();
+        if timed.socket_mut().shutdown_write().is_err() {
+            return;
+        }
+        timed.set_deadline(Some(Instant::now() + LINGER_TIMEOUT));
+        let _ = io::copy(&mut (&mut self.reader).take(LINGER_SIZE), &mut io::sink());
+    }
+
+    /// Runs the `after` hooks on `response` and writes it. Returns what becomes of the
+    /// connection.
+    fn respond(
+        &mut self,
+        shared: &Shared,
+        pending: Pending,
+        mut response: Response,
+        worker: usize,
+    ) -> io::Result<Outcome> {
+        let Pending {
+            request,
+            ran,
+            started,
+        } = pending;
+        shared.middleware.after(ran, &request, &mut response);
+        self.served += 1;
+
+        let upgrade = response.take_upgrade();
+        let keep_alive = &shared.keep_alive;
+        let persistent = upgrade.is_none()
+            && wants_keep_alive(&request)
+            && !self.connection.is_closing()
+            && !response.headers.has_token("Connection", "close")
+            && !response.is_close_delimited(request.version)
+            && self.served < keep_alive.max_requests;
+
+        if upgrade.is_some() {
+            // The handshake response says `Connection: Upgrade`, and nothing else applies.
+        } else if !persistent {
+            response.headers.insert("Connection", "close");
+        } else if request.version == Version::Http10 {
+            // HTTP/1.0 clients only keep the connection open when told so explicitly.
+            response.headers.insert("Connection", "keep-alive");
+            response.headers.insert(
+                "Keep-Alive",
+                &format!(
+                    "timeout={}, max={}",
+                    keep_alive.idle_timeout.as_secs(),
+                    keep_alive.max_requests - self.served
+                ),
+            );
+        }
+
+        let (status, size) = (response.status, response.body.len());
+        response.write_to(self.reader.get_mut(), request.version)?;
+
+        let latency = started.elapsed();
+        if let Some(metrics) = &shared.metrics {
+            let route = shared.hosts.route_of(&request).unwrap_or("unmatched");
+            metrics.record(route, &request.method, status, latency);
+        }
+        if let Some(access_log) = &shared.access_log {
+            access_log.log(&Entry {
+                client: self.client,
+                request: &request,
+                status,
+                size,
+                latency,
+                worker,
+                time: SystemTime::now(),
+            });
+        }
+
+        Ok(match upgrade {
+            Some(upgrade) => Outcome::Upgrade(upgrade),
+            None if persistent => Outcome::KeepOpen,
+            None => Outcome::Close,
+        })
+    }
+
+    /// Hands the connection over to a WebSocket handler. It counts as busy for a shutdown until
+    /// the handler returns.
+    fn upgrade(self, upgrade: Upgrade) -> io::Result<()> {
+        let Session {
+            reader, connection, ..
+        } = self;
+        log::debug!("Upgraded {:?} to a WebSocket.", self.client);
+        upgrade.run(WebSocket::new(Box::new(Upgraded(reader))));
+        drop(connection);
+        Ok(())
+    }
+
+    fn close(mut self) -> io::Result<()> {
+        self.reader.get_mut().socket_mut().close()
+    }
+}
+
+/// What becomes of a connection once a response was sent.
+enum Outcome {
+    KeepOpen,
+    Close,
+    Upgrade(Upgrade),
+}
+
+/// A connection given over to a WebSocket, with whatever the client sent past the handshake
+/// still buffered.
+struct Upgraded<S>(BufReader<Timed<S>>);
+
+impl<S: Socket> Read for Upgraded<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<S: Socket> BufRead for Upgraded<S> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.0.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.0.consume(amount)
+    }
+}
+
+impl<S: Socket> Write for Upgraded<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.get_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.get_mut().flush()
+    }
+}
+
+impl<S: Socket> Transport for Upgraded<S> {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.0.get_mut().set_read_timeout(timeout);
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.0.get_mut().socket_mut().close()
+    }
+}
+
+/// A request being answered, kept along while its response is deferred.
+struct Pending {
+    request: Request,
+    /// How many middlewares ran their `before` hook.
+    ran: usize,
+    started: Instant,
+}
+
+/// Reads the head of a request within the header timeout, then its body.
+fn read_request<S: Socket>(
+    reader: &mut BufReader<Timed<S>>,
+    shared: &Shared,
+) -> Result<Request, ParseError> {
+    let timed_out = |e: ParseError| match e {
+        ParseError::Io(e) if is_timeout(&e) => ParseError::TimedOut,
+        e => e,
+    };
+
+    reader
+        .get_mut()
+        .set_deadline(Some(Instant::now() + shared.timeouts.header));
+    let mut request = Request::parse_head(reader, &shared.limits).map_err(timed_out)?;
+
+    reader.get_mut().set_deadline(None);
+    request
+        .read_body(reader, &shared.limits)
+        .map_err(timed_out)?;
+    Ok(request)
+}
+
+/// A redirect to the URL `request` was sent to, with the `https` scheme and `port`, on a host
+/// the server is known by.
+fn redirect_to_https(request: &Request, port: u16, hosts: &Hosts) -> Response {
+    let host = hosts
+        .redirect_host(request)
+        .unwrap_or_else(|| String::from("localhost"));
+    let authority = match port {
+        443 => host,
+        port => format!("{}:{}", host, port),
+    };
+    let target = match request.target.starts_with('/') {
+        true => request.target.as_str(),
+        false => "/",
+    };
+
+    Response::new(StatusCode::PermanentRedirect)
+        .with_header("Location", &format!("https://{}{}", authority, target))
+}
+
+/// HTTP/1.1 connections are persistent unless `Connection: close` is sent, HTTP/1.0 ones are
+/// closed unless `Connection: keep-alive` is sent.
+fn wants_keep_alive(request: &Request) -> bool {
+    match request.version {
+        Version::Http11 => !request.headers.has_token("Connection", "close"),
+        Version::Http10 => request.headers.has_token("Connection", "keep-alive"),
+    }
+}
+
+// A read timeout surfaces as `WouldBlock` on Unix and `TimedOut` on Windows.
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
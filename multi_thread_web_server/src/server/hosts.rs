@@ -0,0 +1,232 @@
+use crate::http::{Request, Response, StatusCode};
+use crate::router::Router;
+use std::str::FromStr;
+
+/// What a request naming a host without a virtual host of its own gets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownHosts {
+    /// Served by the default host, the router given to `Server::new`.
+    Default,
+    /// A 404, as if the host had no routes at all.
+    NotFound,
+    /// A 421 Misdirected Request, telling the client this server doesn't serve that host.
+    Misdirected,
+}
+
+impl FromStr for UnknownHosts {
+    type Err = String;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy.to_ascii_lowercase().as_str() {
+            "default" => Ok(UnknownHosts::Default),
+            "404" | "not_found" => Ok(UnknownHosts::NotFound),
+            "421" | "misdirected" => Ok(UnknownHosts::Misdirected),
+            _ => Err(format!(
+                "unknown policy {:?}, expected default, 404 or 421",
+                policy
+            )),
+        }
+    }
+}
+
+/// Picks the router serving a request from its `Host` header.
+pub(super) struct Hosts {
+    /// The health, metrics and shutdown routes, answered whatever the host.
+    pub(super) admin: Router,
+    pub(super) default: Router,
+    /// Lowercase names, either exact or `*.` followed by a domain.
+    pub(super) named: Vec<(String, Router)>,
+    pub(super) unknown: UnknownHosts,
+    /// The lowercase name the server goes by, where redirects for other hosts point.
+    pub(super) name: Option<String>,
+}
+
+impl Hosts {
+    pub(super) fn new(default: Router) -> Self {
+        Hosts {
+            admin: Router::new(),
+            default,
+            named: Vec::new(),
+            unknown: UnknownHosts::Default,
+            name: None,
+        }
+    }
+
+    pub(super) fn handle(&self, request: &Request) -> Response {
+        match self.router(request) {
+            Some(router) => router.handle(request),
+            None if self.unknown == UnknownHosts::Misdirected => {
+                Response::text(StatusCode::MisdirectedRequest, "Misdirected Request")
+            }
+            None => Response::text(StatusCode::NotFound, "Not Found"),
+        }
+    }
+
+    /// The pattern of the route matching `request` on the router serving it.
+    pub(super) fn route_of(&self, request: &Request) -> Option<&str> {
+        self.router(request)?.route_of(request)
+    }
+
+    /// The router serving `request`, `None` when its host is refused.
+    fn router(&self, request: &Request) -> Option<&Router> {
+        if self.admin.route_of(request).is_some() {
+            return Some(&self.admin);
+        }
+
+        let host = match request.headers.get("Host").map(hostname) {
+            // A malformed host is no virtual host's.
+            Some(host) if host.as_ref().is_none_or(|host| !host.is_empty()) => host,
+            // HTTP/1.0 clients may not say which host they want.
+            _ => return Some(&self.default),
+        };
+
+        match host.and_then(|host| self.named(&host)) {
+            Some(router) => Some(router),
+            None if self.unknown == UnknownHosts::Default => Some(&self.default),
+            None => None,
+        }
+    }
+
+    /// The host a redirect for `request` points at: the one it names if the server goes by that
+    /// name or has a virtual host for it, the server's own name otherwise. A forged `Host` header
+    /// then can't send the client elsewhere.
+    pub(super) fn redirect_host(&self, request: &Request) -> Option<String> {
+        let named = request.headers.get("Host").and_then(hostname);
+        named
+            .filter(|host| self.name.as_ref() == Some(host) || self.named(host).is_some())
+            .or_else(|| self.name.clone())
+    }
+
+    /// The router of the virtual host `host`, matched exactly first.
+    fn named(&self, host: &str) -> Option<&Router> {
+        let exact = self.named.iter().find(|(name, _)| *name == host);
+        let wildcard = || {
+            self.named.iter().find(|(name, _)| {
+                name.strip_prefix("*.").is_some_and(|domain| {
+                    host.strip_suffix(domain)
+                        .is_some_and(|subdomain| subdomain.len() > 1 && subdomain.ends_with('.'))
+                })
+            })
+        };
+        exact.or_else(wildcard).map(|(_, router)| router)
+    }
+}
+
+/// The lowercase host name of a `Host` header, without its port or a trailing dot. `None` unless
+/// the header holds a host name or a bracketed IP address, and maybe a port.
+fn hostname(host: &str) -> Option<String> {
+    let host = host.trim();
+    let (name, port) = if host.starts_with('[') {
+        // An IPv6 address, whose colons aren't the port's.
+        let (address, port) = host.split_at(host.find(']')? + 1);
+        let digits = &address[1..address.len() - 1];
+        if !digits
+            .bytes()
+            .all(|b| b.is_ascii_hexdigit() || b":.".contains(&b))
+        {
+            return None;
+        }
+        (address, port)
+    } else {
+        let (name, port) = host.split_at(host.find(':').unwrap_or(host.len()));
+        if !name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-._".contains(&b))
+        {
+            return None;
+        }
+        (name, port)
+    };
+    let port = port.strip_prefix(':').unwrap_or(port);
+    if !port.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(name.trim_end_matches('.').to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(body: &'static str) -> Router {
+        let mut router = Router::new();
+        router.get("/", move |_, _| Response::text(StatusCode::Ok, body));
+        router
+    }
+
+    fn get(hosts: &Hosts, host: Option<&str>) -> (StatusCode, String) {
+        let host = host.map_or(String::new(), |host| format!("Host: {}\r\n", host));
+        let request = Request::parse(&mut format!("GET / HTTP/1.1\r\n{}\r\n", host).as_bytes());
+        let response = hosts.handle(&request.unwrap());
+        let body = String::from_utf8(response.body.as_bytes().unwrap().to_vec()).unwrap();
+        (response.status, body)
+    }
+
+    #[test]
+    fn it_routes_by_host_name() {
+        let mut hosts = Hosts::new(text("default"));
+        hosts
+            .named
+            .push((String::from("example.com"), text("example")));
+        hosts
+            .named
+            .push((String::from("*.example.com"), text("subdomain")));
+        let body = |host| get(&hosts, host).1;
+
+        assert_eq!(body(Some("example.com")), "example");
+        assert_eq!(body(Some("Example.COM.:8080")), "example");
+        assert_eq!(body(Some("blog.example.com")), "subdomain");
+        assert_eq!(body(Some("a.b.example.com")), "subdomain");
+        assert_eq!(body(Some("badexample.com")), "default");
+        assert_eq!(body(Some("evil.com/x.example.com")), "default");
+        assert_eq!(body(Some("[::1]:8000")), "default");
+        assert_eq!(body(None), "default");
+
+        hosts.unknown = UnknownHosts::Misdirected;
+        assert_eq!(
+            get(&hosts, Some("other.org")).0,
+            StatusCode::MisdirectedRequest
+        );
+        assert_eq!(get(&hosts, None).1, "default");
+        hosts.unknown = UnknownHosts::NotFound;
+        assert_eq!(get(&hosts, Some("other.org")).0, StatusCode::NotFound);
+
+        assert_eq!(hostname("[::1]:8000").as_deref(), Some("[::1]"));
+        assert_eq!(hostname("[::1]x"), None);
+        assert_eq!(hostname("example.com:80:80"), None);
+    }
+
+    #[test]
+    fn it_redirects_to_known_hosts_only() {
+        let mut hosts = Hosts::new(text("default"));
+        hosts
+            .named
+            .push((String::from("*.example.com"), text("subdomain")));
+        hosts.name = Some(String::from("example.com"));
+        let redirect_host = |host: &str| {
+            let request = format!("GET / HTTP/1.1\r\nHost: {}\r\n\r\n", host);
+            hosts.redirect_host(&Request::parse(&mut request.as_bytes()).unwrap())
+        };
+
+        assert_eq!(
+            redirect_host("Example.com:8000").as_deref(),
+            Some("example.com")
+        );
+        assert_eq!(
+            redirect_host("blog.example.com").as_deref(),
+            Some("blog.example.com")
+        );
+        assert_eq!(
+            redirect_host("evil.example").as_deref(),
+            Some("example.com")
+        );
+        assert_eq!(redirect_host("").as_deref(), Some("example.com"));
+        for forged in [
+            "evil.com/x.example.com",
+            "a?.example.com",
+            "evil.com@blog.example.com",
+        ] {
+            assert_eq!(redirect_host(forged).as_deref(), Some("example.com"));
+        }
+    }
+}
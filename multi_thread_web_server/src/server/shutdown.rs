@@ -0,0 +1,208 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Asks a running `Server` to stop. Cloning it is cheap and every clone controls the same server.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownHandle {
+    state: Arc<ShutdownState>,
+}
+
+#[derive(Debug, Default)]
+struct ShutdownState {
+    requested: AtomicBool,
+    addresses: Mutex<Vec<SocketAddr>>,
+}
+
+impl ShutdownHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops the server from accepting connections and starts draining the ones in progress.
+    /// Calling it more than once has no further effect.
+    pub fn shutdown(&self) {
+        if self.state.requested.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        // The accept loops are blocked waiting for a client, so we become that client to wake
+        // them up and let them see the request.
+        for address in self.state.addresses.lock().unwrap().iter() {
+            TcpStream::connect(address).ok();
+        }
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.state.requested.load(Ordering::SeqCst)
+    }
+
+    /// Shuts the server down on the first SIGINT or SIGTERM, a second one exits the process right
+    /// away for when draining takes longer than we're willing to wait.
+    #[cfg(unix)]
+    pub fn on_signals(&self) -> io::Result<()> {
+        use signal_hook::{
+            consts::{SIGINT, SIGTERM},
+            iterator::Signals,
+        };
+
+        let mut signals = Signals::new([SIGINT, SIGTERM])?;
+        let handle = self.clone();
+
+        std::thread::spawn(move || {
+            for signal in signals.forever() {
+                if handle.is_requested() {
+                    log::warn!("Received signal {} again, exiting now.", signal);
+                    std::process::exit(130);
+                }
+
+                log::info!("Received signal {}, shutting down.", signal);
+                handle.shutdown();
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Whether the server is bound to an address and not shutting down.
+    pub(crate) fn is_listening(&self) -> bool {
+        !self.is_requested() && !self.state.addresses.lock().unwrap().is_empty()
+    }
+
+    pub(crate) fn listening_on(&self, address: SocketAddr) {
+        // A listener bound to every interface can't be connected to as is, the loopback
+        // interface is one of them.
+        let address = match address.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => {
+                SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), address.port())
+            }
+            IpAddr::V6(ip) if ip.is_unspecified() => {
+                SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), address.port())
+            }
+            _ => address,
+        };
+
+        self.state.addresses.lock().unwrap().push(address);
+    }
+}
+
+/// What was left undone when the server stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// Connections that were still waiting for, or in the middle of, a request when the shutdown
+    /// deadline passed. Their sockets are closed under them.
+    pub abandoned: usize,
+}
+
+struct Tracked {
+    stream: TcpStream,
+    idle: bool,
+}
+
+/// Keeps track of the open connections so a shutdown knows what it is waiting for.
+/// A connection is busy from the moment it is accepted (it may sit in the pool's queue before a
+/// worker picks it up) and idle while it waits for the next request of a keep-alive session.
+pub(crate) struct Connections {
+    shutdown: ShutdownHandle,
+    open: Mutex<HashMap<u64, Tracked>>,
+    closed: Condvar,
+    next_id: AtomicU64,
+}
+
+impl Connections {
+    pub(crate) fn new(shutdown: ShutdownHandle) -> Self {
+        Connections {
+            shutdown,
+            open: Mutex::new(HashMap::new()),
+            closed: Condvar::new(),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn register(self: &Arc<Self>, stream: &TcpStream) -> io::Result<Connection> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let tracked = Tracked {
+            stream: stream.try_clone()?,
+            idle: false,
+        };
+        self.open.lock().unwrap().insert(id, tracked);
+
+        Ok(Connection {
+            connections: Arc::clone(self),
+            id,
+        })
+    }
+
+    /// Closes the idle connections and waits up to `timeout` for the busy ones to finish.
+    /// Returns how many were still open by then, those are closed too.
+    pub(crate) fn drain(&self, timeout: Duration) -> usize {
+        let deadline = Instant::now() + timeout;
+        let mut open = self.open.lock().unwrap();
+
+        for tracked in open.values().filter(|tracked| tracked.idle) {
+            tracked.stream.shutdown(Shutdown::Both).ok();
+        }
+
+        while !open.is_empty() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            open = self.closed.wait_timeout(open, remaining).unwrap().0;
+        }
+
+        for tracked in open.values() {
+            tracked.stream.shutdown(Shutdown::Both).ok();
+        }
+
+        open.len()
+    }
+}
+
+/// A connection registered in `Connections`, it is forgotten when dropped.
+pub(crate) struct Connection {
+    connections: Arc<Connections>,
+    id: u64,
+}
+
+impl Connection {
+    /// Whether the server is shutting down, the connection should not wait for another request.
+    pub(crate) fn is_closing(&self) -> bool {
+        self.connections.shutdown.is_requested()
+    }
+
+    /// Marks the connection as waiting for its next request. Returns `false` when the server is
+    /// shutting down, in which case the connection should be closed instead.
+    pub(crate) fn idle(&self) -> bool {
+        let mut open = self.connections.open.lock().unwrap();
+        // Checked under the lock so a drain can't miss a connection turning idle.
+        if self.is_closing() {
+            return false;
+        }
+        if let Some(tracked) = open.get_mut(&self.id) {
+            tracked.idle = true;
+        }
+        true
+    }
+
+    /// Marks the connection as handling a request.
+    pub(crate) fn busy(&self) {
+        if let Some(tracked) = self.connections.open.lock().unwrap().get_mut(&self.id) {
+            tracked.idle = false;
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.connections.open.lock().unwrap().remove(&self.id);
+        self.connections.closed.notify_all();
+    }
+}
@@ -0,0 +1,131 @@
+use crate::tls::TlsStream;
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream},
+    time::{Duration, Instant},
+};
+
+/// How long a client may take to send a request and to take the response, so slow or stalled
+/// clients can't hold on to a worker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// From the first byte of a request to the end of its header fields, however the bytes
+    /// trickle in.
+    pub header: Duration,
+    /// How long reading the body may wait for more bytes.
+    pub read: Duration,
+    /// How long writing the response may wait for the client to make room.
+    pub write: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            header: Duration::from_secs(10),
+            read: Duration::from_secs(30),
+            write: Duration::from_secs(30),
+        }
+    }
+}
+
+/// A connection whose read timeout can be changed while it is served, and that can move to
+/// another worker while its response is deferred.
+pub(crate) trait Socket: Read + Write + Send + 'static {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Tells the client nothing more will be sent, while what it sends can still be read.
+    fn shutdown_write(&mut self) -> io::Result<()>;
+
+    /// Ends the connection on purpose, before it is dropped.
+    fn close(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Socket for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        self.shutdown(Shutdown::Write)
+    }
+}
+
+impl Socket for TlsStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_read_timeout(timeout)
+    }
+
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        self.close()?;
+        self.sock.shutdown(Shutdown::Write)
+    }
+
+    /// Tells the client the connection was closed on purpose rather than cut.
+    fn close(&mut self) -> io::Result<()> {
+        self.conn.send_close_notify();
+        self.flush()
+    }
+}
+
+/// Reads from a socket without waiting past a deadline: a read timeout alone only bounds each
+/// read, and a client sending one byte at a time would never hit it.
+pub(crate) struct Timed<S> {
+    socket: S,
+    deadline: Option<Instant>,
+    read_timeout: Option<Duration>,
+}
+
+impl<S: Socket> Timed<S> {
+    /// Without a deadline every read waits up to `read_timeout`, unless changed.
+    pub(crate) fn new(socket: S, read_timeout: Duration) -> Self {
+        Timed {
+            socket,
+            deadline: None,
+            read_timeout: Some(read_timeout),
+        }
+    }
+
+    /// Fails reads once `deadline` has passed, until it is changed.
+    pub(crate) fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// How long reads wait without a deadline, forever when `None`.
+    pub(crate) fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    pub(crate) fn socket_mut(&mut self) -> &mut S {
+        &mut self.socket
+    }
+}
+
+impl<S: Socket> Read for Timed<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timeout = match self.deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Err(io::ErrorKind::TimedOut.into());
+                }
+                Some(remaining)
+            }
+            None => self.read_timeout,
+        };
+
+        self.socket.set_read_timeout(timeout)?;
+        self.socket.read(buf)
+    }
+}
+
+impl<S: Socket> Write for Timed<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.socket.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.socket.flush()
+    }
+}
@@ -0,0 +1,433 @@
+use crate::http::{date, mime, Request, Response, StatusCode};
+use std::{
+    fs::{self, File, Metadata},
+    io::{self, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Serves the files found under a root directory.
+///
+/// Mount it on a wildcard route and hand it the captured path:
+///
+/// ```no_run
+/// use multi_thread_web_server::router::Router;
+/// use multi_thread_web_server::static_files::StaticFiles;
+///
+/// let files = StaticFiles::new("static");
+/// let mut router = Router::new();
+/// router.get("/static/*path", move |request, params| {
+///     files.serve(request, params.raw("path").unwrap_or(""))
+/// });
+/// ```
+pub struct StaticFiles {
+    root: PathBuf,
+    index_files: Vec<String>,
+}
+
+impl StaticFiles {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        StaticFiles {
+            root: root.into(),
+            index_files: vec![String::from("index.html")],
+        }
+    }
+
+    /// The files looked up, in order, when a directory is requested. Defaults to `index.html`.
+    pub fn index_files(mut self, names: &[&str]) -> Self {
+        self.index_files = names.iter().map(|name| String::from(*name)).collect();
+        self
+    }
+
+    /// Answers `request` with the file at `path`, relative to the root directory.
+    pub fn serve(&self, request: &Request, path: &str) -> Response {
+        let relative = match sanitize(path) {
+            Some(relative) => relative,
+            None => return Response::text(StatusCode::Forbidden, "Forbidden"),
+        };
+
+        let mut full = self.root.join(relative);
+        let mut metadata = match fs::metadata(&full) {
+            Ok(metadata) => metadata,
+            Err(e) => return error_response(&e),
+        };
+
+        if metadata.is_dir() {
+            // Without the trailing slash relative links in the index page would resolve against
+            // the parent directory.
+            if !request.path.ends_with('/') {
+                return redirect_to_directory(request);
+            }
+
+            let index = self.index_files.iter().find_map(|name| {
+                let candidate = full.join(name);
+                match fs::metadata(&candidate) {
+                    Ok(metadata) if metadata.is_file() => Some((candidate, metadata)),
+                    _ => None,
+                }
+            });
+
+            match index {
+                Some((index, index_metadata)) => {
+                    full = index;
+                    metadata = index_metadata;
+                }
+                None => return Response::text(StatusCode::NotFound, "Not Found"),
+            }
+        }
+
+        // `sanitize` keeps `..` out of the path, this also catches symlinks pointing outside.
+        match (full.canonicalize(), self.root.canonicalize()) {
+            (Ok(full), Ok(root)) if full.starts_with(&root) => {}
+            _ => return Response::text(StatusCode::Forbidden, "Forbidden"),
+        }
+
+        match File::open(&full) {
+            Ok(file) => file_response(request, &full, file, &metadata),
+            Err(e) => error_response(&e),
+        }
+    }
+}
+
+/// Turns the requested path into a relative path made only of normal components.
+/// Returns `None` when it tries to climb out of the root directory.
+pub(crate) fn sanitize(path: &str) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => continue,
+            ".." => return None,
+            _ if segment.contains('\\') || segment.contains('\0') || segment.contains(':') => {
+                return None
+            }
+            _ => relative.push(segment),
+        }
+    }
+
+    Some(relative)
+}
+
+fn error_response(e: &io::Error) -> Response {
+    match e.kind() {
+        io::ErrorKind::PermissionDenied => Response::text(StatusCode::Forbidden, "Forbidden"),
+        _ => Response::text(StatusCode::NotFound, "Not Found"),
+    }
+}
+
+fn redirect_to_directory(request: &Request) -> Response {
+    // Never the target as sent, an absolute-form one would redirect to the host it names.
+    let target = request.origin_form();
+    let location = match target.split_once('?') {
+        Some((path, query)) => format!("{}/?{}", path, query),
+        None => format!("{}/", target),
+    };
+
+    Response::new(StatusCode::MovedPermanently).with_header("Location", &location)
+}
+
+fn file_response(request: &Request, path: &Path, mut file: File, metadata: &Metadata) -> Response {
+    let length = metadata.len();
+    let modified = metadata
+        .modified()
+        .map(truncate_to_secs)
+        .unwrap_or(UNIX_EPOCH);
+    let etag = etag(metadata);
+    let last_modified = date::format(modified);
+
+    if is_not_modified(request, &etag, modified) {
+        return Response::new(StatusCode::NotModified)
+            .with_header("ETag", &etag)
+            .with_header("Last-Modified", &last_modified);
+    }
+
+    let response = Response::new(StatusCode::Ok)
+        .with_header("Content-Type", mime::from_path(path))
+        .with_header("ETag", &etag)
+        .with_header("Last-Modified", &last_modified)
+        .with_header("Accept-Ranges", "bytes");
+
+    match requested_range(request, &etag, modified, length) {
+        None => response.with_stream(file, length),
+        Some(Ok((start, end))) => {
+            if let Err(e) = file.seek(SeekFrom::Start(start)) {
+                return error_response(&e);
+            }
+
+            response
+                .with_status(StatusCode::PartialContent)
+                .with_header(
+                    "Content-Range",
+                    &format!("bytes {}-{}/{}", start, end, length),
+                )
+                .with_stream(file, end - start + 1)
+        }
+        Some(Err(())) => Response::new(StatusCode::RangeNotSatisfiable)
+            .with_header("Content-Range", &format!("bytes */{}", length)),
+    }
+}
+
+/// HTTP dates only carry whole seconds, comparisons have to be done at that precision too.
+fn truncate_to_secs(time: SystemTime) -> SystemTime {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+fn etag(metadata: &Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+
+    format!("\"{:x}-{:x}\"", modified.as_nanos(), metadata.len())
+}
+
+/// `If-None-Match` wins over `If-Modified-Since` when both are sent.
+fn is_not_modified(request: &Request, etag: &str, modified: SystemTime) -> bool {
+    if let Some(tags) = request.headers.get("If-None-Match") {
+        return tags.split(',').any(|tag| {
+            let tag = tag.trim();
+            // If-None-Match uses the weak comparison, a `W/` prefix doesn't matter.
+            tag == "*" || tag.trim_start_matches("W/") == etag
+        });
+    }
+
+    match request
+        .headers
+        .get("If-Modified-Since")
+        .and_then(date::parse)
+    {
+        Some(since) => modified <= since,
+        None => false,
+    }
+}
+
+/// Works out the byte range asked for with a `Range` header, as inclusive `(start, end)` offsets.
+/// Returns `None` when the whole file should be sent (no header, a syntax we don't support, or an
+/// outdated `If-Range`), and `Some(Err(()))` when the range lies outside of the file.
+fn requested_range(
+    request: &Request,
+    etag: &str,
+    modified: SystemTime,
+    length: u64,
+) -> Option<Result<(u64, u64), ()>> {
+    let range = request.headers.get("Range")?;
+
+    if let Some(condition) = request.headers.get("If-Range") {
+        // If-Range uses the strong comparison, weak tags never match.
+        let current = if condition.starts_with('"') {
+            condition == etag
+        } else {
+            date::parse(condition) == Some(modified)
+        };
+
+        if !current {
+            return None;
+        }
+    }
+
+    // Multiple ranges would need a multipart/byteranges body, sending the whole file is allowed.
+    let spec = range.strip_prefix("bytes=")?.trim();
+    if spec.contains(',') {
+        return None;
+    }
+
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            if suffix == 0 || length == 0 {
+                return Some(Err(()));
+            }
+            (length.saturating_sub(suffix), length - 1)
+        }
+        (start, "") => (start.parse().ok()?, length.saturating_sub(1)),
+        (start, end) => {
+            let (start, end): (u64, u64) = (start.parse().ok()?, end.parse().ok()?);
+            if end < start {
+                return None;
+            }
+            (start, end.min(length.saturating_sub(1)))
+        }
+    };
+
+    if start >= length {
+        return Some(Err(()));
+    }
+
+    Some(Ok((start, end)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    struct Fixture {
+        root: PathBuf,
+        files: StaticFiles,
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            fs::remove_dir_all(&self.root).ok();
+        }
+    }
+
+    fn fixture(name: &str) -> Fixture {
+        let root = env::temp_dir().join(format!("static-files-{}-{}", name, process::id()));
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::create_dir_all(root.join("empty")).unwrap();
+        fs::write(root.join("hello.txt"), "Hello, world!").unwrap();
+        fs::write(root.join("logo.png"), [0x89, b'P', b'N', b'G', 0, 0xff]).unwrap();
+        fs::write(root.join("docs/index.html"), "<p>docs</p>").unwrap();
+
+        Fixture {
+            files: StaticFiles::new(&root),
+            root,
+        }
+    }
+
+    fn get(fixture: &Fixture, target: &str, headers: &str) -> Response {
+        let raw = format!("GET {} HTTP/1.1\r\n{}\r\n", target, headers);
+        let request = Request::parse(&mut raw.as_bytes()).unwrap();
+        let path = request.path.trim_start_matches('/').to_string();
+        fixture.files.serve(&request, &path)
+    }
+
+    fn body(response: Response) -> Vec<u8> {
+        response.body.into_bytes().unwrap()
+    }
+
+    #[test]
+    fn it_serves_text_and_binary_files_with_their_type() {
+        let fixture = fixture("types");
+
+        let response = get(&fixture, "/hello.txt", "");
+        assert_eq!(response.status, StatusCode::Ok);
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(response.headers.get("Accept-Ranges"), Some("bytes"));
+        assert_eq!(body(response), b"Hello, world!");
+
+        let response = get(&fixture, "/logo.png", "");
+        assert_eq!(response.headers.get("Content-Type"), Some("image/png"));
+        assert_eq!(body(response), [0x89, b'P', b'N', b'G', 0, 0xff]);
+
+        assert_eq!(
+            get(&fixture, "/missing.txt", "").status,
+            StatusCode::NotFound
+        );
+    }
+
+    #[test]
+    fn it_serves_index_files_and_redirects_directories() {
+        let fixture = fixture("index");
+
+        let response = get(&fixture, "/docs/", "");
+        assert_eq!(body(response), b"<p>docs</p>");
+
+        let response = get(&fixture, "/docs?page=1", "");
+        assert_eq!(response.status, StatusCode::MovedPermanently);
+        assert_eq!(response.headers.get("Location"), Some("/docs/?page=1"));
+
+        // The redirect stays on this server whatever host the target names.
+        for target in ["http://evil.example/docs", "//docs"] {
+            let response = get(&fixture, target, "");
+            assert_eq!(response.headers.get("Location"), Some("/docs/"));
+        }
+
+        assert_eq!(get(&fixture, "/empty/", "").status, StatusCode::NotFound);
+    }
+
+    #[test]
+    fn it_rejects_path_traversal() {
+        let fixture = fixture("traversal");
+
+        assert_eq!(
+            get(&fixture, "/../etc/passwd", "").status,
+            StatusCode::Forbidden
+        );
+        assert_eq!(
+            get(&fixture, "/docs/%2e%2e/%2e%2e/etc/passwd", "").status,
+            StatusCode::Forbidden
+        );
+        assert_eq!(
+            get(&fixture, "/docs/..%5c..%5cetc", "").status,
+            StatusCode::Forbidden
+        );
+    }
+
+    #[test]
+    fn it_answers_conditional_requests_with_304() {
+        let fixture = fixture("conditional");
+        let response = get(&fixture, "/hello.txt", "");
+        let etag = response.headers.get("ETag").unwrap().to_string();
+        let last_modified = response.headers.get("Last-Modified").unwrap().to_string();
+
+        let response = get(
+            &fixture,
+            "/hello.txt",
+            &format!("If-None-Match: \"x\", {}\r\n", etag),
+        );
+        assert_eq!(response.status, StatusCode::NotModified);
+        assert!(response.body.is_empty());
+
+        let response = get(
+            &fixture,
+            "/hello.txt",
+            &format!("If-Modified-Since: {}\r\n", last_modified),
+        );
+        assert_eq!(response.status, StatusCode::NotModified);
+
+        let response = get(
+            &fixture,
+            "/hello.txt",
+            "If-Modified-Since: Thu, 01 Jan 1970 00:00:00 GMT\r\n",
+        );
+        assert_eq!(response.status, StatusCode::Ok);
+
+        // If-None-Match decides on its own when present.
+        let headers = format!(
+            "If-None-Match: \"other\"\r\nIf-Modified-Since: {}\r\n",
+            last_modified
+        );
+        assert_eq!(get(&fixture, "/hello.txt", &headers).status, StatusCode::Ok);
+    }
+
+    #[test]
+    fn it_serves_byte_ranges() {
+        let fixture = fixture("ranges");
+
+        let response = get(&fixture, "/hello.txt", "Range: bytes=0-4\r\n");
+        assert_eq!(response.status, StatusCode::PartialContent);
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes 0-4/13"));
+        assert_eq!(body(response), b"Hello");
+
+        let response = get(&fixture, "/hello.txt", "Range: bytes=-6\r\n");
+        assert_eq!(body(response), b"world!");
+
+        let response = get(&fixture, "/hello.txt", "Range: bytes=7-100\r\n");
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes 7-12/13"));
+
+        let response = get(&fixture, "/hello.txt", "Range: bytes=13-\r\n");
+        assert_eq!(response.status, StatusCode::RangeNotSatisfiable);
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes */13"));
+
+        let response = get(&fixture, "/hello.txt", "Range: bytes=0-1, 4-5\r\n");
+        assert_eq!(response.status, StatusCode::Ok);
+
+        let response = get(
+            &fixture,
+            "/hello.txt",
+            "Range: bytes=0-4\r\nIf-Range: \"stale\"\r\n",
+        );
+        assert_eq!(response.status, StatusCode::Ok);
+    }
+}
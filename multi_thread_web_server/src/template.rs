@@ -0,0 +1,516 @@
+use crate::http::{Response, StatusCode};
+use crate::static_files::sanitize;
+use parse::{Condition, Node, Template};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    error, fmt, fs, io,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+
+mod parse;
+
+/// How deep includes and layouts may nest, which stops templates including each other forever.
+const MAX_DEPTH: usize = 16;
+
+/// HTML templates read from a directory, compiled the first time they are rendered and cached
+/// from then on. With `auto_reload`, on in debug builds, a template whose file changed since is
+/// compiled again, so pages can be edited without restarting the server.
+///
+/// Templates are rendered with any `Serialize` value as their data:
+///
+/// ```text
+/// {% extends "layout.html" %}
+/// {# Only the blocks of a template extending a layout are rendered, in place of the layout's. #}
+/// {% block content %}
+///   <h1>{{ user.name }}</h1>
+///   {% if not user.verified %}<p>Please check your inbox.</p>{% endif %}
+///   <ul>
+///   {% for order in user.orders %}
+///     <li>{{ loop.index }}. {{ order.item }}</li>
+///   {% else %}
+///     <li>No orders yet.</li>
+///   {% endfor %}
+///   </ul>
+///   {% include "footer.html" %}
+/// {% endblock %}
+/// ```
+///
+/// Values are HTML-escaped unless written `{{ value | raw }}`. Missing values render as nothing
+/// and are false in conditions, as are `false`, `0`, empty strings and empty lists. Inside a loop,
+/// `loop.index` counts from 1 and `loop.first` and `loop.last` tell the ends apart.
+pub struct Templates {
+    root: PathBuf,
+    auto_reload: bool,
+    cache: RwLock<HashMap<String, Cached>>,
+}
+
+struct Cached {
+    template: Arc<Template>,
+    /// When the file was last modified as of compiling it.
+    modified: Option<SystemTime>,
+}
+
+impl Templates {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Templates {
+            root: root.into(),
+            auto_reload: cfg!(debug_assertions),
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Whether templates are compiled again when their file changes. Checking costs reading the
+    /// file's metadata on every render, so it defaults to debug builds only.
+    pub fn auto_reload(mut self, auto_reload: bool) -> Self {
+        self.auto_reload = auto_reload;
+        self
+    }
+
+    /// Renders the template `name`, a path relative to the template directory, with `data`.
+    pub fn render<T: Serialize + ?Sized>(
+        &self,
+        name: &str,
+        data: &T,
+    ) -> Result<String, TemplateError> {
+        let data = serde_json::to_value(data).map_err(|e| TemplateError::Render {
+            name: String::from(name),
+            message: e.to_string(),
+        })?;
+
+        let mut out = String::new();
+        let mut scope = Scope {
+            data: &data,
+            locals: Vec::new(),
+        };
+        self.render_into(name, &mut scope, &mut out, 0)?;
+        Ok(out)
+    }
+
+    /// Renders `name` into an HTML response, or logs why it couldn't and answers with a 500.
+    pub fn response<T: Serialize + ?Sized>(
+        &self,
+        status: StatusCode,
+        name: &str,
+        data: &T,
+    ) -> Response {
+        match self.render(name, data) {
+            Ok(html) => Response::html(status, html),
+            Err(e) => {
+                log::error!("{}", e);
+                Response::text(StatusCode::InternalServerError, "Internal Server Error")
+            }
+        }
+    }
+
+    fn render_into(
+        &self,
+        name: &str,
+        scope: &mut Scope,
+        out: &mut String,
+        depth: usize,
+    ) -> Result<(), TemplateError> {
+        // The template, then the layouts it extends, a block being taken from the first one
+        // defining it.
+        let mut chain = vec![self.load(name)?];
+        while let Some(layout) = chain.last().and_then(|template| template.extends.clone()) {
+            if depth + chain.len() > MAX_DEPTH {
+                return Err(too_deep(name));
+            }
+            chain.push(self.load(&layout)?);
+        }
+
+        let mut blocks = HashMap::new();
+        for template in &chain {
+            for (block, nodes) in &template.blocks {
+                blocks.entry(block.as_str()).or_insert(nodes.as_slice());
+            }
+        }
+
+        let renderer = Renderer {
+            templates: self,
+            name,
+            blocks: &blocks,
+            depth: depth + chain.len() - 1,
+        };
+        renderer.render(&chain[chain.len() - 1].nodes, scope, out)
+    }
+
+    /// The compiled template `name`, from the cache unless it changed since.
+    fn load(&self, name: &str) -> Result<Arc<Template>, TemplateError> {
+        let io_error = |error| TemplateError::Io {
+            name: String::from(name),
+            error,
+        };
+        let path = sanitize(name)
+            .filter(|relative| relative.as_os_str() != "")
+            .map(|relative| self.root.join(relative))
+            .ok_or_else(|| {
+                io_error(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "not a path within the template directory",
+                ))
+            })?;
+
+        let modified = if self.auto_reload {
+            fs::metadata(&path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        } else {
+            None
+        };
+        if let Some(cached) = self.cache.read().unwrap().get(name) {
+            if !self.auto_reload || cached.modified == modified {
+                return Ok(Arc::clone(&cached.template));
+            }
+            log::debug!("Template {} changed, compiling it again.", name);
+        }
+
+        let source = fs::read_to_string(&path).map_err(io_error)?;
+        let template = Arc::new(parse::parse(&source).map_err(|e| TemplateError::Syntax {
+            name: String::from(name),
+            line: e.line,
+            message: e.message,
+        })?);
+        self.cache.write().unwrap().insert(
+            String::from(name),
+            Cached {
+                template: Arc::clone(&template),
+                modified,
+            },
+        );
+        Ok(template)
+    }
+}
+
+/// The data a template is rendered with, along with the variables of the loops it is in.
+struct Scope<'a> {
+    data: &'a Value,
+    locals: Vec<(String, Value)>,
+}
+
+impl Scope<'_> {
+    fn lookup(&self, path: &[String]) -> Option<&Value> {
+        let (first, rest) = path.split_first()?;
+        let mut value = self
+            .locals
+            .iter()
+            .rev()
+            .find(|(name, _)| name == first)
+            .map(|(_, value)| value)
+            .or_else(|| self.data.get(first))?;
+
+        for segment in rest {
+            value = match value {
+                Value::Array(items) => items.get(segment.parse::<usize>().ok()?)?,
+                _ => value.get(segment)?,
+            };
+        }
+        Some(value)
+    }
+
+    fn holds(&self, condition: &Condition) -> bool {
+        let truthy = match self.lookup(&condition.path) {
+            None | Some(Value::Null) => false,
+            Some(Value::Bool(value)) => *value,
+            Some(Value::Number(number)) => number.as_f64() != Some(0.0),
+            Some(Value::String(text)) => !text.is_empty(),
+            Some(Value::Array(items)) => !items.is_empty(),
+            Some(Value::Object(_)) => true,
+        };
+        truthy != condition.negated
+    }
+}
+
+struct Renderer<'a> {
+    templates: &'a Templates,
+    /// The template being rendered, for error messages.
+    name: &'a str,
+    blocks: &'a HashMap<&'a str, &'a [Node]>,
+    depth: usize,
+}
+
+impl Renderer<'_> {
+    fn render(
+        &self,
+        nodes: &[Node],
+        scope: &mut Scope,
+        out: &mut String,
+    ) -> Result<(), TemplateError> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => out.push_str(text),
+                Node::Value { path, raw } => {
+                    let text = match scope.lookup(path) {
+                        None | Some(Value::Null) => continue,
+                        Some(Value::String(text)) => text.clone(),
+                        Some(Value::Bool(value)) => value.to_string(),
+                        Some(Value::Number(number)) => number.to_string(),
+                        Some(_) => {
+                            return Err(self.error(&format!(
+                                "{} is a list or an object, which can't be printed",
+                                path.join(".")
+                            )))
+                        }
+                    };
+                    if *raw {
+                        out.push_str(&text);
+                    } else {
+                        escape_into(&text, out);
+                    }
+                }
+                Node::If {
+                    branches,
+                    otherwise,
+                } => {
+                    let body = branches
+                        .iter()
+                        .find(|(condition, _)| scope.holds(condition))
+                        .map_or(otherwise, |(_, body)| body);
+                    self.render(body, scope, out)?;
+                }
+                Node::For {
+                    name,
+                    path,
+                    body,
+                    empty,
+                } => {
+                    let items = match scope.lookup(path) {
+                        None | Some(Value::Null) => Vec::new(),
+                        Some(Value::Array(items)) => items.clone(),
+                        Some(_) => {
+                            return Err(self.error(&format!("{} is not a list", path.join("."))))
+                        }
+                    };
+                    if items.is_empty() {
+                        self.render(empty, scope, out)?;
+                    }
+
+                    let count = items.len();
+                    for (i, item) in items.into_iter().enumerate() {
+                        let info = json!({
+                            "index": i + 1,
+                            "first": i == 0,
+                            "last": i + 1 == count,
+                        });
+                        scope.locals.push((String::from("loop"), info));
+                        scope.locals.push((name.clone(), item));
+                        let rendered = self.render(body, scope, out);
+                        scope.locals.truncate(scope.locals.len() - 2);
+                        rendered?;
+                    }
+                }
+                Node::Include(included) => {
+                    if self.depth >= MAX_DEPTH {
+                        return Err(too_deep(self.name));
+                    }
+                    self.templates
+                        .render_into(included, scope, out, self.depth + 1)?;
+                }
+                Node::Block(block) => {
+                    if let Some(nodes) = self.blocks.get(block.as_str()) {
+                        self.render(nodes, scope, out)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn error(&self, message: &str) -> TemplateError {
+        TemplateError::Render {
+            name: String::from(self.name),
+            message: String::from(message),
+        }
+    }
+}
+
+fn too_deep(name: &str) -> TemplateError {
+    TemplateError::Render {
+        name: String::from(name),
+        message: format!(
+            "includes and layouts nest more than {} deep, do they include each other?",
+            MAX_DEPTH
+        ),
+    }
+}
+
+/// Appends `text` with the characters that mean something in HTML replaced by entities, which
+/// makes it safe both in element content and in quoted attribute values.
+fn escape_into(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TemplateError {
+    /// The template file couldn't be read.
+    Io { name: String, error: io::Error },
+    Syntax {
+        name: String,
+        line: usize,
+        message: String,
+    },
+    /// The template doesn't fit the data it was given.
+    Render { name: String, message: String },
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::Io { name, error } => {
+                write!(f, "failed to read template {}: {}", name, error)
+            }
+            TemplateError::Syntax {
+                name,
+                line,
+                message,
+            } => write!(
+                f,
+                "syntax error in template {}, line {}: {}",
+                name, line, message
+            ),
+            TemplateError::Render { name, message } => {
+                write!(f, "failed to render template {}: {}", name, message)
+            }
+        }
+    }
+}
+
+impl error::Error for TemplateError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            TemplateError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs::File, path::Path, time::Duration};
+
+    fn directory(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("server-templates-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (file, contents) in files {
+            fs::write(dir.join(file), contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn it_renders_layouts_includes_loops_and_conditions() {
+        let dir = directory(
+            "render",
+            &[
+                (
+                    "layout.html",
+                    "<title>{% block title %}Site{% endblock %}</title>{% block body %}{% endblock %}{% include \"footer.html\" %}",
+                ),
+                ("footer.html", "<footer>{{ footer }}</footer>"),
+                (
+                    "users.html",
+                    "{% extends \"layout.html\" %}{% block body %}<ul>{% for user in users %}<li{% if loop.last %} class=\"last\"{% endif %}>{{ loop.index }} {{ user.name }}{% if not user.admin %}?{% elif user.badge %}{{ user.badge | raw }}{% else %}!{% endif %}</li>{% else %}<li>nobody</li>{% endfor %}</ul>{% endblock %}",
+                ),
+            ],
+        );
+        let templates = Templates::new(&dir);
+
+        let html = templates
+            .render(
+                "users.html",
+                &json!({
+                    "footer": "Tom & Jerry",
+                    "users": [
+                        {"name": "<script>", "admin": false},
+                        {"name": "Ann", "admin": true, "badge": "<b>*</b>"},
+                        {"name": "Bob", "admin": true},
+                    ],
+                }),
+            )
+            .unwrap();
+        assert_eq!(
+            html,
+            "<title>Site</title><ul><li>1 &lt;script&gt;?</li><li>2 Ann<b>*</b></li><li class=\"last\">3 Bob!</li></ul><footer>Tom &amp; Jerry</footer>"
+        );
+
+        let html = templates.render("users.html", &json!({})).unwrap();
+        assert_eq!(
+            html,
+            "<title>Site</title><ul><li>nobody</li></ul><footer></footer>"
+        );
+
+        assert!(matches!(
+            templates.render("users.html", &json!({"footer": [1]})),
+            Err(TemplateError::Render { .. })
+        ));
+        assert!(matches!(
+            templates.render("../users.html", &()),
+            Err(TemplateError::Io { .. })
+        ));
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn it_compiles_templates_again_once_changed() {
+        let dir = directory(
+            "reload",
+            &[
+                ("page.html", "one"),
+                ("loop.html", "{% include \"loop.html\" %}"),
+            ],
+        );
+        let set = |path: &Path, contents: &str, modified: SystemTime| {
+            fs::write(path, contents).unwrap();
+            File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        };
+        let page = dir.join("page.html");
+        let earlier = SystemTime::now() - Duration::from_secs(60);
+        set(&page, "one", earlier);
+
+        let cached = Templates::new(&dir).auto_reload(false);
+        let reloaded = Templates::new(&dir).auto_reload(true);
+        assert_eq!(cached.render("page.html", &()).unwrap(), "one");
+        assert_eq!(reloaded.render("page.html", &()).unwrap(), "one");
+
+        set(&page, "two", SystemTime::now());
+        assert_eq!(cached.render("page.html", &()).unwrap(), "one");
+        assert_eq!(reloaded.render("page.html", &()).unwrap(), "two");
+
+        set(
+            &page,
+            "{% if %}",
+            SystemTime::now() + Duration::from_secs(60),
+        );
+        assert!(matches!(
+            reloaded.render("page.html", &()),
+            Err(TemplateError::Syntax { line: 1, .. })
+        ));
+        assert!(matches!(
+            cached.render("loop.html", &()),
+            Err(TemplateError::Render { .. })
+        ));
+
+        fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,392 @@
+use std::collections::HashMap;
+
+/// A template compiled from its source.
+#[derive(Debug)]
+pub(crate) struct Template {
+    pub(crate) nodes: Vec<Node>,
+    /// Every block the template defines, by name, nested ones included.
+    pub(crate) blocks: HashMap<String, Vec<Node>>,
+    /// The layout named by `{% extends %}`, if any.
+    pub(crate) extends: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Node {
+    Text(String),
+    /// `{{ path }}`, or `{{ path | raw }}` to skip escaping.
+    Value {
+        path: Vec<String>,
+        raw: bool,
+    },
+    /// `{% if %}`, its `{% elif %}` branches and `{% else %}`.
+    If {
+        branches: Vec<(Condition, Vec<Node>)>,
+        otherwise: Vec<Node>,
+    },
+    /// `{% for name in path %}`, `empty` being what `{% else %}` renders for an empty list.
+    For {
+        name: String,
+        path: Vec<String>,
+        body: Vec<Node>,
+        empty: Vec<Node>,
+    },
+    Include(String),
+    /// Where the block `name` goes, its contents are in `Template::blocks`.
+    Block(String),
+}
+
+/// `path`, or `not path`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Condition {
+    pub(crate) negated: bool,
+    pub(crate) path: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SyntaxError {
+    pub(crate) line: usize,
+    pub(crate) message: String,
+}
+
+enum Token<'a> {
+    Text(&'a str),
+    /// The inside of `{{ }}`, and the line it starts on.
+    Output(&'a str, usize),
+    /// The inside of `{% %}`, and the line it starts on.
+    Tag(&'a str, usize),
+}
+
+/// A tag ending the nodes parsed so far, split into its keyword and the rest.
+struct End<'a> {
+    keyword: &'a str,
+    rest: &'a str,
+    line: usize,
+}
+
+pub(crate) fn parse(source: &str) -> Result<Template, SyntaxError> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?.into_iter(),
+        blocks: HashMap::new(),
+        extends: None,
+    };
+    let (nodes, _) = parser.nodes(&[])?;
+
+    Ok(Template {
+        nodes,
+        blocks: parser.blocks,
+        extends: parser.extends,
+    })
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token<'_>>, SyntaxError> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    let mut line = 1;
+
+    while let Some(start) = rest.find('{') {
+        let close = match rest[start..].get(..2) {
+            Some("{{") => "}}",
+            Some("{%") => "%}",
+            Some("{#") => "#}",
+            _ => {
+                // A lone brace, as in inline CSS or scripts.
+                let (text, after) = rest.split_at(start + 1);
+                tokens.push(Token::Text(text));
+                line += text.matches('\n').count();
+                rest = after;
+                continue;
+            }
+        };
+
+        let text = &rest[..start];
+        if !text.is_empty() {
+            tokens.push(Token::Text(text));
+        }
+        line += text.matches('\n').count();
+
+        let inside = &rest[start + 2..];
+        let end = inside.find(close).ok_or_else(|| SyntaxError {
+            line,
+            message: format!("{} is never closed", &rest[start..start + 2]),
+        })?;
+        match close {
+            "}}" => tokens.push(Token::Output(inside[..end].trim(), line)),
+            "%}" => tokens.push(Token::Tag(inside[..end].trim(), line)),
+            // A comment.
+            _ => {}
+        }
+        line += inside[..end].matches('\n').count();
+        rest = &inside[end + 2..];
+    }
+
+    if !rest.is_empty() {
+        tokens.push(Token::Text(rest));
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: std::vec::IntoIter<Token<'a>>,
+    blocks: HashMap<String, Vec<Node>>,
+    extends: Option<String>,
+}
+
+impl<'a> Parser<'a> {
+    /// Parses nodes up to one of the `until` tags, returned along with them. Reaching the end of
+    /// the source instead is only fine at the top level, when `until` is empty.
+    fn nodes(&mut self, until: &[&str]) -> Result<(Vec<Node>, Option<End<'a>>), SyntaxError> {
+        let mut nodes = Vec::new();
+
+        while let Some(token) = self.tokens.next() {
+            let (tag, line) = match token {
+                Token::Text(text) => {
+                    // Text is split at lone braces, joined back here.
+                    match nodes.last_mut() {
+                        Some(Node::Text(previous)) => previous.push_str(text),
+                        _ => nodes.push(Node::Text(String::from(text))),
+                    }
+                    continue;
+                }
+                Token::Output(expression, line) => {
+                    nodes.push(value(expression, line)?);
+                    continue;
+                }
+                Token::Tag(tag, line) => (tag, line),
+            };
+
+            let (keyword, rest) = match tag.split_once(char::is_whitespace) {
+                Some((keyword, rest)) => (keyword, rest.trim()),
+                None => (tag, ""),
+            };
+            if until.contains(&keyword) {
+                return Ok((
+                    nodes,
+                    Some(End {
+                        keyword,
+                        rest,
+                        line,
+                    }),
+                ));
+            }
+
+            let node = match keyword {
+                "if" => self.conditional(rest, line)?,
+                "for" => self.for_loop(rest, line)?,
+                "include" => Node::Include(quoted(rest, line)?),
+                "extends" => {
+                    if self.extends.is_some() {
+                        return Err(error(line, "a template extends a single layout"));
+                    }
+                    self.extends = Some(quoted(rest, line)?);
+                    continue;
+                }
+                "block" => {
+                    let name = identifier(rest, line)?;
+                    let (body, _) = self.closed(&["endblock"], "block", line)?;
+                    if self.blocks.insert(name.clone(), body).is_some() {
+                        return Err(error(line, &format!("block {} is defined twice", name)));
+                    }
+                    Node::Block(name)
+                }
+                _ => return Err(error(line, &format!("unexpected {{% {} %}}", tag))),
+            };
+            nodes.push(node);
+        }
+
+        Ok((nodes, None))
+    }
+
+    /// Like `nodes`, failing when the source ends before one of the `until` tags.
+    fn closed(
+        &mut self,
+        until: &[&str],
+        opened: &str,
+        line: usize,
+    ) -> Result<(Vec<Node>, End<'a>), SyntaxError> {
+        match self.nodes(until)? {
+            (nodes, Some(end)) => Ok((nodes, end)),
+            (_, None) => Err(error(line, &format!("{{% {} %}} is never closed", opened))),
+        }
+    }
+
+    fn conditional(&mut self, condition: &str, line: usize) -> Result<Node, SyntaxError> {
+        let mut branches = Vec::new();
+        let mut condition = parse_condition(condition, line)?;
+
+        loop {
+            let (body, end) = self.closed(&["elif", "else", "endif"], "if", line)?;
+            branches.push((condition, body));
+            match end.keyword {
+                "elif" => condition = parse_condition(end.rest, end.line)?,
+                "else" => {
+                    let (otherwise, _) = self.closed(&["endif"], "if", line)?;
+                    return Ok(Node::If {
+                        branches,
+                        otherwise,
+                    });
+                }
+                _ => {
+                    return Ok(Node::If {
+                        branches,
+                        otherwise: Vec::new(),
+                    })
+                }
+            }
+        }
+    }
+
+    fn for_loop(&mut self, header: &str, line: usize) -> Result<Node, SyntaxError> {
+        let mut words = header.split_whitespace();
+        let (name, path) = match (words.next(), words.next(), words.next(), words.next()) {
+            (Some(name), Some("in"), Some(path), None) => {
+                (identifier(name, line)?, parse_path(path, line)?)
+            }
+            _ => return Err(error(line, "expected {% for item in items %}")),
+        };
+
+        let (body, end) = self.closed(&["else", "endfor"], "for", line)?;
+        let empty = match end.keyword {
+            "else" => self.closed(&["endfor"], "for", line)?.0,
+            _ => Vec::new(),
+        };
+
+        Ok(Node::For {
+            name,
+            path,
+            body,
+            empty,
+        })
+    }
+}
+
+fn value(expression: &str, line: usize) -> Result<Node, SyntaxError> {
+    let (path, raw) = match expression.split_once('|') {
+        Some((path, filter)) if filter.trim() == "raw" => (path, true),
+        Some((_, filter)) => {
+            return Err(error(
+                line,
+                &format!("unknown filter {:?}, only raw is", filter.trim()),
+            ))
+        }
+        None => (expression, false),
+    };
+
+    Ok(Node::Value {
+        path: parse_path(path.trim(), line)?,
+        raw,
+    })
+}
+
+fn parse_condition(condition: &str, line: usize) -> Result<Condition, SyntaxError> {
+    let (negated, path) = match condition.strip_prefix("not ") {
+        Some(path) => (true, path.trim()),
+        None => (false, condition),
+    };
+
+    Ok(Condition {
+        negated,
+        path: parse_path(path, line)?,
+    })
+}
+
+/// `user.name` or `items.0`.
+fn parse_path(path: &str, line: usize) -> Result<Vec<String>, SyntaxError> {
+    path.split('.')
+        .map(|segment| identifier(segment, line))
+        .collect::<Result<_, _>>()
+        .map_err(|_| error(line, &format!("invalid variable {:?}", path)))
+}
+
+fn identifier(name: &str, line: usize) -> Result<String, SyntaxError> {
+    if name.is_empty()
+        || !name
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-')
+    {
+        return Err(error(line, &format!("invalid name {:?}", name)));
+    }
+    Ok(String::from(name))
+}
+
+/// A template name, in double quotes.
+fn quoted(name: &str, line: usize) -> Result<String, SyntaxError> {
+    name.strip_prefix('"')
+        .and_then(|name| name.strip_suffix('"'))
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+        .ok_or_else(|| error(line, "expected a template name in double quotes"))
+}
+
+fn error(line: usize, message: &str) -> SyntaxError {
+    SyntaxError {
+        line,
+        message: String::from(message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(path: &str) -> Vec<String> {
+        path.split('.').map(String::from).collect()
+    }
+
+    #[test]
+    fn it_parses_nested_tags() {
+        let template = parse(
+            "{% extends \"layout.html\" %}{# ignored #}{% block body %}{% for user in users %}{% if not user.admin %}{{ user.name }}{% else %}{{ user.name | raw }}!{% endif %}{% else %}none{% endfor %}{% endblock %}",
+        )
+        .unwrap();
+
+        assert_eq!(template.extends.as_deref(), Some("layout.html"));
+        assert_eq!(template.nodes, [Node::Block(String::from("body"))]);
+        assert_eq!(
+            template.blocks["body"],
+            [Node::For {
+                name: String::from("user"),
+                path: path("users"),
+                body: vec![Node::If {
+                    branches: vec![(
+                        Condition {
+                            negated: true,
+                            path: path("user.admin"),
+                        },
+                        vec![Node::Value {
+                            path: path("user.name"),
+                            raw: false,
+                        }],
+                    )],
+                    otherwise: vec![
+                        Node::Value {
+                            path: path("user.name"),
+                            raw: true,
+                        },
+                        Node::Text(String::from("!")),
+                    ],
+                }],
+                empty: vec![Node::Text(String::from("none"))],
+            }]
+        );
+
+        assert_eq!(
+            parse("a { b } c").unwrap().nodes,
+            [Node::Text(String::from("a { b } c"))]
+        );
+    }
+
+    #[test]
+    fn it_reports_the_line_of_syntax_errors() {
+        let line = |source: &str| parse(source).unwrap_err().line;
+
+        assert_eq!(line("<p>\n{% if ok %}\n</p>"), 2);
+        assert_eq!(line("\n\n{{ name"), 3);
+        assert_eq!(line("{% if a %}\n{% endfor %}"), 2);
+        assert_eq!(line("{{ a..b }}"), 1);
+        assert_eq!(line("{# a\ncomment #}\n{{ x | upper }}"), 3);
+        assert_eq!(
+            line("{% block a %}{% endblock %}\n{% block a %}{% endblock %}"),
+            2
+        );
+    }
+}
@@ -0,0 +1,252 @@
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+type Callback = Box<dyn FnOnce() + Send + 'static>;
+
+/// How many slots the wheel has. Timers further away than one turn wait for their round.
+const SLOTS: usize = 512;
+
+/// Runs callbacks once a delay has elapsed, all of them from a single thread.
+///
+/// Timers are kept in a hashed timing wheel: time is cut into ticks and each timer sits in the
+/// slot of the tick it is due at, so scheduling one and firing the ones due are both cheap no
+/// matter how many are pending. Callbacks fire at most one tick late.
+///
+/// Cloning a `Timer` is cheap and every clone schedules on the same thread, which stops once the
+/// last clone is dropped. Callbacks still pending then are dropped without being run.
+///
+/// Callbacks run on the timer thread and hold up every timer behind them, they should only hand
+/// work over to another thread.
+#[derive(Clone)]
+pub struct Timer {
+    handle: Arc<Handle>,
+}
+
+struct Handle {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    wheel: Mutex<Wheel>,
+    changed: Condvar,
+}
+
+struct Entry {
+    due: u64,
+    callback: Callback,
+}
+
+struct Wheel {
+    slots: Vec<Vec<Entry>>,
+    /// The last tick whose slot was fired.
+    current: u64,
+    pending: usize,
+    started: Instant,
+    tick: Duration,
+    stopped: bool,
+}
+
+impl Wheel {
+    fn tick_at(&self, instant: Instant) -> u64 {
+        let elapsed = instant.saturating_duration_since(self.started);
+        (elapsed.as_nanos() / self.tick.as_nanos()) as u64
+    }
+
+    fn instant_of(&self, tick: u64) -> Instant {
+        // In nanoseconds, multiplying the tick by a `u32` would wrap after 2^32 ticks.
+        let nanos = self.tick.as_nanos() * u128::from(tick);
+        let offset = Duration::new(
+            (nanos / 1_000_000_000) as u64,
+            (nanos % 1_000_000_000) as u32,
+        );
+        self.started + offset
+    }
+}
+
+impl Timer {
+    /// A timer with a 10 millisecond resolution.
+    pub fn new() -> Self {
+        Timer::with_tick(Duration::from_millis(10))
+    }
+
+    /// Panics if `tick` is zero.
+    pub fn with_tick(tick: Duration) -> Self {
+        assert!(!tick.is_zero(), "the tick of a timer must not be zero");
+
+        let shared = Arc::new(Shared {
+            wheel: Mutex::new(Wheel {
+                slots: (0..SLOTS).map(|_| Vec::new()).collect(),
+                current: 0,
+                pending: 0,
+                started: Instant::now(),
+                tick,
+                stopped: false,
+            }),
+            changed: Condvar::new(),
+        });
+
+        let turning = Arc::clone(&shared);
+        thread::Builder::new()
+            .name(String::from("timer"))
+            .spawn(move || turn(&turning))
+            .expect("failed to spawn the timer thread");
+
+        Timer {
+            handle: Arc::new(Handle { shared }),
+        }
+    }
+
+    /// Runs `callback` on the timer thread once `delay` has elapsed.
+    pub fn schedule<F>(&self, delay: Duration, callback: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let shared = &self.handle.shared;
+        let mut wheel = shared.wheel.lock().unwrap();
+
+        // Rounded up so a timer never fires early, and at least one tick ahead of the one being
+        // fired so it can't be missed.
+        let due = Instant::now() + delay + wheel.tick - Duration::from_nanos(1);
+        let due = wheel.tick_at(due).max(wheel.current + 1);
+        wheel.slots[due as usize % SLOTS].push(Entry {
+            due,
+            callback: Box::new(callback),
+        });
+        wheel.pending += 1;
+
+        shared.changed.notify_one();
+    }
+
+    /// How many callbacks are waiting to run.
+    pub fn pending(&self) -> usize {
+        self.handle.shared.wheel.lock().unwrap().pending
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Timer::new()
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.shared.wheel.lock().unwrap().stopped = true;
+        self.shared.changed.notify_one();
+    }
+}
+
+/// The timer thread: sleeps until the next tick, fires the callbacks due by then, repeats.
+fn turn(shared: &Shared) {
+    let mut wheel = shared.wheel.lock().unwrap();
+
+    loop {
+        if wheel.stopped {
+            return;
+        }
+
+        if wheel.pending == 0 {
+            // Nothing to fire, skip the idle ticks instead of waking up for each of them.
+            wheel.current = wheel.tick_at(Instant::now());
+            wheel = shared.changed.wait(wheel).unwrap();
+            continue;
+        }
+
+        let next = wheel.instant_of(wheel.current + 1);
+        let now = Instant::now();
+        if now < next {
+            wheel = shared.changed.wait_timeout(wheel, next - now).unwrap().0;
+            continue;
+        }
+
+        let mut due = Vec::new();
+        let reached = wheel.tick_at(now);
+        while wheel.current < reached {
+            wheel.current += 1;
+            let current = wheel.current;
+            let slot = &mut wheel.slots[current as usize % SLOTS];
+            let (ready, waiting) = std::mem::take(slot)
+                .into_iter()
+                .partition(|entry| entry.due <= current);
+            *slot = waiting;
+            due.extend(ready);
+
+            if wheel.current + SLOTS as u64 <= reached {
+                // A whole turn has gone by, every slot was looked at already.
+                wheel.current = reached;
+            }
+        }
+        wheel.pending -= due.len();
+
+        drop(wheel);
+        for entry in due {
+            (entry.callback)();
+        }
+        wheel = shared.wheel.lock().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn it_fires_callbacks_in_due_order_and_never_early() {
+        let timer = Timer::with_tick(Duration::from_millis(5));
+        let (sender, receiver) = mpsc::channel();
+        let started = Instant::now();
+
+        for millis in [60, 20, 40] {
+            let sender = sender.clone();
+            timer.schedule(Duration::from_millis(millis), move || {
+                sender.send((millis, started.elapsed())).unwrap();
+            });
+        }
+        assert_eq!(timer.pending(), 3);
+
+        let fired: Vec<_> = receiver.iter().take(3).collect();
+        assert_eq!(
+            fired.iter().map(|(millis, _)| *millis).collect::<Vec<_>>(),
+            vec![20, 40, 60]
+        );
+        for (millis, elapsed) in fired {
+            assert!(elapsed >= Duration::from_millis(millis));
+        }
+        assert_eq!(timer.pending(), 0);
+    }
+
+    #[test]
+    fn it_computes_instants_past_two_to_the_thirty_two_ticks() {
+        let wheel = Wheel {
+            slots: Vec::new(),
+            current: 0,
+            pending: 0,
+            started: Instant::now(),
+            tick: Duration::from_millis(10),
+            stopped: false,
+        };
+        let tick = u64::from(u32::MAX) + 2;
+        let instant = wheel.instant_of(tick);
+        assert_eq!(instant - wheel.started, Duration::from_millis(10 * tick));
+        assert_eq!(wheel.tick_at(instant), tick);
+    }
+
+    #[test]
+    fn it_handles_delays_longer_than_a_turn_of_the_wheel() {
+        let timer = Timer::with_tick(Duration::from_micros(100));
+        let (sender, receiver) = mpsc::channel();
+        let started = Instant::now();
+
+        // 512 slots of 100µs make a turn of about 51ms.
+        timer.schedule(Duration::from_millis(120), move || {
+            sender.send(started.elapsed()).unwrap();
+        });
+
+        let elapsed = receiver.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert!(elapsed >= Duration::from_millis(120));
+    }
+}
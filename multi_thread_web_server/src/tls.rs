@@ -0,0 +1,140 @@
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    ServerConnection, StreamOwned,
+};
+use std::{
+    error, fmt, fs, io,
+    net::TcpStream,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// A TLS connection, it reads and writes plain text and does the encryption underneath.
+pub type TlsStream = StreamOwned<ServerConnection, TcpStream>;
+
+/// The certificate and key HTTPS connections are served with.
+///
+/// ```no_run
+/// use multi_thread_web_server::tls::Tls;
+///
+/// let tls = Tls::from_pem_files("cert.pem", "key.pem").unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Tls {
+    config: Arc<rustls::ServerConfig>,
+}
+
+impl Tls {
+    /// Loads a PEM certificate chain (the server certificate first) and its PEM private key.
+    pub fn from_pem_files(cert: impl AsRef<Path>, key: impl AsRef<Path>) -> Result<Self, TlsError> {
+        let read = |path: &Path| {
+            fs::read(path).map_err(|error| TlsError::Io {
+                path: path.to_path_buf(),
+                error,
+            })
+        };
+
+        Tls::from_pem(&read(cert.as_ref())?, &read(key.as_ref())?)
+    }
+
+    pub fn from_pem(cert: &[u8], key: &[u8]) -> Result<Self, TlsError> {
+        let chain = CertificateDer::pem_slice_iter(cert)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| TlsError::Pem(e.to_string()))?;
+        if chain.is_empty() {
+            return Err(TlsError::NoCertificate);
+        }
+
+        let key = PrivateKeyDer::from_pem_slice(key).map_err(|e| match e {
+            rustls::pki_types::pem::Error::NoItemsFound => TlsError::NoPrivateKey,
+            e => TlsError::Pem(e.to_string()),
+        })?;
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = rustls::ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(TlsError::Rustls)?
+            .with_no_client_auth()
+            .with_single_cert(chain, key)
+            .map_err(TlsError::Rustls)?;
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        Ok(Tls {
+            config: Arc::new(config),
+        })
+    }
+
+    /// Wraps an accepted connection. The handshake happens on the first read or write, within
+    /// whatever timeouts are set on `stream`.
+    pub(crate) fn accept(&self, stream: TcpStream) -> io::Result<TlsStream> {
+        let connection =
+            ServerConnection::new(Arc::clone(&self.config)).map_err(io::Error::other)?;
+        Ok(StreamOwned::new(connection, stream))
+    }
+}
+
+#[derive(Debug)]
+pub enum TlsError {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    Pem(String),
+    NoCertificate,
+    NoPrivateKey,
+    /// The key doesn't match the certificate, or either is of an unsupported kind.
+    Rustls(rustls::Error),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsError::Io { path, error } => {
+                write!(f, "failed to read {}: {}", path.display(), error)
+            }
+            TlsError::Pem(reason) => write!(f, "invalid PEM file: {}", reason),
+            TlsError::NoCertificate => write!(f, "no certificate found in the PEM file"),
+            TlsError::NoPrivateKey => write!(f, "no private key found in the PEM file"),
+            TlsError::Rustls(e) => write!(f, "invalid certificate or key: {}", e),
+        }
+    }
+}
+
+impl error::Error for TlsError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            TlsError::Io { error, .. } => Some(error),
+            TlsError::Rustls(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_loads_a_matching_certificate_and_key() {
+        let generated =
+            rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+        let cert = generated.cert.pem();
+        let key = generated.key_pair.serialize_pem();
+
+        assert!(Tls::from_pem(cert.as_bytes(), key.as_bytes()).is_ok());
+        assert!(matches!(
+            Tls::from_pem(key.as_bytes(), key.as_bytes()),
+            Err(TlsError::NoCertificate)
+        ));
+        assert!(matches!(
+            Tls::from_pem(cert.as_bytes(), cert.as_bytes()),
+            Err(TlsError::NoPrivateKey)
+        ));
+
+        let other = rcgen::KeyPair::generate().unwrap().serialize_pem();
+        assert!(matches!(
+            Tls::from_pem(cert.as_bytes(), other.as_bytes()),
+            Err(TlsError::Rustls(_))
+        ));
+    }
+}
@@ -0,0 +1,514 @@
+use crate::http::{Method, Request, Response, StatusCode};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use frame::{read_frame, write_frame, Opcode};
+use sha1::{Digest, Sha1};
+use std::{
+    error, fmt,
+    io::{self, BufRead, Write},
+    time::{Duration, Instant},
+};
+
+mod frame;
+
+/// Close codes from RFC 6455, section 7.4.1.
+pub const NORMAL_CLOSURE: u16 = 1000;
+pub const GOING_AWAY: u16 = 1001;
+pub const PROTOCOL_ERROR: u16 = 1002;
+pub const INVALID_DATA: u16 = 1007;
+pub const MESSAGE_TOO_BIG: u16 = 1009;
+
+/// Appended to the client's key before hashing it, RFC 6455, section 1.3.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Messages longer than this are sent as several frames.
+const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+/// How long `WebSocket::close` waits for the client to answer the close frame.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What a WebSocket talks over once the connection was upgraded.
+pub(crate) trait Transport: BufRead + Write + Send {
+    /// How long reads may wait, forever when `None`.
+    fn set_read_timeout(&mut self, timeout: Option<Duration>);
+
+    /// Ends the connection on purpose, before it is dropped.
+    fn close(&mut self) -> io::Result<()>;
+}
+
+/// Runs once the handshake response was sent, with the WebSocket the connection became.
+pub(crate) struct Upgrade(Box<dyn FnOnce(WebSocket) + Send + 'static>);
+
+impl Upgrade {
+    pub(crate) fn run(self, socket: WebSocket) {
+        (self.0)(socket)
+    }
+}
+
+impl fmt::Debug for Upgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Upgrade")
+    }
+}
+
+/// Answers a WebSocket handshake, `handler` then gets the connection on the worker that served
+/// the request, and keeps that worker for as long as it runs. Requests that aren't a valid
+/// handshake get a 4xx instead.
+///
+/// `Router::websocket` registers a route doing this.
+pub fn upgrade<F>(request: &Request, handler: F) -> Response
+where
+    F: FnOnce(WebSocket) + Send + 'static,
+{
+    if request.method != Method::Get {
+        return Response::text(StatusCode::MethodNotAllowed, "Method Not Allowed")
+            .with_header("Allow", "GET");
+    }
+    if !request.headers.has_token("Upgrade", "websocket")
+        || !request.headers.has_token("Connection", "upgrade")
+    {
+        return Response::text(StatusCode::UpgradeRequired, "Upgrade Required")
+            .with_header("Upgrade", "websocket")
+            .with_header("Connection", "Upgrade");
+    }
+    if request.headers.get("Sec-WebSocket-Version") != Some("13") {
+        return Response::text(StatusCode::UpgradeRequired, "Unsupported WebSocket version")
+            .with_header("Sec-WebSocket-Version", "13");
+    }
+    let key = match request.headers.get("Sec-WebSocket-Key") {
+        Some(key) if BASE64.decode(key).is_ok_and(|nonce| nonce.len() == 16) => key,
+        _ => return Response::text(StatusCode::BadRequest, "Invalid Sec-WebSocket-Key"),
+    };
+
+    Response::new(StatusCode::SwitchingProtocols)
+        .with_header("Upgrade", "websocket")
+        .with_header("Connection", "Upgrade")
+        .with_header("Sec-WebSocket-Accept", &accept_key(key))
+        .with_upgrade(Upgrade(Box::new(handler)))
+}
+
+/// The `Sec-WebSocket-Accept` value proving the server understood the handshake.
+fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(GUID.as_bytes());
+    BASE64.encode(sha1.finalize())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl From<String> for Message {
+    fn from(text: String) -> Self {
+        Message::Text(text)
+    }
+}
+
+impl From<&str> for Message {
+    fn from(text: &str) -> Self {
+        Message::Text(String::from(text))
+    }
+}
+
+impl From<Vec<u8>> for Message {
+    fn from(bytes: Vec<u8>) -> Self {
+        Message::Binary(bytes)
+    }
+}
+
+#[derive(Debug)]
+pub enum WebSocketError {
+    /// The connection was closed. `code` and `reason` are what the client sent, `code` is `None`
+    /// when it sent none or when the server closed first.
+    Closed {
+        code: Option<u16>,
+        reason: String,
+    },
+    /// The client broke the protocol, the connection was closed with `code`.
+    Protocol {
+        code: u16,
+        reason: &'static str,
+    },
+    /// A message was larger than the limit, the connection was closed with 1009.
+    TooBig,
+    Io(io::Error),
+}
+
+impl WebSocketError {
+    pub(crate) fn protocol(reason: &'static str) -> Self {
+        WebSocketError::Protocol {
+            code: PROTOCOL_ERROR,
+            reason,
+        }
+    }
+
+    fn closed() -> Self {
+        WebSocketError::Closed {
+            code: None,
+            reason: String::new(),
+        }
+    }
+}
+
+impl From<io::Error> for WebSocketError {
+    fn from(e: io::Error) -> Self {
+        WebSocketError::Io(e)
+    }
+}
+
+impl fmt::Display for WebSocketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebSocketError::Closed {
+                code: Some(code),
+                reason,
+            } => write!(f, "connection closed with {} {:?}", code, reason),
+            WebSocketError::Closed { code: None, .. } => write!(f, "connection closed"),
+            WebSocketError::Protocol { code, reason } => {
+                write!(f, "protocol error ({}): {}", code, reason)
+            }
+            WebSocketError::TooBig => write!(f, "message too big"),
+            WebSocketError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for WebSocketError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            WebSocketError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// A connection upgraded to the WebSocket protocol, from the server's side.
+///
+/// Pings are answered and fragmented messages put back together while receiving, the handler
+/// only sees whole text and binary messages. Dropping it closes the connection with 1000 unless
+/// it was closed already.
+///
+/// ```no_run
+/// use multi_thread_web_server::router::Router;
+///
+/// let mut router = Router::new();
+/// router.websocket("/echo", |mut socket, _, _| {
+///     while let Ok(message) = socket.recv() {
+///         if socket.send(message).is_err() {
+///             break;
+///         }
+///     }
+/// });
+/// ```
+pub struct WebSocket {
+    transport: Box<dyn Transport>,
+    max_message_size: usize,
+    /// The message being received when it came in several frames.
+    partial: Option<(Opcode, Vec<u8>)>,
+    /// Whether we sent a close frame.
+    closing: bool,
+    /// Whether the client sent a close frame, or the connection is gone.
+    closed: bool,
+}
+
+impl WebSocket {
+    pub(crate) fn new(transport: Box<dyn Transport>) -> Self {
+        WebSocket {
+            transport,
+            max_message_size: 16 * 1024 * 1024,
+            partial: None,
+            closing: false,
+            closed: false,
+        }
+    }
+
+    /// Larger messages are refused by closing the connection with 1009, 16 MiB by default.
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = size;
+    }
+
+    /// Waits for the next message.
+    pub fn recv(&mut self) -> Result<Message, WebSocketError> {
+        self.receive(None)
+            .map(|message| message.expect("no deadline to miss"))
+    }
+
+    /// Waits up to `timeout` for the next message, `None` if none came by then. Handy to push
+    /// updates at a regular pace while still answering the client's pings.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<Message>, WebSocketError> {
+        self.receive(Some(Instant::now() + timeout))
+    }
+
+    pub fn send(&mut self, message: impl Into<Message>) -> Result<(), WebSocketError> {
+        let (opcode, payload) = match message.into() {
+            Message::Text(text) => (Opcode::Text, text.into_bytes()),
+            Message::Binary(bytes) => (Opcode::Binary, bytes),
+        };
+        if self.closing || self.closed {
+            return Err(WebSocketError::closed());
+        }
+
+        let mut chunks = payload.chunks(MAX_FRAME_SIZE).peekable();
+        let mut opcode = opcode;
+        if chunks.peek().is_none() {
+            write_frame(&mut self.transport, true, opcode, &[])?;
+        }
+        while let Some(chunk) = chunks.next() {
+            write_frame(&mut self.transport, chunks.peek().is_none(), opcode, chunk)?;
+            opcode = Opcode::Continuation;
+        }
+        Ok(())
+    }
+
+    /// Sends a ping, the client's pong is ignored when it comes.
+    pub fn ping(&mut self, payload: &[u8]) -> Result<(), WebSocketError> {
+        if payload.len() > 125 {
+            return Err(WebSocketError::protocol(
+                "ping payloads are at most 125 bytes",
+            ));
+        }
+        write_frame(&mut self.transport, true, Opcode::Ping, payload)?;
+        Ok(())
+    }
+
+    /// Sends a close frame and waits a few seconds for the client to answer it, dropping whatever
+    /// messages it sends meanwhile.
+    pub fn close(mut self, code: u16, reason: &str) -> Result<(), WebSocketError> {
+        self.send_close(Some(code), reason)?;
+
+        let deadline = Instant::now() + CLOSE_TIMEOUT;
+        while !self.closed && Instant::now() < deadline {
+            match self.receive(Some(deadline)) {
+                Ok(_) => {}
+                Err(WebSocketError::Closed { .. }) => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn send_close(&mut self, code: Option<u16>, reason: &str) -> io::Result<()> {
+        if self.closing {
+            return Ok(());
+        }
+        self.closing = true;
+
+        let mut payload = Vec::new();
+        if let Some(code) = code {
+            payload.extend_from_slice(&code.to_be_bytes());
+            // Control frames are limited to 125 bytes, cut the reason at a character boundary.
+            let mut end = reason.len().min(123);
+            while !reason.is_char_boundary(end) {
+                end -= 1;
+            }
+            payload.extend_from_slice(&reason.as_bytes()[..end]);
+        }
+        write_frame(&mut self.transport, true, Opcode::Close, &payload)
+    }
+
+    /// Receives the next message, answering the control frames that come before it. Returns
+    /// `None` once `deadline` passes without a frame starting.
+    fn receive(&mut self, deadline: Option<Instant>) -> Result<Option<Message>, WebSocketError> {
+        if self.closed {
+            return Err(WebSocketError::closed());
+        }
+
+        loop {
+            if !self.wait(deadline)? {
+                return Ok(None);
+            }
+
+            let frame = match read_frame(&mut self.transport, self.max_message_size) {
+                Ok(frame) => frame,
+                Err(e) => return Err(self.fail(e)),
+            };
+
+            match frame.opcode {
+                Opcode::Ping => {
+                    if !self.closing {
+                        write_frame(&mut self.transport, true, Opcode::Pong, &frame.payload)?;
+                    }
+                }
+                Opcode::Pong => {}
+                Opcode::Close => return Err(self.closed_by_client(&frame.payload)),
+                Opcode::Text | Opcode::Binary if self.partial.is_some() => {
+                    return Err(self.fail(WebSocketError::protocol(
+                        "a new message started before the previous one ended",
+                    )));
+                }
+                Opcode::Text | Opcode::Binary if frame.fin => {
+                    return self.message(frame.opcode, frame.payload).map(Some);
+                }
+                Opcode::Text | Opcode::Binary => self.partial = Some((frame.opcode, frame.payload)),
+                Opcode::Continuation => {
+                    let (opcode, mut payload) = match self.partial.take() {
+                        Some(partial) => partial,
+                        None => {
+                            return Err(self.fail(WebSocketError::protocol(
+                                "continuation frame without a message to continue",
+                            )))
+                        }
+                    };
+                    if payload.len() + frame.payload.len() > self.max_message_size {
+                        return Err(self.fail(WebSocketError::TooBig));
+                    }
+                    payload.extend_from_slice(&frame.payload);
+
+                    if frame.fin {
+                        return self.message(opcode, payload).map(Some);
+                    }
+                    self.partial = Some((opcode, payload));
+                }
+            }
+        }
+    }
+
+    /// Waits for the next frame to start, returns `false` if `deadline` passed first. The rest of
+    /// a frame is read without a timeout once it has started.
+    fn wait(&mut self, deadline: Option<Instant>) -> Result<bool, WebSocketError> {
+        let timeout = match deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Ok(false);
+                }
+                Some(remaining)
+            }
+            None => None,
+        };
+
+        self.transport.set_read_timeout(timeout);
+        let started = match self.transport.fill_buf() {
+            Ok([]) => {
+                // Gone without a close frame.
+                self.closing = true;
+                self.closed = true;
+                return Err(WebSocketError::closed());
+            }
+            Ok(_) => true,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                false
+            }
+            Err(e) => return Err(e.into()),
+        };
+        self.transport.set_read_timeout(None);
+        Ok(started)
+    }
+
+    fn message(&mut self, opcode: Opcode, payload: Vec<u8>) -> Result<Message, WebSocketError> {
+        match opcode {
+            Opcode::Text => match String::from_utf8(payload) {
+                Ok(text) => Ok(Message::Text(text)),
+                Err(_) => Err(self.fail(WebSocketError::Protocol {
+                    code: INVALID_DATA,
+                    reason: "text message is not valid UTF-8",
+                })),
+            },
+            _ => Ok(Message::Binary(payload)),
+        }
+    }
+
+    /// Answers the client's close frame with the same code, as the closing handshake goes.
+    fn closed_by_client(&mut self, payload: &[u8]) -> WebSocketError {
+        let (code, reason) = match payload {
+            [] => (None, String::new()),
+            [_] => return self.fail(WebSocketError::protocol("truncated close code")),
+            [high, low, reason @ ..] => {
+                let code = u16::from_be_bytes([*high, *low]);
+                if !is_valid_close_code(code) {
+                    return self.fail(WebSocketError::protocol("invalid close code"));
+                }
+                match String::from_utf8(reason.to_vec()) {
+                    Ok(reason) => (Some(code), reason),
+                    Err(_) => {
+                        return self.fail(WebSocketError::Protocol {
+                            code: INVALID_DATA,
+                            reason: "close reason is not valid UTF-8",
+                        })
+                    }
+                }
+            }
+        };
+
+        self.send_close(code, "").ok();
+        self.closed = true;
+        WebSocketError::Closed { code, reason }
+    }
+
+    /// Closes the connection with the code `e` calls for, and returns it.
+    fn fail(&mut self, e: WebSocketError) -> WebSocketError {
+        let code = match &e {
+            WebSocketError::Protocol { code, .. } => *code,
+            WebSocketError::TooBig => MESSAGE_TOO_BIG,
+            _ => {
+                // The connection itself failed, nothing more can be sent on it.
+                self.closing = true;
+                self.closed = true;
+                return e;
+            }
+        };
+
+        self.send_close(Some(code), "").ok();
+        self.closed = true;
+        e
+    }
+}
+
+impl Drop for WebSocket {
+    fn drop(&mut self) {
+        if !self.closing && !self.closed {
+            self.send_close(Some(NORMAL_CLOSURE), "").ok();
+        }
+        self.transport.close().ok();
+    }
+}
+
+/// Codes a client may send, the others are reserved or only meant for the API.
+fn is_valid_close_code(code: u16) -> bool {
+    matches!(code, 1000..=1003 | 1007..=1011 | 3000..=4999)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_computes_the_accept_key() {
+        // The example from RFC 6455, section 1.3.
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn it_answers_only_valid_handshakes() {
+        let handshake = |extra: &str| {
+            let raw = format!("GET /ws HTTP/1.1\r\nHost: example.com\r\n{}\r\n", extra);
+            let request = Request::parse(&mut raw.as_bytes()).unwrap();
+            upgrade(&request, |_| {})
+        };
+        let valid = "Upgrade: websocket\r\nConnection: keep-alive, Upgrade\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n";
+
+        let response = handshake(valid);
+        assert_eq!(response.status, StatusCode::SwitchingProtocols);
+        assert_eq!(
+            response.headers.get("Sec-WebSocket-Accept"),
+            Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+        );
+
+        assert_eq!(handshake("").status, StatusCode::UpgradeRequired);
+        let response = handshake(&valid.replace("Version: 13", "Version: 8"));
+        assert_eq!(response.status, StatusCode::UpgradeRequired);
+        assert_eq!(response.headers.get("Sec-WebSocket-Version"), Some("13"));
+        let response = handshake(&valid.replace("dGhlIHNhbXBsZSBub25jZQ==", "c2hvcnQ="));
+        assert_eq!(response.status, StatusCode::BadRequest);
+    }
+}
@@ -0,0 +1,233 @@
+use super::WebSocketError;
+use std::io::{self, Read, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_bits(bits: u8) -> Option<Opcode> {
+        match bits {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xA => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA,
+        }
+    }
+
+    pub(crate) fn is_control(self) -> bool {
+        matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Frame {
+    pub(crate) fin: bool,
+    pub(crate) opcode: Opcode,
+    pub(crate) payload: Vec<u8>,
+}
+
+/// Reads a frame sent by a client, which must be masked, and unmasks its payload.
+/// Payloads larger than `max_size` are refused before being read.
+pub(crate) fn read_frame<R: Read>(
+    reader: &mut R,
+    max_size: usize,
+) -> Result<Frame, WebSocketError> {
+    let mut head = [0; 2];
+    reader.read_exact(&mut head)?;
+
+    let fin = head[0] & 0x80 != 0;
+    if head[0] & 0x70 != 0 {
+        // No extension was negotiated that would give them a meaning.
+        return Err(WebSocketError::protocol("reserved bits set"));
+    }
+    let opcode =
+        Opcode::from_bits(head[0] & 0x0F).ok_or(WebSocketError::protocol("unknown opcode"))?;
+    let masked = head[1] & 0x80 != 0;
+
+    let length = match head[1] & 0x7F {
+        126 => {
+            let mut length = [0; 2];
+            reader.read_exact(&mut length)?;
+            u64::from(u16::from_be_bytes(length))
+        }
+        127 => {
+            let mut length = [0; 8];
+            reader.read_exact(&mut length)?;
+            let length = u64::from_be_bytes(length);
+            if length >> 63 != 0 {
+                return Err(WebSocketError::protocol("payload length out of range"));
+            }
+            length
+        }
+        length => u64::from(length),
+    };
+
+    if opcode.is_control() && (length > 125 || !fin) {
+        return Err(WebSocketError::protocol(
+            "control frames must be short and unfragmented",
+        ));
+    }
+    if !masked {
+        return Err(WebSocketError::protocol("client frames must be masked"));
+    }
+    if length > max_size as u64 {
+        return Err(WebSocketError::TooBig);
+    }
+
+    let mut key = [0; 4];
+    reader.read_exact(&mut key)?;
+    let mut payload = vec![0; length as usize];
+    reader.read_exact(&mut payload)?;
+    apply_mask(&mut payload, key);
+
+    Ok(Frame {
+        fin,
+        opcode,
+        payload,
+    })
+}
+
+/// Writes a frame the way a server sends them, unmasked.
+pub(crate) fn write_frame<W: Write>(
+    writer: &mut W,
+    fin: bool,
+    opcode: Opcode,
+    payload: &[u8],
+) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(if fin { 0x80 } else { 0 } | opcode.bits());
+    match payload.len() {
+        length @ 0..=125 => frame.push(length as u8),
+        length @ 126..=0xFFFF => {
+            frame.push(126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+
+    writer.write_all(&frame)?;
+    writer.flush()
+}
+
+/// XORs `payload` with `key`, which masks and unmasks alike.
+pub(crate) fn apply_mask(payload: &mut [u8], key: [u8; 4]) {
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= key[i % 4];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A frame as a client sends it.
+    fn masked(first: u8, payload: &[u8]) -> Vec<u8> {
+        let key = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = vec![first];
+        match payload.len() {
+            length @ 0..=125 => frame.push(0x80 | length as u8),
+            length => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&key);
+        let mut payload = payload.to_vec();
+        apply_mask(&mut payload, key);
+        frame.extend_from_slice(&payload);
+        frame
+    }
+
+    fn read(bytes: &[u8]) -> Result<Frame, WebSocketError> {
+        read_frame(&mut &bytes[..], 1024)
+    }
+
+    #[test]
+    fn it_unmasks_client_frames() {
+        // The masked "Hello" from RFC 6455, section 5.7.
+        let frame = read(&[
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ])
+        .unwrap();
+        assert_eq!(
+            frame,
+            Frame {
+                fin: true,
+                opcode: Opcode::Text,
+                payload: b"Hello".to_vec(),
+            }
+        );
+
+        let long = vec![b'x'; 300];
+        let frame = read(&masked(0x02, &long)).unwrap();
+        assert_eq!((frame.fin, frame.opcode), (false, Opcode::Binary));
+        assert_eq!(frame.payload, long);
+    }
+
+    #[test]
+    fn it_refuses_frames_breaking_the_protocol() {
+        let protocol_error = |bytes: &[u8]| {
+            assert!(
+                matches!(
+                    read(bytes),
+                    Err(WebSocketError::Protocol { code: 1002, .. })
+                ),
+                "{:?}",
+                bytes
+            )
+        };
+        // Unmasked.
+        protocol_error(&[0x81, 0x05, b'H', b'e', b'l', b'l', b'o']);
+        // Reserved bits, unknown opcode, fragmented ping, oversized close.
+        protocol_error(&masked(0xC1, b"Hello"));
+        protocol_error(&masked(0x83, b""));
+        protocol_error(&masked(0x09, b""));
+        protocol_error(&masked(0x88, &[0; 126]));
+
+        assert!(matches!(
+            read(&masked(0x82, &[0; 2000])),
+            Err(WebSocketError::TooBig)
+        ));
+    }
+
+    #[test]
+    fn it_writes_unmasked_frames_with_the_shortest_length() {
+        let written = |payload: &[u8]| {
+            let mut out = Vec::new();
+            write_frame(&mut out, true, Opcode::Binary, payload).unwrap();
+            out
+        };
+
+        assert_eq!(written(b"Hi"), [0x82, 0x02, b'H', b'i']);
+        assert_eq!(written(&[0; 126])[..4], [0x82, 126, 0x00, 0x7E]);
+        assert_eq!(
+            written(&[0; 70_000])[..10],
+            [0x82, 127, 0, 0, 0, 0, 0, 0x01, 0x11, 0x70]
+        );
+    }
+}
@@ -0,0 +1,986 @@
+use multi_thread_web_server::access_log::{AccessLog, LogFormat};
+use multi_thread_web_server::http::chunked::ChunkedReader;
+use multi_thread_web_server::http::{Deferred, Headers, Limits, Response, StatusCode};
+use multi_thread_web_server::middleware::{Cors, RequestId};
+use multi_thread_web_server::proxy::Proxy;
+use multi_thread_web_server::router::Router;
+use multi_thread_web_server::server::{KeepAlive, Server, ShutdownReport, Timeouts, UnknownHosts};
+use multi_thread_web_server::tls::Tls;
+use multi_thread_web_server_pool::{Overflow, WorkerPool};
+use rustls::{
+    pki_types::CertificateDer, ClientConfig, ClientConnection, RootCertStore, StreamOwned,
+};
+use serde::Serialize;
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{mpsc, Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+#[derive(Serialize)]
+struct Sum {
+    count: usize,
+    total: i64,
+}
+
+fn router() -> Router {
+    let mut router = Router::new();
+    router
+        .get("/", |_, _| Response::text(StatusCode::Ok, "home"))
+        .get("/slow/:millis", |_, params| {
+            thread::sleep(Duration::from_millis(params.get("millis").unwrap()));
+            Response::text(StatusCode::Ok, "done")
+        })
+        .get("/sleep/:millis", |_, params| {
+            let delay = Duration::from_millis(params.get("millis").unwrap());
+            Response::deferred(Deferred::after(delay, || {
+                Response::text(StatusCode::Ok, "rested")
+            }))
+        })
+        .post("/sums", |request, _| match request.json::<Vec<i64>>() {
+            Ok(numbers) => Response::json(
+                StatusCode::Ok,
+                &Sum {
+                    count: numbers.len(),
+                    total: numbers.iter().sum(),
+                },
+            ),
+            Err(e) => e.into(),
+        })
+        .websocket("/ws/echo", |mut socket, _, _| {
+            while let Ok(message) = socket.recv() {
+                if socket.send(message).is_err() {
+                    break;
+                }
+            }
+        })
+        .websocket("/ws/ticks/:count", |mut socket, _, params| {
+            // Pushes updates on its own pace, stopping early when told so.
+            for tick in 0..params.get::<usize>("count").unwrap() {
+                match socket.recv_timeout(Duration::from_millis(20)) {
+                    Ok(None) => socket.send(format!("tick {}", tick)).unwrap(),
+                    _ => return,
+                }
+            }
+            socket.close(1000, "done").unwrap();
+        })
+        .get("/later", |_, _| {
+            let (deferred, completer) = Deferred::channel();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                completer.complete(Response::text(StatusCode::Ok, "completed"));
+            });
+            Response::deferred(deferred)
+        })
+        .get("/users/:id", |_, params| {
+            Response::text(
+                StatusCode::Ok,
+                format!("user {}", params.raw("id").unwrap()),
+            )
+        });
+    router
+}
+
+/// Starts a server on a random local port, it keeps running until the test binary exits.
+fn start(server: Server) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || server.run(listener));
+    address
+}
+
+/// Like `start`, but keeps the server thread around to collect its shutdown report.
+fn start_joinable(server: Server) -> (SocketAddr, JoinHandle<ShutdownReport>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    (address, thread::spawn(move || server.run(listener)))
+}
+
+struct TestResponse {
+    code: u16,
+    headers: Headers,
+    body: String,
+}
+
+fn read_response<R: BufRead>(reader: &mut R) -> TestResponse {
+    let mut response = read_response_head(reader);
+    let length = response
+        .headers
+        .get("Content-Length")
+        .map_or(0, |length| length.parse().unwrap());
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+
+    response.body = String::from_utf8(body).unwrap();
+    response
+}
+
+/// Reads a response to a HEAD request, which has no body whatever its `Content-Length` says.
+fn read_response_head<R: BufRead>(reader: &mut R) -> TestResponse {
+    let mut status = String::new();
+    reader.read_line(&mut status).unwrap();
+    let code = status.split(' ').nth(1).unwrap().parse().unwrap();
+
+    let mut headers = Headers::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':').unwrap();
+        headers.append(name, value.trim());
+    }
+
+    TestResponse {
+        code,
+        headers,
+        body: String::new(),
+    }
+}
+
+/// Whether the server closed its side of the connection.
+fn is_closed(reader: &mut BufReader<TcpStream>) -> bool {
+    reader
+        .get_ref()
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    matches!(reader.read(&mut [0; 1]), Ok(0))
+}
+
+#[test]
+fn it_serves_pipelined_requests_on_one_connection() {
+    let address = start(Server::new(router(), 2));
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\n\r\nGET /users/7 HTTP/1.1\r\n\r\nGET /nope HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+
+    let mut reader = BufReader::new(stream);
+    let first = read_response(&mut reader);
+    let second = read_response(&mut reader);
+    let third = read_response(&mut reader);
+
+    assert_eq!((first.code, first.body.as_str()), (200, "home"));
+    assert_eq!((second.code, second.body.as_str()), (200, "user 7"));
+    assert_eq!(third.code, 404);
+    assert_eq!(third.headers.get("Connection"), Some("close"));
+    assert!(is_closed(&mut reader));
+}
+
+#[test]
+fn it_closes_http_1_0_connections_unless_asked_to_keep_them() {
+    let address = start(Server::new(router(), 2));
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+    let mut reader = BufReader::new(stream);
+    assert_eq!(read_response(&mut reader).code, 200);
+    assert!(is_closed(&mut reader));
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+        .unwrap();
+    let mut reader = BufReader::new(stream);
+    let response = read_response(&mut reader);
+    assert_eq!(response.headers.get("Connection"), Some("keep-alive"));
+
+    reader
+        .get_mut()
+        .write_all(b"GET /users/1 HTTP/1.0\r\n\r\n")
+        .unwrap();
+    assert_eq!(read_response(&mut reader).body, "user 1");
+    assert!(is_closed(&mut reader));
+}
+
+#[test]
+fn it_closes_after_the_request_limit_and_the_idle_timeout() {
+    let keep_alive = KeepAlive {
+        idle_timeout: Duration::from_millis(200),
+        max_requests: 2,
+    };
+    let address = start(Server::new(router(), 2).keep_alive(keep_alive));
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n")
+        .unwrap();
+    let mut reader = BufReader::new(stream);
+    assert_eq!(read_response(&mut reader).headers.get("Connection"), None);
+    assert_eq!(
+        read_response(&mut reader).headers.get("Connection"),
+        Some("close")
+    );
+    assert!(is_closed(&mut reader));
+
+    let stream = TcpStream::connect(address).unwrap();
+    let mut reader = BufReader::new(stream);
+    thread::sleep(Duration::from_millis(400));
+    assert!(is_closed(&mut reader));
+}
+
+#[test]
+fn it_answers_malformed_requests_with_400_and_closes() {
+    let address = start(Server::new(router(), 1));
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(b"GARBAGE\r\n\r\n").unwrap();
+
+    let mut reader = BufReader::new(stream);
+    assert_eq!(read_response(&mut reader).code, 400);
+    assert!(is_closed(&mut reader));
+
+    // The worker survived and keeps serving.
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    assert_eq!(read_response(&mut BufReader::new(stream)).code, 200);
+}
+
+#[test]
+fn it_lets_clients_read_the_413_while_they_are_still_sending_the_body() {
+    let limits = Limits {
+        max_body_size: 1024,
+        ..Limits::default()
+    };
+    let address = start(Server::new(router(), 1).limits(limits));
+
+    for _ in 0..5 {
+        let mut stream = TcpStream::connect(address).unwrap();
+        let mut head = b"POST /upload HTTP/1.1\r\nContent-Length: 262144\r\n\r\n".to_vec();
+        head.extend_from_slice(&[b'a'; 16 * 1024]);
+        stream.write_all(&head).unwrap();
+        // The server answers without reading the body, which keeps coming.
+        let mut sending = stream.try_clone().unwrap();
+        let sender = thread::spawn(move || {
+            sending.write_all(&[b'a'; 240 * 1024]).ok();
+            sending.shutdown(std::net::Shutdown::Write).ok();
+        });
+
+        let mut reader = BufReader::new(stream);
+        let response = read_response(&mut reader);
+        assert_eq!(response.code, 413);
+        assert_eq!(response.headers.get("Connection"), Some("close"));
+        assert!(is_closed(&mut reader));
+        sender.join().unwrap();
+    }
+}
+
+#[test]
+fn it_answers_clients_too_slow_to_send_their_request_with_408() {
+    let timeouts = Timeouts {
+        header: Duration::from_millis(200),
+        read: Duration::from_millis(200),
+        write: Duration::from_secs(1),
+    };
+    let address = start(Server::new(router(), 1).timeouts(timeouts));
+
+    // Each header field arrives well within the read timeout, the whole head doesn't.
+    let started = Instant::now();
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+    for field in ["X-A: 1\r\n", "X-B: 2\r\n"] {
+        thread::sleep(Duration::from_millis(100));
+        stream.write_all(field.as_bytes()).ok();
+    }
+    let response = read_response(&mut BufReader::new(stream));
+    assert_eq!(response.code, 408);
+    assert_eq!(response.headers.get("Connection"), Some("close"));
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    // The only worker was freed, and a body that stalls is refused the same way.
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(b"POST /upload HTTP/1.1\r\nContent-Length: 10\r\n\r\nab")
+        .unwrap();
+    assert_eq!(read_response(&mut BufReader::new(stream)).code, 408);
+}
+
+#[test]
+fn it_serves_many_deferred_responses_with_a_small_pool() {
+    let address = start(Server::new(router(), 2));
+    let started = Instant::now();
+
+    // Sleeping on the workers, 2 of them would take 10 rounds of 300ms.
+    let clients: Vec<_> = (0..20)
+        .map(|_| {
+            thread::spawn(move || {
+                let mut stream = TcpStream::connect(address).unwrap();
+                stream
+                    .write_all(b"GET /sleep/300 HTTP/1.1\r\nConnection: close\r\n\r\n")
+                    .unwrap();
+                read_response(&mut BufReader::new(stream))
+            })
+        })
+        .collect();
+    for client in clients {
+        let response = client.join().unwrap();
+        assert_eq!((response.code, response.body.as_str()), (200, "rested"));
+    }
+
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(300));
+    assert!(elapsed < Duration::from_millis(1500), "took {:?}", elapsed);
+}
+
+#[test]
+fn it_answers_503_once_the_worker_queue_is_full() {
+    let pool = WorkerPool::builder(1)
+        .capacity(1)
+        .overflow(Overflow::Reject)
+        .build();
+    let address = start(Server::with_pool(router(), pool));
+
+    // One connection keeps the only worker busy, the next one waits in the queue.
+    let mut busy = TcpStream::connect(address).unwrap();
+    busy.write_all(b"GET /slow/500 HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    thread::sleep(Duration::from_millis(100));
+    let mut queued = TcpStream::connect(address).unwrap();
+    queued
+        .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    let mut turned_down = BufReader::new(TcpStream::connect(address).unwrap());
+    let response = read_response(&mut turned_down);
+    assert_eq!(response.code, 503);
+    assert_eq!(response.headers.get("Connection"), Some("close"));
+    assert!(is_closed(&mut turned_down));
+
+    assert_eq!(read_response(&mut BufReader::new(busy)).body, "done");
+    assert_eq!(read_response(&mut BufReader::new(queued)).body, "home");
+}
+
+#[test]
+fn it_keeps_serving_the_connection_after_a_deferred_response() {
+    let address = start(Server::new(router(), 1).middleware(RequestId::new()));
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(
+            b"GET /later HTTP/1.1\r\n\r\nHEAD /sleep/10 HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n",
+        )
+        .unwrap();
+    let mut reader = BufReader::new(stream);
+
+    let completed = read_response(&mut reader);
+    assert_eq!(completed.body, "completed");
+    assert!(completed.headers.contains("X-Request-Id"));
+
+    // The HEAD response announces the body without sending it, or the next one would be garbled.
+    let head = read_response_head(&mut reader);
+    assert_eq!(head.headers.get("Content-Length"), Some("6"));
+    assert_eq!(read_response(&mut reader).body, "home");
+}
+
+/// Sends `request` on a new connection and reads the response.
+fn send(address: SocketAddr, request: &str) -> TestResponse {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    read_response(&mut BufReader::new(stream))
+}
+
+#[test]
+fn it_reports_health_and_metrics() {
+    let server = Server::new(router(), 2)
+        .health_route("/health")
+        .metrics_route("/metrics");
+    let shutdown = server.shutdown_handle();
+    let (address, running) = start_joinable(server);
+
+    let health = send(address, "GET /health HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert_eq!(health.code, 200);
+    assert_eq!(
+        health.body,
+        r#"{"status":"ready","workers":2,"listening":true}"#
+    );
+
+    send(
+        address,
+        "GET /users/1 HTTP/1.1\r\nConnection: close\r\n\r\n",
+    );
+    send(
+        address,
+        "GET /users/2 HTTP/1.1\r\nConnection: close\r\n\r\n",
+    );
+    send(address, "GET /nope HTTP/1.1\r\nConnection: close\r\n\r\n");
+
+    // Requests are counted once their response is written, the client may get there first.
+    let deadline = Instant::now() + Duration::from_secs(2);
+    let metrics = loop {
+        let metrics = send(
+            address,
+            "GET /metrics HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+        let counted = [
+            r#"http_requests_total{route="/users/:id",method="GET",status="200"} 2"#,
+            r#"http_requests_total{route="unmatched",method="GET",status="404"} 1"#,
+        ];
+        if counted.iter().all(|line| metrics.body.contains(line)) || Instant::now() > deadline {
+            break metrics;
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    assert_eq!(metrics.code, 200);
+    assert!(metrics
+        .headers
+        .get("Content-Type")
+        .unwrap()
+        .starts_with("text/plain; version=0.0.4"));
+    let lines: Vec<_> = metrics.body.lines().collect();
+    for expected in [
+        r#"http_requests_total{route="/users/:id",method="GET",status="200"} 2"#,
+        r#"http_requests_total{route="unmatched",method="GET",status="404"} 1"#,
+        r#"http_request_duration_seconds_count{route="/users/:id"} 2"#,
+        "worker_pool_workers 2",
+        // The worker answering this very request.
+        "worker_pool_busy_workers 1",
+        "worker_pool_queued_tasks 0",
+    ] {
+        assert!(
+            lines.contains(&expected),
+            "{} missing from\n{}",
+            expected,
+            metrics.body
+        );
+    }
+
+    shutdown.shutdown();
+    running.join().unwrap();
+}
+
+#[test]
+fn it_answers_json_requests_with_json() {
+    let address = start(Server::new(router(), 2));
+    let post = |content_type: &str, body: &str| {
+        send(
+            address,
+            &format!(
+                "POST /sums HTTP/1.1\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                content_type,
+                body.len(),
+                body
+            ),
+        )
+    };
+
+    let response = post("application/json", "[1, 2, 39]");
+    assert_eq!(response.code, 200);
+    assert_eq!(
+        response.headers.get("Content-Type"),
+        Some("application/json")
+    );
+    assert_eq!(response.body, r#"{"count":3,"total":42}"#);
+
+    let response = post("application/json; charset=utf-8", "[1, 2,\n \"three\"]");
+    assert_eq!(response.code, 400);
+    assert_eq!(
+        response.body,
+        r#"{"error":"invalid type: string \"three\", expected i64","line":2,"column":8}"#
+    );
+
+    assert_eq!(post("text/plain", "[1]").code, 415);
+}
+
+/// Opens a WebSocket to `path`, returns the connection once the handshake is done.
+fn websocket(address: SocketAddr, path: &str) -> BufReader<TcpStream> {
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        path
+    )
+    .unwrap();
+
+    let mut reader = BufReader::new(stream);
+    let response = read_response_head(&mut reader);
+    assert_eq!(response.code, 101);
+    assert_eq!(
+        response.headers.get("Sec-WebSocket-Accept"),
+        Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+    );
+    reader
+}
+
+/// Sends a frame the way a client must, masked.
+fn send_frame(reader: &mut BufReader<TcpStream>, fin: bool, opcode: u8, payload: &[u8]) {
+    let key = [0x12, 0x34, 0x56, 0x78];
+    let mut frame = vec![
+        if fin { 0x80 } else { 0 } | opcode,
+        0x80 | payload.len() as u8,
+    ];
+    frame.extend_from_slice(&key);
+    frame.extend(
+        payload
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| byte ^ key[i % 4]),
+    );
+    reader.get_mut().write_all(&frame).unwrap();
+}
+
+/// Reads a short unmasked frame from the server, returns its opcode and payload.
+fn read_frame(reader: &mut BufReader<TcpStream>) -> (u8, Vec<u8>) {
+    let mut head = [0; 2];
+    reader.read_exact(&mut head).unwrap();
+    assert_eq!(head[0] & 0x80, 0x80, "unexpected fragment");
+    assert!(head[1] < 126, "unexpected mask or long payload");
+    let mut payload = vec![0; head[1] as usize];
+    reader.read_exact(&mut payload).unwrap();
+    (head[0] & 0x0F, payload)
+}
+
+#[test]
+fn it_talks_websocket_with_upgraded_connections() {
+    let address = start(Server::new(router(), 2));
+    let mut socket = websocket(address, "/ws/echo");
+
+    send_frame(&mut socket, true, 0x1, b"hello");
+    assert_eq!(read_frame(&mut socket), (0x1, b"hello".to_vec()));
+
+    // A fragmented message with a ping in the middle: the ping is answered right away and the
+    // message echoed once whole.
+    send_frame(&mut socket, false, 0x2, b"frag");
+    send_frame(&mut socket, true, 0x9, b"are you there?");
+    send_frame(&mut socket, true, 0x0, b"mented");
+    assert_eq!(read_frame(&mut socket), (0xA, b"are you there?".to_vec()));
+    assert_eq!(read_frame(&mut socket), (0x2, b"fragmented".to_vec()));
+
+    send_frame(&mut socket, true, 0x8, &1000u16.to_be_bytes());
+    assert_eq!(
+        read_frame(&mut socket),
+        (0x8, 1000u16.to_be_bytes().to_vec())
+    );
+    assert!(is_closed(&mut socket));
+
+    // Unmasked frames break the protocol.
+    let mut socket = websocket(address, "/ws/echo");
+    socket
+        .get_mut()
+        .write_all(&[0x81, 0x02, b'h', b'i'])
+        .unwrap();
+    assert_eq!(
+        read_frame(&mut socket),
+        (0x8, 1002u16.to_be_bytes().to_vec())
+    );
+
+    // The server can push on its own and close first.
+    let mut socket = websocket(address, "/ws/ticks/3");
+    for tick in 0..3 {
+        assert_eq!(
+            read_frame(&mut socket),
+            (0x1, format!("tick {}", tick).into_bytes())
+        );
+    }
+    let (opcode, payload) = read_frame(&mut socket);
+    assert_eq!(
+        (opcode, &payload[..2], &payload[2..]),
+        (0x8, &[0x03, 0xE8][..], &b"done"[..])
+    );
+    send_frame(&mut socket, true, 0x8, &payload[..2]);
+    assert!(is_closed(&mut socket));
+
+    let response = send(
+        address,
+        "GET /ws/echo HTTP/1.1\r\nConnection: close\r\n\r\n",
+    );
+    assert_eq!(response.code, 426);
+}
+
+/// A stand-in upstream answering every request with its `name`, chunked for `/api/stream`. Hands
+/// over the requests it received, body included.
+fn upstream(name: &'static str) -> (SocketAddr, mpsc::Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut reader = BufReader::new(stream.unwrap());
+            let mut request = String::new();
+            while !request.ends_with("\r\n\r\n") {
+                reader.read_line(&mut request).unwrap();
+            }
+            let length = request
+                .lines()
+                .find_map(|line| line.strip_prefix("Content-Length: "))
+                .map_or(0, |length| length.parse().unwrap());
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            request.push_str(&String::from_utf8(body).unwrap());
+
+            let response = if request.starts_with("GET /api/stream ") {
+                format!(
+                    "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+                    name.len(),
+                    name
+                )
+            } else {
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nKeep-Alive: timeout=5\r\n\r\n{}",
+                    name.len(),
+                    name
+                )
+            };
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+            sender.send(request).unwrap();
+        }
+    });
+    (address, receiver)
+}
+
+#[test]
+fn it_proxies_to_upstreams_in_turn() {
+    let (a, a_requests) = upstream("a");
+    let (b, b_requests) = upstream("b");
+    let mut router = router();
+    router.proxy("/api", Proxy::new(&[a.to_string(), b.to_string()]).unwrap());
+    let address = start(Server::new(router, 2));
+
+    let get = |target: &str| {
+        send(
+            address,
+            &format!(
+                "GET {} HTTP/1.1\r\nHost: example.com\r\nX-Forwarded-For: 10.0.0.1\r\nConnection: close\r\n\r\n",
+                target
+            ),
+        )
+    };
+    let bodies: Vec<_> = ["/api/items?page=2", "/api", "/api/x", "/api/y"]
+        .iter()
+        .map(|target| get(target).body)
+        .collect();
+    assert_eq!(bodies, ["a", "b", "a", "b"]);
+
+    let forwarded = a_requests.recv().unwrap();
+    assert!(forwarded.starts_with("GET /api/items?page=2 HTTP/1.1\r\n"));
+    assert!(forwarded.contains(&format!("\r\nHost: {}\r\n", a)));
+    assert!(forwarded.contains("\r\nX-Forwarded-For: 10.0.0.1, 127.0.0.1\r\n"));
+    assert!(forwarded.contains("\r\nX-Forwarded-Host: example.com\r\n"));
+    assert!(forwarded.contains("\r\nConnection: close\r\n"));
+    assert!(b_requests
+        .recv()
+        .unwrap()
+        .starts_with("GET /api HTTP/1.1\r\n"));
+
+    let response = send(
+        address,
+        "POST /api/items HTTP/1.1\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello",
+    );
+    assert_eq!(response.body, "a");
+    assert!(a_requests.iter().nth(1).unwrap().ends_with("\r\n\r\nhello"));
+    // Only what concerns the client's connection is dropped.
+    assert_eq!(response.headers.get("Keep-Alive"), None);
+
+    // The upstream's chunked body is passed on as it comes.
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(b"GET /api/stream HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut reader = BufReader::new(stream);
+    let response = read_response_head(&mut reader);
+    assert_eq!(response.headers.get("Transfer-Encoding"), Some("chunked"));
+    let mut body = String::new();
+    ChunkedReader::new(&mut reader)
+        .read_to_string(&mut body)
+        .unwrap();
+    assert_eq!(body, "b");
+
+    assert_eq!(get("/apis").code, 404);
+}
+
+#[test]
+fn it_leaves_out_upstreams_it_cannot_connect_to() {
+    let dead = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let (live, live_requests) = upstream("live");
+    let mut router = router();
+    router
+        .proxy(
+            "/api",
+            Proxy::new(&[dead.to_string(), live.to_string()]).unwrap(),
+        )
+        .proxy("/gone", Proxy::new(&[dead.to_string()]).unwrap());
+    let address = start(Server::new(router, 2));
+
+    for _ in 0..4 {
+        let response = send(
+            address,
+            "GET /api/items HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+        assert_eq!((response.code, response.body.as_str()), (200, "live"));
+    }
+    assert_eq!(live_requests.try_iter().count(), 4);
+
+    let response = send(address, "GET /gone HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert_eq!(response.code, 502);
+}
+
+#[test]
+fn it_serves_each_virtual_host_with_its_own_routes() {
+    let mut blog = Router::new();
+    blog.get("/", |_, _| Response::text(StatusCode::Ok, "blog"));
+    let server = Server::new(router(), 2)
+        .virtual_host("Blog.Example.com", blog)
+        .unknown_hosts(UnknownHosts::Misdirected)
+        .health_route("/health");
+    let address = start(server);
+    let get = |host: &str, target: &str| {
+        send(
+            address,
+            &format!(
+                "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+                target, host
+            ),
+        )
+    };
+
+    assert_eq!(get("blog.example.com:80", "/").body, "blog");
+    assert_eq!(get("blog.example.com", "/users/7").code, 404);
+    assert_eq!(get("example.com", "/").code, 421);
+    // Whatever the host, so load balancers needn't know them.
+    assert_eq!(get("example.com", "/health").code, 200);
+
+    let response = send(address, "GET / HTTP/1.0\r\n\r\n");
+    assert_eq!(response.body, "home");
+}
+
+#[test]
+fn it_lets_in_flight_requests_finish_on_shutdown() {
+    let server = Server::new(router(), 2);
+    let shutdown = server.shutdown_handle();
+    let (address, running) = start_joinable(server);
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(b"GET /slow/300 HTTP/1.1\r\n\r\n").unwrap();
+    thread::sleep(Duration::from_millis(100));
+    shutdown.shutdown();
+
+    let mut reader = BufReader::new(stream);
+    let response = read_response(&mut reader);
+    assert_eq!((response.code, response.body.as_str()), (200, "done"));
+    assert_eq!(response.headers.get("Connection"), Some("close"));
+
+    assert_eq!(running.join().unwrap(), ShutdownReport { abandoned: 0 });
+    assert!(TcpStream::connect(address).is_err());
+}
+
+#[test]
+fn it_closes_idle_connections_right_away_on_shutdown() {
+    let server = Server::new(router(), 2);
+    let shutdown = server.shutdown_handle();
+    let (address, running) = start_joinable(server);
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    let mut reader = BufReader::new(stream);
+    assert_eq!(read_response(&mut reader).code, 200);
+
+    let started = Instant::now();
+    shutdown.shutdown();
+    assert_eq!(running.join().unwrap().abandoned, 0);
+    assert!(started.elapsed() < Duration::from_secs(2));
+    assert!(is_closed(&mut reader));
+}
+
+#[test]
+fn it_reports_requests_abandoned_after_the_deadline() {
+    let server = Server::new(router(), 2).shutdown_timeout(Duration::from_millis(100));
+    let shutdown = server.shutdown_handle();
+    let (address, running) = start_joinable(server);
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(b"GET /slow/1000 HTTP/1.1\r\n\r\n")
+        .unwrap();
+    thread::sleep(Duration::from_millis(100));
+    shutdown.shutdown();
+
+    assert_eq!(running.join().unwrap().abandoned, 1);
+}
+
+#[test]
+fn it_shuts_down_from_the_admin_route() {
+    let server = Server::new(router(), 2).shutdown_route("/admin/shutdown");
+    let (address, running) = start_joinable(server);
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(b"POST /admin/shutdown HTTP/1.1\r\n\r\n")
+        .unwrap();
+    let response = read_response(&mut BufReader::new(stream));
+
+    assert_eq!(response.code, 202);
+    assert_eq!(running.join().unwrap().abandoned, 0);
+}
+
+/// An access log destination the test can read back.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn it_records_requests_in_the_access_log() {
+    let buffer = SharedBuffer::default();
+    let access_log = AccessLog::to_writer(buffer.clone(), LogFormat::Json);
+    let server = Server::new(router(), 1).access_log(access_log);
+    let shutdown = server.shutdown_handle();
+    let (address, running) = start_joinable(server);
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(
+            b"GET /users/7?full=1 HTTP/1.1\r\nUser-Agent: tests\r\nConnection: close\r\n\r\n",
+        )
+        .unwrap();
+    assert_eq!(read_response(&mut BufReader::new(stream)).code, 200);
+
+    shutdown.shutdown();
+    running.join().unwrap();
+
+    let log = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    assert_eq!(log.lines().count(), 1);
+    for field in [
+        "\"client\":\"127.0.0.1\"",
+        "\"method\":\"GET\"",
+        "\"path\":\"/users/7\"",
+        "\"query\":\"full=1\"",
+        "\"status\":200",
+        "\"size\":6",
+        "\"worker\":0",
+        "\"user_agent\":\"tests\"",
+    ] {
+        assert!(log.contains(field), "{} is missing from {}", field, log);
+    }
+}
+
+#[test]
+fn it_runs_middlewares_around_the_handlers() {
+    let server = Server::new(router(), 1)
+        .middleware(RequestId::new())
+        .middleware(Cors::any());
+    let address = start(server);
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nOrigin: https://app.example\r\n\r\n")
+        .unwrap();
+    let response = read_response(&mut BufReader::new(stream));
+
+    assert_eq!(response.body, "home");
+    assert!(response.headers.contains("X-Request-Id"));
+    assert_eq!(
+        response.headers.get("Access-Control-Allow-Origin"),
+        Some("*")
+    );
+}
+
+/// A server certificate for `localhost`, and a client configuration trusting it.
+fn self_signed() -> (Tls, Arc<ClientConfig>) {
+    let generated = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+    let tls = Tls::from_pem(
+        generated.cert.pem().as_bytes(),
+        generated.key_pair.serialize_pem().as_bytes(),
+    )
+    .unwrap();
+
+    let mut roots = RootCertStore::empty();
+    roots
+        .add(CertificateDer::from(generated.cert.der().to_vec()))
+        .unwrap();
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let client = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    (tls, Arc::new(client))
+}
+
+fn https_get(address: SocketAddr, client: &Arc<ClientConfig>, target: &str) -> TestResponse {
+    let connection =
+        ClientConnection::new(Arc::clone(client), "localhost".try_into().unwrap()).unwrap();
+    let mut stream = StreamOwned::new(connection, TcpStream::connect(address).unwrap());
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        target
+    )
+    .unwrap();
+    read_response(&mut BufReader::new(stream))
+}
+
+#[test]
+fn it_serves_https_with_a_self_signed_certificate() {
+    let (tls, client) = self_signed();
+    let address = start(Server::new(router(), 2).tls(tls));
+
+    let response = https_get(address, &client, "/users/42");
+    assert_eq!((response.code, response.body.as_str()), (200, "user 42"));
+
+    // Plain HTTP on a TLS port is refused by the handshake, not answered.
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok();
+    assert!(!line.starts_with("HTTP/1.1 200"));
+}
+
+#[test]
+fn it_redirects_plain_http_to_https() {
+    let (tls, client) = self_signed();
+    let server = Server::new(router(), 2)
+        .tls(tls)
+        .redirect_to_https(true)
+        .server_name("localhost");
+    let http = TcpListener::bind("127.0.0.1:0").unwrap();
+    let https = TcpListener::bind("127.0.0.1:0").unwrap();
+    let (http_address, https_address) = (http.local_addr().unwrap(), https.local_addr().unwrap());
+    let shutdown = server.shutdown_handle();
+    let running = thread::spawn(move || server.run_http_and_https(http, https));
+
+    let mut stream = TcpStream::connect(http_address).unwrap();
+    write!(
+        stream,
+        "GET /users/7?full=1 HTTP/1.1\r\nHost: localhost:{}\r\n\r\n",
+        http_address.port()
+    )
+    .unwrap();
+    let response = read_response(&mut BufReader::new(stream));
+    assert_eq!(response.code, 308);
+    assert_eq!(
+        response.headers.get("Location"),
+        Some(format!("https://localhost:{}/users/7?full=1", https_address.port()).as_str())
+    );
+
+    // A host the server doesn't go by is never redirected to.
+    let mut stream = TcpStream::connect(http_address).unwrap();
+    stream
+        .write_all(b"GET /login HTTP/1.1\r\nHost: evil.example\r\n\r\n")
+        .unwrap();
+    let response = read_response(&mut BufReader::new(stream));
+    assert_eq!(
+        response.headers.get("Location"),
+        Some(format!("https://localhost:{}/login", https_address.port()).as_str())
+    );
+
+    assert_eq!(https_get(https_address, &client, "/").body, "home");
+
+    shutdown.shutdown();
+    assert_eq!(running.join().unwrap().abandoned, 0);
+}
@@ -0,0 +1,283 @@
+//! Compares the work-stealing `WorkerPool` with the single shared queue it replaced, for
+//! throughput and for how long tasks wait before a worker starts them.
+//!
+//! Both pools log every task like the pool always did, so send standard output away and read the
+//! results on standard error:
+//!
+//! ```text
+//! cargo bench --bench scheduler > /dev/null
+//! cargo bench --bench scheduler -- --workers 16 > /dev/null
+//! ```
+
+use std::{
+    hint::black_box,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use multi_thread_web_server_pool::WorkerPool;
+
+/// The pool as it was before work stealing: every worker waits on one shared receiver.
+mod shared_queue {
+    use std::{
+        sync::{mpsc, Arc, Mutex},
+        thread,
+    };
+
+    type Task = Box<dyn FnOnce(usize) + Send + 'static>;
+
+    pub struct SharedQueuePool {
+        workers: Vec<thread::JoinHandle<()>>,
+        sender: Option<mpsc::Sender<Task>>,
+    }
+
+    impl SharedQueuePool {
+        pub fn new(size: usize) -> Self {
+            let (sender, receiver) = mpsc::channel::<Task>();
+            let receiver = Arc::new(Mutex::new(receiver));
+            let workers = (0..size)
+                .map(|id| {
+                    let receiver = Arc::clone(&receiver);
+                    thread::spawn(move || loop {
+                        let resolved = receiver.lock().unwrap().recv();
+                        match resolved {
+                            Ok(task) => {
+                                println!("Worker {} got a job; executing.", id);
+                                task(id);
+                                println!("Worker {} finished successfully.", id);
+                            }
+                            Err(_) => break,
+                        }
+                    })
+                })
+                .collect();
+
+            SharedQueuePool {
+                workers,
+                sender: Some(sender),
+            }
+        }
+
+        pub fn execute(&self, task: impl FnOnce(usize) + Send + 'static) {
+            self.sender.as_ref().unwrap().send(Box::new(task)).unwrap();
+        }
+    }
+
+    impl Drop for SharedQueuePool {
+        fn drop(&mut self) {
+            drop(self.sender.take());
+            for worker in self.workers.drain(..) {
+                worker.join().unwrap();
+            }
+        }
+    }
+}
+
+use shared_queue::SharedQueuePool;
+
+trait Pool: Send + Sync + 'static {
+    const NAME: &'static str;
+
+    fn new(size: usize) -> Self;
+
+    fn spawn(&self, task: impl FnOnce() + Send + 'static);
+}
+
+impl Pool for WorkerPool {
+    const NAME: &'static str = "work stealing";
+
+    fn new(size: usize) -> Self {
+        WorkerPool::new(size)
+    }
+
+    fn spawn(&self, task: impl FnOnce() + Send + 'static) {
+        self.execute(move |_| {
+            task();
+            Ok(())
+        });
+    }
+}
+
+impl Pool for SharedQueuePool {
+    const NAME: &'static str = "shared queue";
+
+    fn new(size: usize) -> Self {
+        SharedQueuePool::new(size)
+    }
+
+    fn spawn(&self, task: impl FnOnce() + Send + 'static) {
+        self.execute(move |_| task());
+    }
+}
+
+/// Counts finished tasks down to zero, recording how long each waited to start.
+struct Tracker {
+    left: Mutex<usize>,
+    done: Condvar,
+    waits: Mutex<Vec<Duration>>,
+}
+
+impl Tracker {
+    fn new(tasks: usize) -> Arc<Self> {
+        Arc::new(Tracker {
+            left: Mutex::new(tasks),
+            done: Condvar::new(),
+            waits: Mutex::new(Vec::with_capacity(tasks)),
+        })
+    }
+
+    fn started(&self, submitted: Instant) {
+        self.waits.lock().unwrap().push(submitted.elapsed());
+    }
+
+    fn finished(&self) {
+        let mut left = self.left.lock().unwrap();
+        *left -= 1;
+        if *left == 0 {
+            self.done.notify_all();
+        }
+    }
+
+    fn wait(&self) {
+        let mut left = self.left.lock().unwrap();
+        while *left > 0 {
+            left = self.done.wait(left).unwrap();
+        }
+    }
+}
+
+/// Roughly `rounds` nanoseconds of CPU work.
+fn work(rounds: u64) -> u64 {
+    (0..rounds).fold(0, |hash, round| {
+        black_box(hash ^ round.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+    })
+}
+
+/// Many small tasks submitted from outside the pool.
+fn flat<P: Pool>(pool: &Arc<P>, tracker: &Arc<Tracker>, tasks: usize) {
+    for _ in 0..tasks {
+        let tracker = Arc::clone(tracker);
+        let submitted = Instant::now();
+        pool.spawn(move || {
+            tracker.started(submitted);
+            work(2_000);
+            tracker.finished();
+        });
+    }
+}
+
+/// A few tasks each submitting many more from inside the pool, as divide and conquer does.
+fn nested<P: Pool>(pool: &Arc<P>, tracker: &Arc<Tracker>, parents: usize, children: usize) {
+    for _ in 0..parents {
+        let (inner, tracker) = (Arc::clone(pool), Arc::clone(tracker));
+        let submitted = Instant::now();
+        pool.spawn(move || {
+            tracker.started(submitted);
+            for _ in 0..children {
+                let tracker = Arc::clone(&tracker);
+                let submitted = Instant::now();
+                inner.spawn(move || {
+                    tracker.started(submitted);
+                    work(2_000);
+                    tracker.finished();
+                });
+            }
+            // Never the last one holding the pool, dropping it on a worker would join itself.
+            drop(inner);
+            tracker.finished();
+        });
+    }
+}
+
+/// Tasks trickling in while the pool is busy, half as fast as it could run them.
+fn trickle<P: Pool>(pool: &Arc<P>, tracker: &Arc<Tracker>, tasks: usize, workers: usize) {
+    let pause = Duration::from_micros(20) / workers as u32;
+    for _ in 0..tasks {
+        let tracker = Arc::clone(tracker);
+        let submitted = Instant::now();
+        pool.spawn(move || {
+            tracker.started(submitted);
+            work(10_000);
+            tracker.finished();
+        });
+        thread::sleep(pause);
+    }
+}
+
+fn measure<P: Pool>(
+    workload: &str,
+    workers: usize,
+    tasks: usize,
+    run: impl Fn(&Arc<P>, &Arc<Tracker>),
+) {
+    let pool = Arc::new(P::new(workers));
+    let tracker = Tracker::new(tasks);
+
+    let started = Instant::now();
+    run(&pool, &tracker);
+    tracker.wait();
+    let elapsed = started.elapsed();
+    drop(pool);
+
+    let mut waits = std::mem::take(&mut *tracker.waits.lock().unwrap());
+    waits.sort();
+    let percentile = |p: usize| waits[(waits.len() - 1) * p / 100];
+    eprintln!(
+        "{:<8} {:<14} {:>12.0} {:>12?} {:>12?} {:>12?}",
+        workload,
+        P::NAME,
+        tasks as f64 / elapsed.as_secs_f64(),
+        percentile(50),
+        percentile(99),
+        waits[waits.len() - 1],
+    );
+}
+
+fn compare(workers: usize) {
+    let tasks = 20_000;
+    measure::<SharedQueuePool>("flat", workers, tasks, |pool, tracker| {
+        flat(pool, tracker, tasks)
+    });
+    measure::<WorkerPool>("flat", workers, tasks, |pool, tracker| {
+        flat(pool, tracker, tasks)
+    });
+
+    let (parents, children) = (64, 312);
+    let tasks = parents * (children + 1);
+    measure::<SharedQueuePool>("nested", workers, tasks, |pool, tracker| {
+        nested(pool, tracker, parents, children)
+    });
+    measure::<WorkerPool>("nested", workers, tasks, |pool, tracker| {
+        nested(pool, tracker, parents, children)
+    });
+
+    let tasks = 5_000;
+    measure::<SharedQueuePool>("trickle", workers, tasks, |pool, tracker| {
+        trickle(pool, tracker, tasks, workers)
+    });
+    measure::<WorkerPool>("trickle", workers, tasks, |pool, tracker| {
+        trickle(pool, tracker, tasks, workers)
+    });
+}
+
+fn main() {
+    // `cargo test --benches` runs this without `--bench`, only `cargo bench` measures.
+    let args: Vec<String> = std::env::args().collect();
+    if !args.iter().any(|arg| arg == "--bench") {
+        return;
+    }
+
+    // One worker per core unless `--workers` says otherwise.
+    let workers = args
+        .iter()
+        .position(|arg| arg == "--workers")
+        .and_then(|index| args.get(index + 1)?.parse().ok())
+        .unwrap_or_else(|| thread::available_parallelism().map_or(4, |n| n.get()));
+    eprintln!("{} workers, waits are from submission to start.", workers);
+    eprintln!(
+        "{:<8} {:<14} {:>12} {:>12} {:>12} {:>12}",
+        "workload", "pool", "tasks/s", "p50 wait", "p99 wait", "max wait"
+    );
+    compare(workers);
+}
@@ -0,0 +1,146 @@
+use std::{
+    error::Error,
+    fmt, io,
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::lock;
+
+/// Why a submitted task gave no value.
+#[derive(Debug)]
+pub enum TaskError {
+    /// The task returned an error.
+    Failed(io::Error),
+    /// The task panicked, with this message.
+    Panicked(String),
+    /// The task was dropped before it could run.
+    Cancelled,
+}
+
+impl fmt::Display for TaskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskError::Failed(error) => write!(f, "task failed: {}", error),
+            TaskError::Panicked(message) => write!(f, "task panicked: {}", message),
+            TaskError::Cancelled => f.write_str("task was cancelled"),
+        }
+    }
+}
+
+impl Error for TaskError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TaskError::Failed(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Where a task leaves its outcome for its handle.
+struct Slot<T> {
+    outcome: Mutex<Option<Result<T, TaskError>>>,
+    ready: Condvar,
+}
+
+/// The task's side of a `TaskHandle`. Dropping it unfulfilled, along with a task that never ran,
+/// cancels the task.
+pub(crate) struct Promise<T> {
+    slot: Arc<Slot<T>>,
+}
+
+impl<T> Promise<T> {
+    pub(crate) fn fulfill(self, outcome: Result<T, TaskError>) {
+        self.set(outcome);
+    }
+
+    fn set(&self, outcome: Result<T, TaskError>) {
+        let mut slot = lock(&self.slot.outcome);
+        if slot.is_none() {
+            *slot = Some(outcome);
+            self.slot.ready.notify_all();
+        }
+    }
+}
+
+impl<T> Drop for Promise<T> {
+    fn drop(&mut self) {
+        self.set(Err(TaskError::Cancelled));
+    }
+}
+
+/// The outcome of a task given to `WorkerPool::submit`, once it ran.
+pub struct TaskHandle<T> {
+    slot: Arc<Slot<T>>,
+}
+
+pub(crate) fn pair<T>() -> (Promise<T>, TaskHandle<T>) {
+    let slot = Arc::new(Slot {
+        outcome: Mutex::new(None),
+        ready: Condvar::new(),
+    });
+    (
+        Promise {
+            slot: Arc::clone(&slot),
+        },
+        TaskHandle { slot },
+    )
+}
+
+impl<T> TaskHandle<T> {
+    /// Whether the task is done, so that `join` won't block.
+    pub fn is_finished(&self) -> bool {
+        lock(&self.slot.outcome).is_some()
+    }
+
+    /// Waits for the task to be done and returns its value, or why there is none.
+    pub fn join(self) -> Result<T, TaskError> {
+        let mut outcome = lock(&self.slot.outcome);
+        loop {
+            if let Some(outcome) = outcome.take() {
+                return outcome;
+            }
+            outcome = self
+                .slot
+                .ready
+                .wait(outcome)
+                .unwrap_or_else(|error| error.into_inner());
+        }
+    }
+
+    /// The task's outcome if it is done, the handle back otherwise.
+    pub fn try_join(self) -> Result<Result<T, TaskError>, Self> {
+        let outcome = lock(&self.slot.outcome).take();
+        outcome.ok_or(self)
+    }
+
+    /// Like `join`, giving the handle back if the task isn't done within `timeout`.
+    pub fn join_timeout(self, timeout: Duration) -> Result<Result<T, TaskError>, Self> {
+        let deadline = Instant::now() + timeout;
+        let mut outcome = lock(&self.slot.outcome);
+        loop {
+            if let Some(outcome) = outcome.take() {
+                return Ok(outcome);
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                drop(outcome);
+                return Err(self);
+            }
+            outcome = self
+                .slot
+                .ready
+                .wait_timeout(outcome, left)
+                .unwrap_or_else(|error| error.into_inner())
+                .0;
+        }
+    }
+}
+
+impl<T> fmt::Debug for TaskHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaskHandle")
+            .field("finished", &self.is_finished())
+            .finish()
+    }
+}
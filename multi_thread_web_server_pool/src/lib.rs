@@ -1,28 +1,185 @@
 use std::{
-    sync::{mpsc, Arc, Mutex},
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex, MutexGuard, PoisonError,
+    },
     thread,
+    time::{Duration, Instant},
 };
 
+mod handle;
+mod queue;
+mod steal;
+
+pub use handle::{TaskError, TaskHandle};
+pub use queue::{Overflow, Rejected};
+use queue::{Pop, Queue};
+use steal::{Deques, Local};
+
 type Task = Box<dyn FnOnce(usize) -> Result<(), std::io::Error> + Send + 'static>;
 
+/// The worker id tasks run with when `Overflow::CallerRuns` runs them on the calling thread.
+pub const CALLER: usize = usize::MAX;
+
+/// Called with the id of the worker whose task panicked and what the task panicked with.
+type PanicHandler = dyn Fn(usize, &(dyn Any + Send)) + Send + Sync + 'static;
+
 pub struct WorkerPool {
-    workers: Vec<Worker>,
-    sender: Option<mpsc::Sender<Task>>,
+    workers: Arc<Mutex<Vec<Worker>>>,
+    shared: Arc<Shared>,
+    supervisor: Option<Supervisor>,
+}
+
+/// What every worker needs, and the supervisor to respawn them.
+struct Shared {
+    queue: Queue,
+    deques: Deques,
+    counters: Counters,
+    sizes: Sizes,
+    /// How long a worker past the core size may wait for a task before retiring.
+    keep_alive: Duration,
+    next_id: AtomicUsize,
+    events: mpsc::Sender<Event>,
+    panic_handler: Box<PanicHandler>,
+}
+
+/// What the pool is up to, updated by `execute` and the workers.
+#[derive(Default)]
+struct Counters {
+    /// Workers running or starting, leaving out the ones retiring.
+    workers: AtomicUsize,
+    busy: AtomicUsize,
+    panicked: AtomicUsize,
+}
+
+/// Read by every worker on every turn, so kept out of a lock. `set_size` moves the max size
+/// along with the core size, their difference never changes.
+struct Sizes {
+    /// Workers kept however idle they are.
+    core: AtomicUsize,
+    /// Workers past `core + extra` are never spawned, the ones beyond it retire after their task.
+    extra: usize,
+}
+
+impl Sizes {
+    fn core(&self) -> usize {
+        self.core.load(Ordering::SeqCst)
+    }
+
+    fn max(&self) -> usize {
+        self.core() + self.extra
+    }
+}
+
+/// Sets up a `WorkerPool` before its workers start.
+pub struct Builder {
+    size: usize,
+    max_size: usize,
+    keep_alive: Duration,
+    capacity: Option<usize>,
+    overflow: Overflow,
+    panic_handler: Box<PanicHandler>,
+}
+
+impl Builder {
+    /// Calls `handler` whenever a task panics, on the worker that ran it, instead of printing
+    /// the panic message. The worker carries on with the next task either way.
+    pub fn panic_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(usize, &(dyn Any + Send)) + Send + Sync + 'static,
+    {
+        self.panic_handler = Box::new(handler);
+        self
+    }
+
+    /// Keeps at most `capacity` tasks waiting for a worker, instead of any number of them.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// What to do with a task when `capacity` tasks are already waiting, blocking the caller
+    /// until there is room by default.
+    pub fn overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Spawns up to `max_size` workers in all while tasks wait for a free one, instead of
+    /// sticking to the pool's size.
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// How long the workers spawned past the pool's size wait for a task before retiring, a
+    /// minute by default.
+    pub fn keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Starts the workers, and the supervisor respawning them.
+    /// If size or capacity is 0, or max size is below size, then panic.
+    pub fn build(self) -> WorkerPool {
+        assert!(self.size > 0);
+        assert!(self.max_size >= self.size);
+        assert!(self.capacity != Some(0));
+
+        // We then create a way to communicate work among workers, a queue every worker takes tasks from, shared via atomic reference counting.
+        let (events, events_received) = mpsc::channel();
+        let shared = Arc::new(Shared {
+            queue: Queue::new(self.capacity, self.overflow),
+            deques: Deques::default(),
+            counters: Counters::default(),
+            sizes: Sizes {
+                core: AtomicUsize::new(self.size),
+                extra: self.max_size - self.size,
+            },
+            keep_alive: self.keep_alive,
+            next_id: AtomicUsize::new(0),
+            events,
+            panic_handler: self.panic_handler,
+        });
+
+        let workers = Arc::new(Mutex::new(Vec::new()));
+        while spawn(&shared, &workers, self.size) {}
+
+        let supervisor =
+            Supervisor::new(events_received, Arc::clone(&workers), Arc::clone(&shared));
+
+        WorkerPool {
+            workers,
+            shared,
+            supervisor: Some(supervisor),
+        }
+    }
 }
 
 impl Drop for WorkerPool {
     fn drop(&mut self) {
-        println!("Dropping sender, no more tasks will be accepted.");
-        drop(self.sender.take());
-        println!("Dropped sender and dropping all workers, no more tasks will be accepted beyond this point, \"main\" thread awaiting all current workers to finish.");
-        for worker in &mut self.workers {
+        // Stop respawning workers first, so none shows up once the others were joined.
+        if let Some(supervisor) = self.supervisor.take() {
+            supervisor.stop();
+        }
+
+        println!("Closing the queue, no more tasks will be accepted.");
+        self.shared.queue.close();
+        println!("Closed the queue and dropping all workers, no more tasks will be accepted beyond this point, \"main\" thread awaiting all current workers to finish.");
+        // Taken out of the lock, tasks may still be running and submitting others.
+        let workers = std::mem::take(&mut *lock(&self.workers));
+        for mut worker in workers {
             println!("Shutting down worker {}", worker.id);
             // For this, thread needs to be an Option given that the worker pool would take ownership of the thread,
             // and we need to be able to take ownership back to join the thread, since the join() method takes ownership of its argument
             // So we use "take()" from the Option thread to take the value out of the Option which would be the handle of the thread, and then join the thread
             // to the main thread so it can finish its work. The main thread will wait for it to finish before continuing.
             if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
+                if thread.join().is_err() {
+                    println!("Worker {} had died.", worker.id);
+                }
             }
         }
     }
@@ -34,28 +191,56 @@ struct Worker {
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Task>>>) -> Self {
-        let thread = thread::spawn(move || loop {
-            let resolved = receiver.lock().unwrap().recv();
-
-            match resolved {
-                Ok(task) => {
-                    println!("Worker {} got a job; executing.", id);
-                    match task(id) {
-                        Ok(_) => {
-                            println!("Worker {} finished successfully.", id);
+    fn new(id: usize, shared: Arc<Shared>) -> Self {
+        let thread = thread::Builder::new()
+            .name(format!("worker-{}", id))
+            .spawn(move || {
+                let _watch = Watch {
+                    id,
+                    events: shared.events.clone(),
+                };
+                let local = Local::register(id, &shared);
+                let mut idle_since = Instant::now();
+                loop {
+                    if local.is_empty() && shared.retire(false) {
+                        println!("Worker {} is past the pool's size, retiring.", id);
+                        break;
+                    }
+
+                    // Our own newest task first, then the oldest one submitted from outside,
+                    // then one stolen from a busy worker.
+                    let task = local
+                        .pop()
+                        .or_else(|| shared.queue.try_pop())
+                        .or_else(|| shared.deques.steal(id, &shared));
+                    // Tasks run outside of the queues' locks, a panicking one can't poison them.
+                    let popped = match task {
+                        Some(task) => Pop::Task(task),
+                        None => shared.queue.pop(idle_since + shared.keep_alive),
+                    };
+                    match popped {
+                        Pop::Task(task) => {
+                            println!("Worker {} got a job; executing.", id);
+                            run(&shared, id, task);
+                            idle_since = Instant::now();
+                        }
+                        Pop::Empty if idle_since.elapsed() >= shared.keep_alive => {
+                            if shared.retire(true) {
+                                println!("Worker {} was idle for too long, retiring.", id);
+                                break;
+                            }
+                            idle_since = Instant::now();
                         }
-                        Err(_) => {
-                            println!("Worker {} failed to execute job.", id);
+                        Pop::Empty => {}
+                        Pop::Closed => {
+                            println!("Worker {} not capable to receive task, shutting down.", id);
+                            return;
                         }
                     }
                 }
-                Err(_) => {
-                    println!("Worker {} not capable to receive task, shutting down.", id);
-                    break;
-                }
-            }
-        });
+                shared.events.send(Event::Retired(id)).ok();
+            })
+            .expect("failed to spawn a worker thread");
 
         Self {
             id,
@@ -64,36 +249,639 @@ impl Worker {
     }
 }
 
-impl WorkerPool {
-    pub fn new(size: usize) -> Self {
-        /// Create a new WorkerPool.
-        /// If size is 0, then panic.
-        assert!(size > 0);
+impl Shared {
+    /// Whether a worker may stop, counting it out if so. Workers past the max size may, and
+    /// the ones past the core size once idle for the keep-alive duration.
+    fn retire(&self, idle: bool) -> bool {
+        let limit = if idle {
+            self.sizes.core()
+        } else {
+            self.sizes.max()
+        };
+        self.counters
+            .workers
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |running| {
+                (running > limit).then(|| running - 1)
+            })
+            .is_ok()
+    }
+}
 
-        // We then create a way to communicate work among workers, so we create a shared channel via a channel + a mutex with atomic reference counting for safe thread sharing of the channel via the mutex.
-        let (sender, receiver) = mpsc::channel::<Task>();
-        let mutex = Arc::new(Mutex::new(receiver));
+/// Starts a worker if fewer than `limit` are running, returning whether it did.
+fn spawn(shared: &Arc<Shared>, workers: &Mutex<Vec<Worker>>, limit: usize) -> bool {
+    let counted =
+        shared
+            .counters
+            .workers
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |running| {
+                (running < limit).then(|| running + 1)
+            });
+    if counted.is_err() {
+        return false;
+    }
 
-        let mut workers = Vec::with_capacity(size);
+    let mut workers = lock(workers);
+    // Once closed, the pool is being dropped and joined the workers it had.
+    if shared.queue.is_closed() {
+        shared.counters.workers.fetch_sub(1, Ordering::SeqCst);
+        return false;
+    }
+    let id = shared.next_id.fetch_add(1, Ordering::SeqCst);
+    workers.push(Worker::new(id, Arc::clone(shared)));
+    true
+}
 
-        for i in 0..size {
-            workers.push(Worker::new(i, Arc::clone(&mutex)));
+/// Runs `task` as worker `id`, catching its panic.
+fn run(shared: &Shared, id: usize, task: Task) {
+    shared.counters.busy.fetch_add(1, Ordering::SeqCst);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| task(id)));
+    shared.counters.busy.fetch_sub(1, Ordering::SeqCst);
+    match result {
+        Ok(Ok(_)) => {
+            println!("Worker {} finished successfully.", id);
+        }
+        Ok(Err(_)) => {
+            println!("Worker {} failed to execute job.", id);
+        }
+        Err(payload) => {
+            shared.counters.panicked.fetch_add(1, Ordering::SeqCst);
+            (shared.panic_handler)(id, &*payload);
         }
+    }
+}
 
-        WorkerPool {
-            workers,
-            sender: Some(sender),
+/// Tells the supervisor when the worker thread it lives on dies, e.g. because the panic handler
+/// panicked itself.
+struct Watch {
+    id: usize,
+    events: mpsc::Sender<Event>,
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.events.send(Event::Died(self.id)).ok();
         }
     }
+}
+
+enum Event {
+    Died(usize),
+    /// The worker stopped on its own, to be joined.
+    Retired(usize),
+    Stop,
+}
+
+/// Replaces the workers that die, so the pool keeps its size, and joins the ones retiring.
+struct Supervisor {
+    events: mpsc::Sender<Event>,
+    thread: thread::JoinHandle<()>,
+}
 
+impl Supervisor {
+    fn new(
+        received: mpsc::Receiver<Event>,
+        workers: Arc<Mutex<Vec<Worker>>>,
+        shared: Arc<Shared>,
+    ) -> Self {
+        let events = shared.events.clone();
+        let thread = thread::Builder::new()
+            .name(String::from("pool-supervisor"))
+            .spawn(move || loop {
+                let (id, respawn) = match received.recv() {
+                    Ok(Event::Died(id)) => (id, true),
+                    Ok(Event::Retired(id)) => (id, false),
+                    Ok(Event::Stop) | Err(_) => break,
+                };
+
+                let mut workers = lock(&workers);
+                let Some(index) = workers.iter().position(|worker| worker.id == id) else {
+                    continue;
+                };
+                let gone = if respawn {
+                    println!("Worker {} died, respawning it.", id);
+                    let worker = Worker::new(id, Arc::clone(&shared));
+                    std::mem::replace(&mut workers[index], worker)
+                } else {
+                    workers.remove(index)
+                };
+                drop(workers);
+                if let Some(thread) = gone.thread {
+                    thread.join().ok();
+                }
+            })
+            .expect("failed to spawn the pool supervisor");
+
+        Supervisor { events, thread }
+    }
+
+    fn stop(self) {
+        self.events.send(Event::Stop).ok();
+        self.thread.join().ok();
+    }
+}
+
+/// Locks `mutex` even if a thread panicked while holding it, none of the pool's state can be
+/// left half updated by one.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The message a panic was raised with, for the usual `panic!` payloads.
+pub fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+impl WorkerPool {
+    /// Create a new WorkerPool.
+    /// If size is 0, then panic.
+    pub fn new(size: usize) -> Self {
+        WorkerPool::builder(size).build()
+    }
+
+    /// A pool of `size` workers to set up further before starting it.
+    pub fn builder(size: usize) -> Builder {
+        Builder {
+            size,
+            max_size: size,
+            keep_alive: Duration::from_secs(60),
+            capacity: None,
+            overflow: Overflow::default(),
+            panic_handler: Box::new(|id, payload| {
+                println!("Worker {} caught a panic: {}", id, panic_message(payload));
+            }),
+        }
+    }
+
+    /// Queues `f` for the next free worker, applying the overflow policy when the queue is full.
+    /// A task turned down by `Overflow::Reject` is dropped, see `try_execute` to get it back.
+    /// Submitted from inside a task under `Overflow::Block`, it runs right away instead.
     pub fn execute<F>(&self, f: F)
     where
         F: FnOnce(usize) -> Result<(), std::io::Error> + Send + 'static,
     {
-        let task = Box::new(f);
-        self.sender.as_ref().unwrap().send(task).unwrap();
+        match self.try_execute(f) {
+            Ok(()) => {}
+            Err(rejected) => match self.shared.queue.overflow {
+                // Only a worker gets its task back under `Block`.
+                Overflow::Block | Overflow::CallerRuns => {
+                    run(&self.shared, CALLER, Box::new(rejected.into_task()))
+                }
+                Overflow::Reject | Overflow::DropOldest => {
+                    println!("Task queue is full, dropping a task.")
+                }
+            },
+        }
+    }
+
+    /// Like `execute`, giving the task back when the queue is full and the overflow policy is
+    /// `Overflow::Reject` or `Overflow::CallerRuns`. It never runs the task on the calling
+    /// thread, whoever gets it back decides what to do with it.
+    ///
+    /// Tasks submitted from inside one of the pool's tasks go to the deque of the worker running
+    /// it instead, where idle workers steal them. They count towards the capacity all the same,
+    /// and are given back under `Overflow::Block` too rather than leave the worker waiting for
+    /// room only workers make.
+    pub fn try_execute<F>(&self, f: F) -> Result<(), Rejected<F>>
+    where
+        F: FnOnce(usize) -> Result<(), std::io::Error> + Send + 'static,
+    {
+        let local = steal::local_deque(&self.shared);
+        let local = local.as_ref().map(|deque| &deque.tasks);
+        let steal_oldest = || self.shared.deques.steal(CALLER, &self.shared);
+        match self.shared.queue.push(f, local, steal_oldest) {
+            Ok(()) => {
+                self.grow();
+                Ok(())
+            }
+            Err(task) => Err(Rejected::new(task)),
+        }
+    }
+
+    /// Queues `f` whatever the capacity and the overflow policy, never blocking nor running it
+    /// on the calling thread. Meant for tasks carrying on work the pool already accepted.
+    pub fn execute_unbounded<F>(&self, f: F)
+    where
+        F: FnOnce(usize) -> Result<(), std::io::Error> + Send + 'static,
+    {
+        self.shared.queue.push_unbounded(Box::new(f));
+        self.grow();
+    }
+
+    /// Runs `f` like `execute`, returning a handle to wait for what it returns, its error or
+    /// its panic. A panic is still counted and reported to the panic handler.
+    pub fn submit<F, T>(&self, f: F) -> TaskHandle<T>
+    where
+        F: FnOnce(usize) -> Result<T, std::io::Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        let (promise, handle) = handle::pair();
+        self.execute(move |id| {
+            match panic::catch_unwind(AssertUnwindSafe(|| f(id))) {
+                Ok(Ok(value)) => {
+                    promise.fulfill(Ok(value));
+                    Ok(())
+                }
+                Ok(Err(error)) => {
+                    // The worker logs a copy, the caller gets the error itself.
+                    let copy = std::io::Error::new(error.kind(), error.to_string());
+                    promise.fulfill(Err(TaskError::Failed(error)));
+                    Err(copy)
+                }
+                Err(payload) => {
+                    let message = String::from(panic_message(&*payload));
+                    promise.fulfill(Err(TaskError::Panicked(message)));
+                    panic::resume_unwind(payload)
+                }
+            }
+        });
+        handle
+    }
+
+    /// Spawns a worker past the core size when more tasks wait than workers are free.
+    fn grow(&self) {
+        let counters = &self.shared.counters;
+        let free = counters
+            .workers
+            .load(Ordering::SeqCst)
+            .saturating_sub(counters.busy.load(Ordering::SeqCst));
+        if self.queued() > free {
+            spawn(&self.shared, &self.workers, self.max_size());
+        }
+    }
+
+    /// Changes how many workers the pool keeps, moving the max size along so it spawns as many
+    /// extra workers as before. Workers are started right away, or retire once done with their
+    /// task.
+    /// If size is 0, then panic.
+    pub fn set_size(&self, size: usize) {
+        assert!(size > 0);
+        self.shared.sizes.core.store(size, Ordering::SeqCst);
+
+        while spawn(&self.shared, &self.workers, size) {}
+        // Idle workers past the new size only find out once woken up.
+        self.shared.queue.wake_all();
+    }
+
+    /// How many workers the pool keeps however idle they are.
+    pub fn size(&self) -> usize {
+        self.shared.sizes.core()
+    }
+
+    /// How many workers the pool may run while tasks are waiting.
+    pub fn max_size(&self) -> usize {
+        self.shared.sizes.max()
+    }
+
+    /// How many workers are running, which falls short of `size` only until a dead worker was
+    /// respawned, and goes past it while extra workers are needed.
+    pub fn alive(&self) -> usize {
+        lock(&self.workers)
+            .iter()
+            .filter(|worker| {
+                worker
+                    .thread
+                    .as_ref()
+                    .is_some_and(|thread| !thread.is_finished())
+            })
+            .count()
+    }
+
+    /// How many tasks are waiting for a free worker, on the shared queue or a worker's deque.
+    pub fn queued(&self) -> usize {
+        self.shared.queue.len()
+    }
+
+    /// How many workers are running a task right now.
+    pub fn busy(&self) -> usize {
+        self.shared.counters.busy.load(Ordering::SeqCst)
+    }
+
+    /// How many tasks panicked so far.
+    pub fn panicked(&self) -> usize {
+        self.shared.counters.panicked.load(Ordering::SeqCst)
     }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn wait_until(condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !condition() {
+            assert!(Instant::now() < deadline, "condition never became true");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn it_counts_busy_workers_and_queued_tasks() {
+        let pool = WorkerPool::new(2);
+        assert_eq!((pool.size(), pool.alive()), (2, 2));
+
+        let (release, released) = mpsc::channel::<()>();
+        let released = Arc::new(Mutex::new(released));
+        for _ in 0..3 {
+            let released = Arc::clone(&released);
+            pool.execute(move |_| {
+                released.lock().unwrap().recv().unwrap();
+                Ok(())
+            });
+        }
+
+        wait_until(|| pool.busy() == 2 && pool.queued() == 1);
+
+        for _ in 0..3 {
+            release.send(()).unwrap();
+        }
+        wait_until(|| pool.busy() == 0 && pool.queued() == 0);
+    }
+
+    #[test]
+    fn it_reports_panics_and_keeps_its_workers() {
+        let (sender, panics) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let pool = WorkerPool::builder(1)
+            .panic_handler(move |_, payload| {
+                let message = String::from(panic_message(payload));
+                sender.lock().unwrap().send(message).unwrap();
+            })
+            .build();
+
+        pool.execute(|_| panic!("task {} failed", 1));
+        pool.execute(|_| std::panic::panic_any(42));
+        assert_eq!(panics.recv().unwrap(), "task 1 failed");
+        assert_eq!(panics.recv().unwrap(), "Box<dyn Any>");
+
+        let (done, finished) = mpsc::channel();
+        pool.execute(move |_| {
+            done.send(()).unwrap();
+            Ok(())
+        });
+        finished.recv_timeout(Duration::from_secs(2)).unwrap();
+        wait_until(|| pool.busy() == 0);
+        assert_eq!((pool.alive(), pool.panicked()), (1, 2));
+    }
+
+    #[test]
+    fn it_hands_back_what_submitted_tasks_return() {
+        let pool = WorkerPool::builder(2).panic_handler(|_, _| {}).build();
+
+        let squares: Vec<_> = (0..8u64).map(|n| pool.submit(move |_| Ok(n * n))).collect();
+        let squares: Vec<_> = squares
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect();
+        assert_eq!(squares, [0, 1, 4, 9, 16, 25, 36, 49]);
+
+        let failed =
+            pool.submit(|_| -> std::io::Result<()> { Err(std::io::Error::other("no luck")) });
+        match failed.join() {
+            Err(TaskError::Failed(error)) => assert_eq!(error.to_string(), "no luck"),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let panicked = pool.submit(|_| -> std::io::Result<()> { panic!("boom") });
+        match panicked.join() {
+            Err(TaskError::Panicked(message)) => assert_eq!(message, "boom"),
+            other => panic!("unexpected {:?}", other),
+        }
+        wait_until(|| pool.panicked() == 1);
+    }
+
+    #[test]
+    fn it_polls_and_times_out_on_handles() {
+        let pool = WorkerPool::new(1);
+        let (release, released) = mpsc::channel::<()>();
+        let handle = pool.submit(move |_| {
+            released.recv().unwrap();
+            Ok("done")
+        });
+
+        let handle = handle.try_join().unwrap_err();
+        assert!(!handle.is_finished());
+        let handle = handle.join_timeout(Duration::from_millis(20)).unwrap_err();
+
+        release.send(()).unwrap();
+        let outcome = handle.join_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(outcome.unwrap(), "done");
+    }
+
+    /// A pool of one worker busy until `release` is sent to, with a queue of one task.
+    fn full_pool(overflow: Overflow) -> (WorkerPool, mpsc::Sender<()>) {
+        let pool = WorkerPool::builder(1)
+            .capacity(1)
+            .overflow(overflow)
+            .build();
+        let (release, released) = mpsc::channel::<()>();
+        pool.execute(move |_| {
+            released.recv().unwrap();
+            Ok(())
+        });
+        wait_until(|| pool.busy() == 1);
+        pool.execute(|_| Ok(()));
+        (pool, release)
+    }
+
+    #[test]
+    fn it_applies_the_overflow_policy_once_the_queue_is_full() {
+        let (pool, release) = full_pool(Overflow::Reject);
+        let rejected = pool.try_execute(|_| Ok(())).unwrap_err();
+        assert_eq!(pool.queued(), 1);
+        pool.execute_unbounded(|_| Ok(()));
+        assert_eq!(pool.queued(), 2);
+        release.send(()).unwrap();
+        wait_until(|| pool.queued() == 0);
+        assert!(pool.try_execute(rejected.into_task()).is_ok());
+        wait_until(|| pool.queued() == 0 && pool.busy() == 0);
+
+        let (pool, release) = full_pool(Overflow::DropOldest);
+        let (dropped, kept) = (pool.submit(|_| Ok(1)), pool.submit(|_| Ok(2)));
+        release.send(()).unwrap();
+        assert!(matches!(dropped.join(), Err(TaskError::Cancelled)));
+        assert_eq!(kept.join().unwrap(), 2);
+
+        let (pool, release) = full_pool(Overflow::CallerRuns);
+        let caller = thread::current().id();
+        let ran = pool.submit(move |id| Ok((id, thread::current().id() == caller)));
+        assert_eq!(ran.try_join().unwrap().unwrap(), (CALLER, true));
+        assert!(pool.try_execute(|_| Ok(())).is_err());
+        release.send(()).unwrap();
+
+        let (pool, release) = full_pool(Overflow::Block);
+        let blocked = thread::spawn(move || {
+            pool.execute(|_| Ok(()));
+            pool
+        });
+        thread::sleep(Duration::from_millis(20));
+        assert!(!blocked.is_finished());
+        release.send(()).unwrap();
+        let pool = blocked.join().unwrap();
+        wait_until(|| pool.queued() == 0 && pool.busy() == 0);
+    }
+
+    #[test]
+    fn it_spawns_extra_workers_and_retires_them_once_idle() {
+        let pool = WorkerPool::builder(1)
+            .max_size(3)
+            .keep_alive(Duration::from_millis(50))
+            .build();
+        let (release, released) = mpsc::channel::<()>();
+        let released = Arc::new(Mutex::new(released));
+        for _ in 0..4 {
+            let released = Arc::clone(&released);
+            pool.execute(move |_| {
+                released.lock().unwrap().recv().unwrap();
+                Ok(())
+            });
+        }
+
+        wait_until(|| pool.busy() == 3 && pool.queued() == 1);
+        assert_eq!((pool.size(), pool.max_size(), pool.alive()), (1, 3, 3));
+
+        for _ in 0..4 {
+            release.send(()).unwrap();
+        }
+        wait_until(|| pool.queued() == 0 && pool.alive() == 1);
+    }
+
+    #[test]
+    fn it_resizes_at_runtime() {
+        let pool = WorkerPool::builder(2).max_size(3).build();
+        pool.set_size(4);
+        assert_eq!((pool.size(), pool.max_size()), (4, 5));
+        wait_until(|| pool.alive() == 4);
+
+        pool.set_size(1);
+        wait_until(|| pool.alive() == 2);
+        pool.set_size(1);
+        assert_eq!((pool.size(), pool.max_size()), (1, 2));
+
+        let handles: Vec<_> = (0..4).map(|n| pool.submit(move |_| Ok(n))).collect();
+        let total: i32 = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .sum();
+        assert_eq!(total, 6);
+    }
+
+    #[test]
+    fn it_lets_idle_workers_steal_tasks_submitted_from_tasks() {
+        let pool = Arc::new(WorkerPool::new(2));
+        let weak = Arc::downgrade(&pool);
+        let (done, finished) = mpsc::channel();
+
+        let parent = pool.submit(move |parent| {
+            // Blocked until its children ran, which only a thief can do.
+            let children: Vec<_> = {
+                let pool = weak.upgrade().unwrap();
+                (0..2)
+                    .map(|_| {
+                        let done = done.clone();
+                        pool.submit(move |child| {
+                            done.send(child).unwrap();
+                            Ok(())
+                        })
+                    })
+                    .collect()
+            };
+            for child in children {
+                child.join_timeout(Duration::from_secs(2)).unwrap().unwrap();
+            }
+            Ok(parent)
+        });
+
+        let parent = parent.join().unwrap();
+        let children: Vec<_> = finished.try_iter().collect();
+        assert_eq!(children.len(), 2);
+        assert!(children.iter().all(|child| *child != parent));
+        assert_eq!(pool.queued(), 0);
+    }
+
+    #[test]
+    fn it_counts_tasks_submitted_from_tasks_towards_the_capacity() {
+        let cases = [
+            (Overflow::Block, 2),
+            (Overflow::Reject, 2),
+            (Overflow::DropOldest, 5),
+        ];
+        for (overflow, accepted) in cases {
+            let pool = Arc::new(
+                WorkerPool::builder(1)
+                    .capacity(2)
+                    .overflow(overflow)
+                    .build(),
+            );
+            let weak = Arc::downgrade(&pool);
+
+            let parent = pool.submit(move |_| {
+                let pool = weak.upgrade().unwrap();
+                let accepted = (0..5)
+                    .filter(|_| pool.try_execute(|_| Ok(())).is_ok())
+                    .count();
+                Ok((accepted, pool.queued()))
+            });
+
+            assert_eq!(parent.join().unwrap(), (accepted, 2));
+            wait_until(|| pool.queued() == 0 && pool.busy() == 0);
+        }
+    }
+
+    #[test]
+    fn it_runs_tasks_a_worker_submits_to_a_full_queue_rather_than_block() {
+        let pool = Arc::new(WorkerPool::builder(1).capacity(2).build());
+        let (weak, ran) = (Arc::downgrade(&pool), Arc::new(AtomicUsize::new(0)));
+
+        let counted = Arc::clone(&ran);
+        let parent = pool.submit(move |_| {
+            let pool = weak.upgrade().unwrap();
+            for _ in 0..5 {
+                let ran = Arc::clone(&counted);
+                pool.execute(move |_| {
+                    ran.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                });
+            }
+            Ok(())
+        });
+
+        parent
+            .join_timeout(Duration::from_secs(3))
+            .unwrap()
+            .unwrap();
+        wait_until(|| ran.load(Ordering::SeqCst) == 5);
+    }
+
+    #[test]
+    fn it_respawns_workers_that_die() {
+        // A panicking handler takes its worker down with it.
+        let pool = WorkerPool::builder(2)
+            .panic_handler(|_, _| panic!("handler failed"))
+            .build();
+        for _ in 0..4 {
+            pool.execute(|_| panic!("task failed"));
+        }
+        wait_until(|| pool.panicked() == 4 && pool.alive() == 2);
+
+        let (done, finished) = mpsc::channel();
+        for _ in 0..4 {
+            let done = done.clone();
+            pool.execute(move |_| {
+                done.send(()).unwrap();
+                Ok(())
+            });
+        }
+        for _ in 0..4 {
+            finished.recv_timeout(Duration::from_secs(2)).unwrap();
+        }
+        assert_eq!((pool.size(), pool.alive()), (2, 2));
+    }
+}
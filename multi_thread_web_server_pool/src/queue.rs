@@ -0,0 +1,244 @@
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Condvar, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{lock, Task};
+
+/// What the pool does with a task when its queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// Wait for a worker to take a task off the queue. A task submitted from inside another
+    /// runs on the submitting worker instead, only workers make room.
+    #[default]
+    Block,
+    /// Turn the task down, `WorkerPool::try_execute` gives it back.
+    Reject,
+    /// Drop the task that waited the longest to make room.
+    DropOldest,
+    /// Run the task on the calling thread, slowing down whoever submits tasks. `try_execute`
+    /// gives the task back instead, for the caller to run it.
+    CallerRuns,
+}
+
+/// A task turned down because the queue was full, see `Overflow::Reject`.
+pub struct Rejected<F> {
+    task: F,
+}
+
+impl<F> Rejected<F> {
+    pub(crate) fn new(task: F) -> Self {
+        Rejected { task }
+    }
+
+    /// The task that was turned down, to run it some other way.
+    pub fn into_task(self) -> F {
+        self.task
+    }
+}
+
+impl<F> fmt::Debug for Rejected<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Rejected { .. }")
+    }
+}
+
+impl<F> fmt::Display for Rejected<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the task queue is full")
+    }
+}
+
+impl<F> Error for Rejected<F> {}
+
+/// The tasks submitted from outside the workers, in the order they were submitted.
+pub(crate) struct Queue {
+    state: Mutex<State>,
+    /// Tasks waiting on the workers' own deques, which idle workers should look at instead of
+    /// waiting here.
+    local: AtomicUsize,
+    /// No limit when `None`.
+    capacity: Option<usize>,
+    pub(crate) overflow: Overflow,
+    /// Signalled when a task is pushed, or the queue closed.
+    filled: Condvar,
+    /// Signalled when a task is taken off the queue.
+    drained: Condvar,
+}
+
+/// What a worker waiting for a task got.
+pub(crate) enum Pop {
+    Task(Task),
+    /// Nothing by the deadline, or the worker was woken up to check whether it should retire.
+    Empty,
+    /// The pool is being dropped and no task is left.
+    Closed,
+}
+
+struct State {
+    tasks: VecDeque<Task>,
+    closed: bool,
+}
+
+impl Queue {
+    pub(crate) fn new(capacity: Option<usize>, overflow: Overflow) -> Self {
+        Queue {
+            state: Mutex::new(State {
+                tasks: VecDeque::new(),
+                closed: false,
+            }),
+            local: AtomicUsize::new(0),
+            capacity,
+            overflow,
+            filled: Condvar::new(),
+            drained: Condvar::new(),
+        }
+    }
+
+    /// Queues `task` here, or on the worker's deque `local`, giving it back when the queue is
+    /// full and the overflow policy is neither to block nor to drop the oldest task. A worker
+    /// never blocks, it gets the task back under `Overflow::Block` as well. The tasks on
+    /// the workers' deques count towards the capacity, `steal_oldest` takes one off them to make
+    /// room when this queue has none to drop.
+    pub(crate) fn push<F>(
+        &self,
+        task: F,
+        local: Option<&Mutex<VecDeque<Task>>>,
+        mut steal_oldest: impl FnMut() -> Option<Task>,
+    ) -> Result<(), F>
+    where
+        F: FnOnce(usize) -> Result<(), std::io::Error> + Send + 'static,
+    {
+        let mut state = lock(&self.state);
+        let mut dropped = Vec::new();
+        while self.is_full(&state) {
+            match self.overflow {
+                // Only workers make room, one waiting for it could wait forever.
+                Overflow::Block if local.is_some() => return Err(task),
+                // Tasks taken off the deques don't go through this lock, a wake up might be
+                // missed without the timeout.
+                Overflow::Block => {
+                    state = self
+                        .drained
+                        .wait_timeout(state, Duration::from_millis(10))
+                        .unwrap_or_else(|error| error.into_inner())
+                        .0
+                }
+                Overflow::DropOldest => {
+                    match state.tasks.pop_front().or_else(&mut steal_oldest) {
+                        Some(oldest) => {
+                            println!("Task queue is full, dropping the oldest task.");
+                            dropped.push(oldest);
+                        }
+                        // Workers took them all in the meantime.
+                        None => break,
+                    }
+                }
+                Overflow::Reject | Overflow::CallerRuns => return Err(task),
+            }
+        }
+
+        match local {
+            Some(deque) => {
+                lock(deque).push_back(Box::new(task));
+                self.local.fetch_add(1, Ordering::SeqCst);
+            }
+            None => state.tasks.push_back(Box::new(task)),
+        }
+        drop(state);
+        // Woken up for a task on a deque, an idle worker steals it.
+        self.filled.notify_one();
+        // Whatever the dropped tasks hold on to is released outside of the lock.
+        drop(dropped);
+        Ok(())
+    }
+
+    /// Queues `task` whatever the capacity.
+    pub(crate) fn push_unbounded(&self, task: Task) {
+        lock(&self.state).tasks.push_back(task);
+        self.filled.notify_one();
+    }
+
+    /// The next task, if there is one.
+    pub(crate) fn try_pop(&self) -> Option<Task> {
+        let task = lock(&self.state).tasks.pop_front();
+        if task.is_some() {
+            self.drained.notify_one();
+        }
+        task
+    }
+
+    /// The next task, waiting for one until `deadline` if need be. Returns `Pop::Empty` right
+    /// away while tasks are left on the workers' deques, for the caller to steal one.
+    pub(crate) fn pop(&self, deadline: Instant) -> Pop {
+        let mut state = lock(&self.state);
+        let mut waited = false;
+        loop {
+            if let Some(task) = state.tasks.pop_front() {
+                drop(state);
+                self.drained.notify_one();
+                return Pop::Task(task);
+            }
+            // Checked under the lock, `push` can't notify in between.
+            if self.local.load(Ordering::SeqCst) > 0 {
+                return Pop::Empty;
+            }
+            if state.closed {
+                return Pop::Closed;
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if waited || left.is_zero() {
+                return Pop::Empty;
+            }
+            state = self
+                .filled
+                .wait_timeout(state, left)
+                .unwrap_or_else(|error| error.into_inner())
+                .0;
+            waited = true;
+        }
+    }
+
+    pub(crate) fn taken_locally(&self, count: usize) {
+        self.local.fetch_sub(count, Ordering::SeqCst);
+        self.drained.notify_one();
+    }
+
+    /// Queues the tasks left by a dead worker, whatever the capacity.
+    pub(crate) fn requeue(&self, tasks: Vec<Task>) {
+        lock(&self.state).tasks.extend(tasks);
+        self.filled.notify_all();
+    }
+
+    /// Wakes every waiting worker up, with `Pop::Empty` unless there is a task for it.
+    pub(crate) fn wake_all(&self) {
+        self.filled.notify_all();
+    }
+
+    /// Lets the workers stop once they took the tasks left.
+    pub(crate) fn close(&self) {
+        lock(&self.state).closed = true;
+        self.filled.notify_all();
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        lock(&self.state).closed
+    }
+
+    /// The tasks waiting, here and on the workers' deques.
+    pub(crate) fn len(&self) -> usize {
+        lock(&self.state).tasks.len() + self.local.load(Ordering::SeqCst)
+    }
+
+    fn is_full(&self, state: &State) -> bool {
+        self.capacity.is_some_and(|capacity| {
+            state.tasks.len() + self.local.load(Ordering::SeqCst) >= capacity
+        })
+    }
+}
@@ -0,0 +1,111 @@
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use crate::{lock, Shared, Task};
+
+/// The tasks a worker submitted from inside its own tasks. The worker takes the newest one, idle
+/// workers steal the oldest.
+pub(crate) struct Deque {
+    owner: usize,
+    pub(crate) tasks: Mutex<VecDeque<Task>>,
+}
+
+/// Every worker's deque, for idle workers to steal from.
+#[derive(Default)]
+pub(crate) struct Deques {
+    deques: RwLock<Vec<Arc<Deque>>>,
+}
+
+thread_local! {
+    /// The deque of the worker running on this thread, with the pool it belongs to.
+    static LOCAL: RefCell<Option<(*const Shared, Arc<Deque>)>> = const { RefCell::new(None) };
+}
+
+impl Deques {
+    /// The oldest task of a worker other than `id`, of any worker for `CALLER`.
+    pub(crate) fn steal(&self, id: usize, shared: &Shared) -> Option<Task> {
+        let deques = self
+            .deques
+            .read()
+            .unwrap_or_else(|error| error.into_inner());
+        // Starting after our own deque, so idle workers don't all go for the same victim.
+        let start = deques
+            .iter()
+            .position(|deque| deque.owner == id)
+            .map_or(0, |index| index + 1);
+        (0..deques.len())
+            .map(|offset| &deques[(start + offset) % deques.len()])
+            .filter(|deque| deque.owner != id)
+            .find_map(|deque| lock(&deque.tasks).pop_front())
+            .inspect(|_| shared.queue.taken_locally(1))
+    }
+}
+
+/// The deque of the worker running on this thread, if it belongs to the pool `shared`.
+pub(crate) fn local_deque(shared: &Shared) -> Option<Arc<Deque>> {
+    LOCAL.with(|local| match &*local.borrow() {
+        Some((pool, deque)) if std::ptr::eq(*pool, shared) => Some(Arc::clone(deque)),
+        _ => None,
+    })
+}
+
+/// A worker's deque, registered for as long as the worker runs. Tasks left on it when the
+/// worker dies are handed back to the shared queue.
+pub(crate) struct Local {
+    shared: Arc<Shared>,
+    deque: Arc<Deque>,
+}
+
+impl Local {
+    pub(crate) fn register(id: usize, shared: &Arc<Shared>) -> Self {
+        let deque = Arc::new(Deque {
+            owner: id,
+            tasks: Mutex::new(VecDeque::new()),
+        });
+        shared
+            .deques
+            .deques
+            .write()
+            .unwrap_or_else(|error| error.into_inner())
+            .push(Arc::clone(&deque));
+        LOCAL.with(|local| {
+            *local.borrow_mut() = Some((Arc::as_ptr(shared), Arc::clone(&deque)));
+        });
+
+        Local {
+            shared: Arc::clone(shared),
+            deque,
+        }
+    }
+
+    /// The newest task this worker pushed.
+    pub(crate) fn pop(&self) -> Option<Task> {
+        let task = lock(&self.deque.tasks).pop_back();
+        task.inspect(|_| self.shared.queue.taken_locally(1))
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        lock(&self.deque.tasks).is_empty()
+    }
+}
+
+impl Drop for Local {
+    fn drop(&mut self) {
+        LOCAL.with(|local| local.borrow_mut().take());
+        self.shared
+            .deques
+            .deques
+            .write()
+            .unwrap_or_else(|error| error.into_inner())
+            .retain(|deque| !Arc::ptr_eq(deque, &self.deque));
+
+        let left: Vec<_> = lock(&self.deque.tasks).drain(..).collect();
+        if !left.is_empty() {
+            self.shared.queue.taken_locally(left.len());
+            self.shared.queue.requeue(left);
+        }
+    }
+}